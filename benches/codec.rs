@@ -0,0 +1,238 @@
+//! Encode and decode throughput benchmarks.
+//!
+//! Run with `cargo +nightly bench --features bench`. Each benchmark reports the time per
+//! operation, and the throughput in encoded bytes per second.
+
+#![feature(test)]
+
+extern crate bytekey;
+extern crate rustc_serialize;
+extern crate test;
+
+use bytekey::{encode, encode_into, decode, Decoder, Encoder};
+use rustc_serialize::{Decodable, Encodable};
+use test::{black_box, Bencher};
+
+#[derive(Clone, Debug, PartialEq)]
+struct BenchStruct {
+    u8_: u8,
+    u16_: u16,
+    u32_: u32,
+    u64_: u64,
+    usize_: usize,
+    i8_: i8,
+    i16_: i16,
+    i32_: i32,
+    i64_: i64,
+    isize_: isize,
+    f32_: f32,
+    f64_: f64,
+    bool_: bool,
+    char_: char,
+    string: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum BenchEnum {
+    A(u32, String),
+    B,
+    C(isize),
+}
+
+impl Encodable for BenchStruct {
+    fn encode<S>(&self, s: &mut S) -> Result<(), S::Error> where S: rustc_serialize::Encoder {
+        s.emit_struct("BenchStruct", 15, |s| {
+            try!(s.emit_struct_field("u8_", 0, |s| self.u8_.encode(s)));
+            try!(s.emit_struct_field("u16_", 1, |s| self.u16_.encode(s)));
+            try!(s.emit_struct_field("u32_", 2, |s| self.u32_.encode(s)));
+            try!(s.emit_struct_field("u64_", 3, |s| self.u64_.encode(s)));
+            try!(s.emit_struct_field("usize_", 4, |s| self.usize_.encode(s)));
+            try!(s.emit_struct_field("i8_", 5, |s| self.i8_.encode(s)));
+            try!(s.emit_struct_field("i16_", 6, |s| self.i16_.encode(s)));
+            try!(s.emit_struct_field("i32_", 7, |s| self.i32_.encode(s)));
+            try!(s.emit_struct_field("i64_", 8, |s| self.i64_.encode(s)));
+            try!(s.emit_struct_field("isize_", 9, |s| self.isize_.encode(s)));
+            try!(s.emit_struct_field("f32_", 10, |s| self.f32_.encode(s)));
+            try!(s.emit_struct_field("f64_", 11, |s| self.f64_.encode(s)));
+            try!(s.emit_struct_field("bool_", 12, |s| self.bool_.encode(s)));
+            try!(s.emit_struct_field("char_", 13, |s| self.char_.encode(s)));
+            s.emit_struct_field("string", 14, |s| self.string.encode(s))
+        })
+    }
+}
+
+impl Decodable for BenchStruct {
+    fn decode<D>(d: &mut D) -> Result<BenchStruct, D::Error> where D: rustc_serialize::Decoder {
+        d.read_struct("BenchStruct", 15, |d| {
+            Ok(BenchStruct {
+                u8_: try!(d.read_struct_field("u8_", 0, Decodable::decode)),
+                u16_: try!(d.read_struct_field("u16_", 1, Decodable::decode)),
+                u32_: try!(d.read_struct_field("u32_", 2, Decodable::decode)),
+                u64_: try!(d.read_struct_field("u64_", 3, Decodable::decode)),
+                usize_: try!(d.read_struct_field("usize_", 4, Decodable::decode)),
+                i8_: try!(d.read_struct_field("i8_", 5, Decodable::decode)),
+                i16_: try!(d.read_struct_field("i16_", 6, Decodable::decode)),
+                i32_: try!(d.read_struct_field("i32_", 7, Decodable::decode)),
+                i64_: try!(d.read_struct_field("i64_", 8, Decodable::decode)),
+                isize_: try!(d.read_struct_field("isize_", 9, Decodable::decode)),
+                f32_: try!(d.read_struct_field("f32_", 10, Decodable::decode)),
+                f64_: try!(d.read_struct_field("f64_", 11, Decodable::decode)),
+                bool_: try!(d.read_struct_field("bool_", 12, Decodable::decode)),
+                char_: try!(d.read_struct_field("char_", 13, Decodable::decode)),
+                string: try!(d.read_struct_field("string", 14, Decodable::decode)),
+            })
+        })
+    }
+}
+
+impl Encodable for BenchEnum {
+    fn encode<S>(&self, s: &mut S) -> Result<(), S::Error> where S: rustc_serialize::Encoder {
+        s.emit_enum("BenchEnum", |s| match *self {
+            BenchEnum::A(a, ref b) => s.emit_enum_variant("A", 0, 2, |s| {
+                try!(s.emit_enum_variant_arg(0, |s| a.encode(s)));
+                s.emit_enum_variant_arg(1, |s| b.encode(s))
+            }),
+            BenchEnum::B => s.emit_enum_variant("B", 1, 0, |_| Ok(())),
+            BenchEnum::C(c) => s.emit_enum_variant("C", 2, 1, |s| {
+                s.emit_enum_variant_arg(0, |s| c.encode(s))
+            }),
+        })
+    }
+}
+
+impl Decodable for BenchEnum {
+    fn decode<D>(d: &mut D) -> Result<BenchEnum, D::Error> where D: rustc_serialize::Decoder {
+        d.read_enum("BenchEnum", |d| d.read_enum_variant(&["A", "B", "C"], |d, idx| match idx {
+            0 => Ok(BenchEnum::A(try!(d.read_enum_variant_arg(0, Decodable::decode)),
+                                 try!(d.read_enum_variant_arg(1, Decodable::decode)))),
+            1 => Ok(BenchEnum::B),
+            2 => Ok(BenchEnum::C(try!(d.read_enum_variant_arg(0, Decodable::decode)))),
+            _ => Err(d.error("unknown BenchEnum variant")),
+        }))
+    }
+}
+
+fn bench_struct() -> BenchStruct {
+    BenchStruct {
+        u8_: 0xAB,
+        u16_: 0xABCD,
+        u32_: 0xDEADBEEF,
+        u64_: 0xDEADBEEFCAFEBABE,
+        usize_: 123456789,
+        i8_: -42,
+        i16_: -4242,
+        i32_: -424242,
+        i64_: -42424242424242,
+        isize_: -123456789,
+        f32_: 3.14159,
+        f64_: -2.718281828459045,
+        bool_: true,
+        char_: 'λ',
+        string: "the quick brown fox".to_string(),
+    }
+}
+
+fn string(len: usize) -> String {
+    (0..len).map(|i| (b'a' + (i % 26) as u8) as char).collect()
+}
+
+/// Benchmarks encoding a value into a reused buffer, and decoding it.
+macro_rules! bench {
+    ($encode:ident, $decode:ident, $ty:ty, $value:expr) => {
+        #[bench]
+        fn $encode(b: &mut Bencher) {
+            let value: $ty = $value;
+            let mut buf = Vec::new();
+            b.bytes = encode(&value).unwrap().len() as u64;
+            b.iter(|| {
+                buf.clear();
+                encode_into(&value, &mut buf).unwrap();
+                black_box(&buf);
+            });
+        }
+
+        #[bench]
+        fn $decode(b: &mut Bencher) {
+            let value: $ty = $value;
+            let bytes = encode(&value).unwrap();
+            b.bytes = bytes.len() as u64;
+            b.iter(|| {
+                let decoded: $ty = Decodable::decode(&mut Decoder::buffered(&bytes[..])).unwrap();
+                black_box(decoded)
+            });
+        }
+    }
+}
+
+bench!(encode_u8, decode_u8, u8, 0xAB);
+bench!(encode_u16, decode_u16, u16, 0xABCD);
+bench!(encode_u32, decode_u32, u32, 0xDEADBEEF);
+bench!(encode_u64, decode_u64, u64, 0xDEADBEEFCAFEBABE);
+bench!(encode_usize_small, decode_usize_small, usize, 7);
+bench!(encode_usize_large, decode_usize_large, usize, 0xDEADBEEFCAFE);
+
+bench!(encode_i8, decode_i8, i8, -42);
+bench!(encode_i16, decode_i16, i16, -4242);
+bench!(encode_i32, decode_i32, i32, -424242);
+bench!(encode_i64, decode_i64, i64, -42424242424242);
+bench!(encode_isize_small, decode_isize_small, isize, -7);
+bench!(encode_isize_large, decode_isize_large, isize, -0xDEADBEEFCAFE);
+
+bench!(encode_f32, decode_f32, f32, 3.14159);
+bench!(encode_f64, decode_f64, f64, -2.718281828459045);
+bench!(encode_bool, decode_bool, bool, true);
+bench!(encode_char, decode_char, char, 'λ');
+bench!(encode_option, decode_option, Option<u32>, Some(42));
+
+bench!(encode_str_10, decode_str_10, String, string(10));
+bench!(encode_str_100, decode_str_100, String, string(100));
+bench!(encode_str_1000, decode_str_1000, String, string(1000));
+bench!(encode_str_10000, decode_str_10000, String, string(10000));
+
+bench!(encode_tuple, decode_tuple, (u32, char, String), (42, 'x', string(16)));
+bench!(encode_struct, decode_struct, BenchStruct, bench_struct());
+bench!(encode_enum_unit, decode_enum_unit, BenchEnum, BenchEnum::B);
+bench!(encode_enum_tuple, decode_enum_tuple, BenchEnum, BenchEnum::A(42, string(16)));
+
+/// Encodes a million `(u32, String)` keys into a single buffer.
+#[bench]
+fn encode_bulk_1m(b: &mut Bencher) {
+    let keys: Vec<(u32, String)> = (0..1000000u32).map(|i| (i, format!("key-{}", i))).collect();
+    let len = keys.iter().fold(0, |len, key| len + encode(key).unwrap().len());
+    let mut buf = Vec::with_capacity(len);
+    b.bytes = len as u64;
+    b.iter(|| {
+        buf.clear();
+        {
+            let mut encoder = Encoder::new(&mut buf);
+            for key in keys.iter() {
+                key.encode(&mut encoder).unwrap();
+            }
+        }
+        black_box(&buf);
+    });
+}
+
+/// Decodes a million `(u32, String)` keys from a single buffer.
+#[bench]
+fn decode_bulk_1m(b: &mut Bencher) {
+    let mut bytes = Vec::new();
+    for i in 0..1000000u32 {
+        encode_into(&(i, format!("key-{}", i)), &mut bytes).unwrap();
+    }
+    b.bytes = bytes.len() as u64;
+    b.iter(|| {
+        let mut decoder = Decoder::buffered(&bytes[..]);
+        for key in decoder.iter::<(u32, String)>() {
+            black_box(key.unwrap());
+        }
+    });
+}
+
+/// Compares the cost of the `decode` convenience function, which copies its input.
+#[bench]
+fn decode_struct_vec(b: &mut Bencher) {
+    let bytes = encode(&bench_struct()).unwrap();
+    b.bytes = bytes.len() as u64;
+    b.iter(|| black_box(decode::<BenchStruct>(bytes.clone()).unwrap()));
+}
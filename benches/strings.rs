@@ -0,0 +1,44 @@
+//! Compares string decoding a byte at a time against the buffered, `memchr` based implementation.
+
+#![feature(test)]
+
+extern crate bytekey;
+extern crate rustc_serialize;
+extern crate test;
+
+use bytekey::{encode, Decoder};
+use rustc_serialize::Decodable;
+use test::{black_box, Bencher};
+
+fn encoded_string(len: usize) -> Vec<u8> {
+    let string: String = (0..len).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+    encode(&string).unwrap()
+}
+
+macro_rules! bench_str {
+    ($len:expr, $bytes:ident, $memchr:ident) => {
+        #[bench]
+        fn $bytes(b: &mut Bencher) {
+            let bytes = encoded_string($len);
+            b.bytes = bytes.len() as u64;
+            b.iter(|| {
+                let string: String = Decodable::decode(&mut Decoder::new(&bytes[..])).unwrap();
+                black_box(string)
+            });
+        }
+
+        #[bench]
+        fn $memchr(b: &mut Bencher) {
+            let bytes = encoded_string($len);
+            b.bytes = bytes.len() as u64;
+            b.iter(|| {
+                let string: String = Decodable::decode(&mut Decoder::buffered(&bytes[..])).unwrap();
+                black_box(string)
+            });
+        }
+    }
+}
+
+bench_str!(10, bytes_10, memchr_10);
+bench_str!(100, bytes_100, memchr_100);
+bench_str!(1000, bytes_1000, memchr_1000);
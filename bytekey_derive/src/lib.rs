@@ -0,0 +1,375 @@
+//! Custom derives for the `bytekey` `KeyEncode` and `KeyDecode` traits.
+//!
+//! Deriving `KeyEncode` and `KeyDecode` for a struct encodes its fields in declaration order,
+//! exactly as a tuple of the same fields would be encoded. Individual fields may change how they
+//! are encoded with the `bytekey` attribute:
+//!
+//! * `#[bytekey(desc)]` encodes the field in descending order (`bytekey::Desc`).
+//! * `#[bytekey(varint)]` variable-length encodes an integer field (`bytekey::VarInt`).
+//! * `#[bytekey(escaped)]` encodes a string or byte string field with null bytes escaped
+//!   (`bytekey::Escaped`).
+//! * `#[bytekey(nulls_last)]` sorts `None` after all `Some` values of an `Option` field
+//!   (`bytekey::OptionLast`).
+//!
+//! Attributes may be combined, for instance `#[bytekey(desc, varint)]`, with the exception of
+//! `varint` and `escaped`.
+//!
+//! Enums are encoded as a variable-length variant tag followed by the variant's fields. By default
+//! the tag is the declaration index of the variant, as with `rustc-serialize` encoding, so
+//! reordering or removing variants changes the encoding. `#[bytekey(tag = N)]` pins a variant to
+//! an explicit tag instead; tags must be unique, and decoding an unknown tag fails with
+//! `bytekey::Error::UnknownTag`.
+//!
+//! #### Usage
+//!
+//! ```
+//! extern crate bytekey;
+//! #[macro_use] extern crate bytekey_derive;
+//! use bytekey::{encode_key, decode_key};
+//!
+//! #[derive(KeyEncode, KeyDecode, Debug, PartialEq)]
+//! struct MyKey {
+//!     a: u32,
+//!     #[bytekey(desc)]
+//!     b: String,
+//! }
+//!
+//! # fn main() {
+//! let a = MyKey { a: 1, b: "foo".to_string() };
+//! let b = MyKey { a: 1, b: "bar".to_string() };
+//!
+//! assert!(encode_key(&a).unwrap() < encode_key(&b).unwrap());
+//! assert_eq!(a, decode_key(&encode_key(&a).unwrap()).unwrap());
+//! # }
+//! ```
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use] extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as Tokens};
+use syn::{Data, DataEnum, DeriveInput, Field, Fields, GenericArgument, Generics, Ident, Lit, Meta,
+          NestedMeta, PathArguments, Type};
+use syn::spanned::Spanned;
+
+#[proc_macro_derive(KeyEncode, attributes(bytekey))]
+pub fn derive_key_encode(input: TokenStream) -> TokenStream {
+    match syn::parse::<DeriveInput>(input).and_then(|input| expand_encode(&input)) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+#[proc_macro_derive(KeyDecode, attributes(bytekey))]
+pub fn derive_key_decode(input: TokenStream) -> TokenStream {
+    match syn::parse::<DeriveInput>(input).and_then(|input| expand_decode(&input)) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+/// The encoding attributes of a single field.
+#[derive(Default)]
+struct FieldAttrs {
+    desc: bool,
+    varint: bool,
+    escaped: bool,
+    nulls_last: bool,
+}
+
+/// Parses the `#[bytekey(...)]` attributes of a field.
+fn field_attrs(field: &Field) -> syn::Result<FieldAttrs> {
+    let mut attrs = FieldAttrs::default();
+    for attr in field.attrs.iter().filter(|attr| attr.path.is_ident("bytekey")) {
+        let list = match try!(attr.parse_meta()) {
+            Meta::List(list) => list,
+            meta => return Err(syn::Error::new(meta.span(), "expected `#[bytekey(...)]`")),
+        };
+        for nested in list.nested.iter() {
+            let flag = match *nested {
+                NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("desc") => &mut attrs.desc,
+                NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("varint") => &mut attrs.varint,
+                NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("escaped") => &mut attrs.escaped,
+                NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("nulls_last") => &mut attrs.nulls_last,
+                _ => return Err(syn::Error::new(nested.span(), "unknown bytekey attribute")),
+            };
+            if *flag {
+                return Err(syn::Error::new(nested.span(), "duplicate bytekey attribute"));
+            }
+            *flag = true;
+        }
+    }
+    if attrs.varint && attrs.escaped {
+        return Err(syn::Error::new(field.span(), "`varint` and `escaped` may not be combined"));
+    }
+    Ok(attrs)
+}
+
+/// Returns `T` if the type is `Option<T>`.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let path = match *ty {
+        Type::Path(ref path) if path.qself.is_none() => &path.path,
+        _ => return None,
+    };
+    let segment = match path.segments.last() {
+        Some(segment) if segment.ident == "Option" => segment,
+        _ => return None,
+    };
+    match segment.arguments {
+        PathArguments::AngleBracketed(ref args) if args.args.len() == 1 => {
+            match args.args[0] {
+                GenericArgument::Type(ref ty) => Some(ty),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Returns the generics with the trait bound added to every type parameter.
+fn add_bounds(generics: &Generics, bound: Tokens) -> Generics {
+    let mut generics = generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(syn::parse2(bound.clone()).unwrap());
+    }
+    generics
+}
+
+/// Returns the statement which encodes the field, given an expression borrowing the field.
+fn encode_field(field: &Field, value: Tokens) -> syn::Result<Tokens> {
+    let attrs = try!(field_attrs(field));
+    let wrap = |value: Tokens| {
+        if attrs.varint {
+            quote!(::bytekey::VarInt(#value))
+        } else if attrs.escaped {
+            quote!(::bytekey::Escaped(#value))
+        } else {
+            value
+        }
+    };
+    let mut value = if attrs.nulls_last {
+        if option_inner(&field.ty).is_none() {
+            return Err(syn::Error::new(field.ty.span(), "`nulls_last` requires an `Option` field"));
+        }
+        let inner = wrap(quote!(__value));
+        quote!(::bytekey::OptionLast(::std::option::Option::as_ref(#value).map(|__value| #inner)))
+    } else {
+        wrap(value)
+    };
+    if attrs.desc {
+        value = quote!(::bytekey::Desc(#value));
+    }
+    Ok(quote!(::bytekey::KeyEncode::encode_key(&#value, __encoder)?;))
+}
+
+/// Returns the expression which decodes the field.
+fn decode_field(field: &Field) -> syn::Result<Tokens> {
+    let attrs = try!(field_attrs(field));
+    let inner = if attrs.nulls_last {
+        match option_inner(&field.ty) {
+            Some(inner) => inner,
+            None => {
+                let msg = "`nulls_last` requires an `Option` field";
+                return Err(syn::Error::new(field.ty.span(), msg));
+            },
+        }
+    } else {
+        &field.ty
+    };
+
+    let (mut ty, wrapped) = if attrs.varint {
+        (quote!(::bytekey::VarInt<#inner>), true)
+    } else if attrs.escaped {
+        (quote!(::bytekey::Escaped<#inner>), true)
+    } else {
+        (quote!(#inner), false)
+    };
+    let mut value = quote!(__value);
+    if attrs.desc {
+        value = quote!(#value.0);
+    }
+    if attrs.nulls_last {
+        ty = quote!(::bytekey::OptionLast<#ty>);
+        value = if wrapped { quote!(#value.0.map(|__value| __value.0)) } else { quote!(#value.0) };
+    } else if wrapped {
+        value = quote!(#value.0);
+    }
+    if attrs.desc {
+        ty = quote!(::bytekey::Desc<#ty>);
+    }
+
+    Ok(quote!({
+        let __value: #ty = ::bytekey::KeyDecode::decode_key(__decoder)?;
+        #value
+    }))
+}
+
+/// Returns the tag of each variant of the enum.
+///
+/// Variants without a `#[bytekey(tag = N)]` attribute are tagged with their declaration index,
+/// which matches the tag written by `rustc-serialize` encoding.
+fn variant_tags(data: &DataEnum) -> syn::Result<Vec<u64>> {
+    let mut tags: Vec<u64> = Vec::new();
+    for (idx, variant) in data.variants.iter().enumerate() {
+        let mut tag = None;
+        for attr in variant.attrs.iter().filter(|attr| attr.path.is_ident("bytekey")) {
+            let list = match try!(attr.parse_meta()) {
+                Meta::List(list) => list,
+                meta => return Err(syn::Error::new(meta.span(), "expected `#[bytekey(tag = N)]`")),
+            };
+            for nested in list.nested.iter() {
+                match *nested {
+                    NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("tag") => {
+                        if tag.is_some() {
+                            return Err(syn::Error::new(nested.span(), "duplicate bytekey tag"));
+                        }
+                        tag = match nv.lit {
+                            Lit::Int(ref lit) => Some(try!(lit.base10_parse::<u64>())),
+                            ref lit => return Err(syn::Error::new(lit.span(),
+                                                                  "expected an integer tag")),
+                        };
+                    },
+                    _ => return Err(syn::Error::new(nested.span(), "unknown bytekey attribute")),
+                }
+            }
+        }
+        let tag = tag.unwrap_or(idx as u64);
+        if tags.contains(&tag) {
+            let msg = format!("duplicate enum variant tag {}", tag);
+            return Err(syn::Error::new(variant.span(), msg));
+        }
+        tags.push(tag);
+    }
+    Ok(tags)
+}
+
+/// Returns the pattern binding each field by reference, and the statements encoding the fields.
+fn encode_fields(path: Tokens, fields: &Fields) -> syn::Result<(Tokens, Vec<Tokens>)> {
+    let mut bindings = Vec::new();
+    let mut stmts = Vec::new();
+    for (idx, field) in fields.iter().enumerate() {
+        let binding = Ident::new(&format!("__field{}", idx), Span::call_site());
+        stmts.push(try!(encode_field(field, quote!(#binding))));
+        bindings.push(match field.ident {
+            Some(ref ident) => quote!(#ident: ref #binding),
+            None => quote!(ref #binding),
+        });
+    }
+    let pattern = match *fields {
+        Fields::Named(_) => quote!(#path { #(#bindings),* }),
+        Fields::Unnamed(_) => quote!(#path(#(#bindings),*)),
+        Fields::Unit => path,
+    };
+    Ok((pattern, stmts))
+}
+
+/// Returns the expression which decodes the fields and constructs the struct or variant.
+fn decode_fields(path: Tokens, fields: &Fields) -> syn::Result<Tokens> {
+    Ok(match *fields {
+        Fields::Named(ref named) => {
+            let mut values = Vec::new();
+            for field in named.named.iter() {
+                let ident = &field.ident;
+                let value = try!(decode_field(field));
+                values.push(quote!(#ident: #value));
+            }
+            quote!(#path { #(#values),* })
+        },
+        Fields::Unnamed(ref unnamed) => {
+            let mut values = Vec::new();
+            for field in unnamed.unnamed.iter() {
+                values.push(try!(decode_field(field)));
+            }
+            quote!(#path(#(#values),*))
+        },
+        Fields::Unit => path,
+    })
+}
+
+fn expand_encode(input: &DeriveInput) -> syn::Result<Tokens> {
+    let name = &input.ident;
+    let body = match input.data {
+        Data::Struct(ref data) => {
+            let (pattern, stmts) = try!(encode_fields(quote!(#name), &data.fields));
+            quote! {
+                let #pattern = *self;
+                #(#stmts)*
+            }
+        },
+        Data::Enum(ref data) => {
+            let tags = try!(variant_tags(data));
+            let mut arms = Vec::new();
+            for (variant, tag) in data.variants.iter().zip(tags) {
+                let ident = &variant.ident;
+                let (pattern, stmts) = try!(encode_fields(quote!(#name::#ident), &variant.fields));
+                arms.push(quote! {
+                    #pattern => {
+                        __encoder.emit_var_u64(#tag)?;
+                        #(#stmts)*
+                    },
+                });
+            }
+            quote! {
+                match *self {
+                    #(#arms)*
+                }
+            }
+        },
+        Data::Union(_) => return Err(syn::Error::new(name.span(),
+                                                     "`KeyEncode` cannot be derived for unions")),
+    };
+
+    let generics = add_bounds(&input.generics, quote!(::bytekey::KeyEncode));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::bytekey::KeyEncode for #name #ty_generics #where_clause {
+            fn encode_key<__W>(&self, __encoder: &mut ::bytekey::Encoder<__W>)
+                               -> ::bytekey::Result<()>
+            where __W: ::std::io::Write {
+                #body
+                Ok(())
+            }
+        }
+    })
+}
+
+fn expand_decode(input: &DeriveInput) -> syn::Result<Tokens> {
+    let name = &input.ident;
+    let body = match input.data {
+        Data::Struct(ref data) => {
+            let value = try!(decode_fields(quote!(#name), &data.fields));
+            quote!(Ok(#value))
+        },
+        Data::Enum(ref data) => {
+            let tags = try!(variant_tags(data));
+            let mut arms = Vec::new();
+            for (variant, tag) in data.variants.iter().zip(tags) {
+                let ident = &variant.ident;
+                let value = try!(decode_fields(quote!(#name::#ident), &variant.fields));
+                arms.push(quote!(#tag => Ok(#value),));
+            }
+            quote! {
+                match __decoder.read_var_u64()? {
+                    #(#arms)*
+                    __tag => Err(::bytekey::Error::UnknownTag { tag: __tag }),
+                }
+            }
+        },
+        Data::Union(_) => return Err(syn::Error::new(name.span(),
+                                                     "`KeyDecode` cannot be derived for unions")),
+    };
+
+    let generics = add_bounds(&input.generics, quote!(::bytekey::KeyDecode));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::bytekey::KeyDecode for #name #ty_generics #where_clause {
+            fn decode_key<__R>(__decoder: &mut ::bytekey::Decoder<__R>)
+                               -> ::bytekey::Result<#name #ty_generics>
+            where __R: ::std::io::Read {
+                #body
+            }
+        }
+    })
+}
@@ -0,0 +1,155 @@
+extern crate bytekey;
+#[macro_use] extern crate bytekey_derive;
+
+use bytekey::{encode, encode_key, decode_key, Desc, Error, Escaped, OptionLast, VarInt};
+
+#[derive(KeyEncode, KeyDecode, Debug, PartialEq)]
+struct MyKey {
+    a: u32,
+    b: String,
+}
+
+#[derive(KeyEncode, KeyDecode, Debug, PartialEq)]
+struct Columns {
+    #[bytekey(desc)]
+    a: u32,
+    #[bytekey(varint)]
+    b: u64,
+    #[bytekey(escaped)]
+    c: Vec<u8>,
+    #[bytekey(nulls_last)]
+    d: Option<String>,
+    #[bytekey(desc, varint, nulls_last)]
+    e: Option<i32>,
+}
+
+#[derive(KeyEncode, KeyDecode, Debug, PartialEq)]
+struct Tuple(#[bytekey(desc)] String, u8);
+
+#[derive(KeyEncode, KeyDecode, Debug, PartialEq)]
+struct Generic<T> {
+    id: T,
+    #[bytekey(desc)]
+    rest: T,
+}
+
+#[derive(KeyEncode, KeyDecode, Debug, PartialEq)]
+struct Unit;
+
+#[derive(KeyEncode, KeyDecode, Debug, PartialEq)]
+enum Event {
+    #[bytekey(tag = 7)]
+    Deleted,
+    #[bytekey(tag = 2)]
+    Created { id: u32, #[bytekey(desc)] name: String },
+    #[bytekey(tag = 300)]
+    Renamed(u32, String),
+}
+
+#[derive(KeyEncode, KeyDecode, Debug, PartialEq)]
+enum Implicit {
+    A,
+    B(u8),
+    #[bytekey(tag = 5)]
+    C,
+    D,
+}
+
+#[test]
+fn test_plain() {
+    let key = MyKey { a: 1, b: "foo".to_string() };
+    assert_eq!(encode_key(&(1u32, "foo")).unwrap(), encode_key(&key).unwrap());
+    assert_eq!(key, decode_key(&encode_key(&key).unwrap()).unwrap());
+}
+
+#[test]
+fn test_attributes() {
+    let key = Columns {
+        a: 42,
+        b: 300,
+        c: vec!(0x00, 0x01),
+        d: None,
+        e: Some(-7),
+    };
+    let expected = (Desc(42u32),
+                    VarInt(300u64),
+                    Escaped(vec!(0x00u8, 0x01)),
+                    OptionLast::<String>(None),
+                    Desc(OptionLast(Some(VarInt(-7i32)))));
+    assert_eq!(encode_key(&expected).unwrap(), encode_key(&key).unwrap());
+    assert_eq!(key, decode_key(&encode_key(&key).unwrap()).unwrap());
+}
+
+#[test]
+fn test_ordering() {
+    let keys = vec!(
+        Columns { a: 2, b: 0, c: vec!(), d: Some("a".to_string()), e: None },
+        Columns { a: 1, b: 0, c: vec!(), d: Some("a".to_string()), e: None },
+        Columns { a: 1, b: 1, c: vec!(), d: Some("a".to_string()), e: None },
+        Columns { a: 1, b: 1, c: vec!(0x00), d: Some("a".to_string()), e: None },
+        Columns { a: 1, b: 1, c: vec!(0x00), d: None, e: None },
+        Columns { a: 1, b: 1, c: vec!(0x00), d: None, e: Some(1) },
+        Columns { a: 1, b: 1, c: vec!(0x00), d: None, e: Some(0) },
+    );
+    for pair in keys.windows(2) {
+        assert!(encode_key(&pair[0]).unwrap() < encode_key(&pair[1]).unwrap(),
+                "{:?} should sort before {:?}", pair[0], pair[1]);
+    }
+}
+
+#[test]
+fn test_tuple_struct() {
+    let key = Tuple("foo".to_string(), 7);
+    assert_eq!(encode_key(&(Desc("foo"), 7u8)).unwrap(), encode_key(&key).unwrap());
+    assert_eq!(key, decode_key(&encode_key(&key).unwrap()).unwrap());
+}
+
+#[test]
+fn test_generic() {
+    let key = Generic { id: 1u16, rest: 2u16 };
+    assert_eq!(encode_key(&(1u16, Desc(2u16))).unwrap(), encode_key(&key).unwrap());
+    assert_eq!(key, decode_key(&encode_key(&key).unwrap()).unwrap());
+}
+
+#[test]
+fn test_unit() {
+    assert_eq!(Vec::<u8>::new(), encode_key(&Unit).unwrap());
+    assert_eq!(Unit, decode_key(&[]).unwrap());
+}
+
+#[test]
+fn test_enum_tags() {
+    assert_eq!(encode_key(&7usize).unwrap(), encode_key(&Event::Deleted).unwrap());
+    assert_eq!(encode_key(&(2usize, 1u32, Desc("foo"))).unwrap(),
+               encode_key(&Event::Created { id: 1, name: "foo".to_string() }).unwrap());
+    assert_eq!(encode_key(&(300usize, 1u32, "foo")).unwrap(),
+               encode_key(&Event::Renamed(1, "foo".to_string())).unwrap());
+
+    let events = vec!(Event::Created { id: 1, name: "b".to_string() },
+                      Event::Created { id: 1, name: "a".to_string() },
+                      Event::Deleted,
+                      Event::Renamed(0, "a".to_string()));
+    for pair in events.windows(2) {
+        assert!(encode_key(&pair[0]).unwrap() < encode_key(&pair[1]).unwrap(),
+                "{:?} should sort before {:?}", pair[0], pair[1]);
+    }
+    for event in events {
+        assert_eq!(event, decode_key(&encode_key(&event).unwrap()).unwrap());
+    }
+}
+
+#[test]
+fn test_enum_implicit_tags() {
+    assert_eq!(encode(&0usize).unwrap(), encode_key(&Implicit::A).unwrap());
+    assert_eq!(encode(&(1usize, 9u8)).unwrap(), encode_key(&Implicit::B(9)).unwrap());
+    assert_eq!(encode(&5usize).unwrap(), encode_key(&Implicit::C).unwrap());
+    assert_eq!(encode(&3usize).unwrap(), encode_key(&Implicit::D).unwrap());
+}
+
+#[test]
+fn test_unknown_tag() {
+    match decode_key::<Event>(&encode_key(&3usize).unwrap()) {
+        Err(Error::UnknownTag { tag: 3 }) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
@@ -0,0 +1,88 @@
+//! Fixed-size arrays.
+
+use std::result;
+
+use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
+
+/// A fixed-size array which is encoded without any per-element or length overhead.
+///
+/// `rustc-serialize` encodes arrays as sequences, which `bytekey` does not support. Because the
+/// length of an array is statically known, wrapping it in `Array` allows it to be encoded as the
+/// concatenation of its elements, exactly as a tuple of the same elements would be. Arrays of up
+/// to 32 elements are supported.
+///
+/// #### Usage
+///
+/// ```
+/// # use bytekey::{encode, decode, Array};
+/// let hash = Array([0xDEu8, 0xAD, 0xBE, 0xEF]);
+/// assert_eq!(vec!(0xDE, 0xAD, 0xBE, 0xEF), encode(&hash).unwrap());
+/// assert_eq!(hash, decode(encode(&hash).unwrap()).unwrap());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Array<A>(pub A);
+
+macro_rules! array {
+    () => ();
+    ($len:expr, $($idx:expr,)*) => {
+        impl<T> Encodable for Array<[T; $len]> where T: Encodable {
+            fn encode<S>(&self, s: &mut S) -> result::Result<(), S::Error>
+            where S: Encoder {
+                s.emit_tuple($len, |s| {
+                    for (idx, elt) in self.0.iter().enumerate() {
+                        try!(s.emit_tuple_arg(idx, |s| elt.encode(s)));
+                    }
+                    Ok(())
+                })
+            }
+        }
+
+        impl<T> Decodable for Array<[T; $len]> where T: Decodable {
+            #[allow(unused_variables)]
+            fn decode<D>(d: &mut D) -> result::Result<Array<[T; $len]>, D::Error>
+            where D: Decoder {
+                d.read_tuple($len, |d| {
+                    Ok(Array([$(try!(d.read_tuple_arg($len - $idx - 1, Decodable::decode)),)*]))
+                })
+            }
+        }
+
+        array!($($idx,)*);
+    }
+}
+
+array!(32, 31, 30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 18, 17,
+       16, 15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,);
+
+#[cfg(test)]
+mod test {
+
+    use {encode, decode};
+    use super::Array;
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!(Vec::<u8>::new(), encode(&Array::<[u8; 0]>([])).unwrap());
+        assert_eq!(vec!(0x01, 0x02, 0x03), encode(&Array([1u8, 2, 3])).unwrap());
+        assert_eq!(vec!(0x00, 0x00, 0x00, 0x2A, 0x00, 0x00, 0x01, 0x00),
+                   encode(&Array([42u32, 256])).unwrap());
+        assert_eq!(encode(&(1u16, 2u16)).unwrap(), encode(&Array([1u16, 2])).unwrap());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = Array([0xFFu8; 32]);
+        assert_eq!(bytes, decode(encode(&bytes).unwrap()).unwrap());
+
+        let strings = Array(["a".to_string(), "b".to_string()]);
+        assert_eq!(strings, decode(encode(&strings).unwrap()).unwrap());
+    }
+
+    #[quickcheck]
+    fn check_array(a: (u32, i32, u32, i32), b: (u32, i32, u32, i32)) -> bool {
+        let a = Array([a.0 as i64, a.1 as i64, a.2 as i64, a.3 as i64]);
+        let b = Array([b.0 as i64, b.1 as i64, b.2 as i64, b.3 as i64]);
+        a.cmp(&b) == encode(&a).unwrap().cmp(&encode(&b).unwrap())
+            && a == decode(encode(&a).unwrap()).unwrap()
+    }
+}
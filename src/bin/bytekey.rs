@@ -0,0 +1,337 @@
+//! Command-line inspector for encoded keys.
+//!
+//! ```text
+//! bytekey decode --schema <file> [<hex>...]
+//! bytekey encode --schema <file> [<json>...]
+//! bytekey json --schema <file> [<hex>...]
+//! bytekey annotate --schema <file> [<hex>...]
+//! ```
+//!
+//! Keys are read from the arguments, or one per line from stdin if none are given. The schema file
+//! uses the syntax of `bytekey::parse_definitions`, and keys are decoded with its last definition.
+
+extern crate bytekey;
+extern crate rustc_serialize;
+
+use std::{env, process};
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+
+use bytekey::{encode_json, to_json, Decoder, Schema, Value};
+use rustc_serialize::hex::{FromHex, ToHex};
+use rustc_serialize::json::Json;
+
+const USAGE: &'static str = "\
+usage: bytekey decode --schema <file> [<hex>...]
+       bytekey encode --schema <file> [<json>...]
+       bytekey json --schema <file> [<hex>...]
+       bytekey annotate --schema <file> [<hex>...]
+
+Keys are read from the arguments, or one per line from stdin if none are given.";
+
+const COMMANDS: &'static [&'static str] = &["decode", "encode", "json", "annotate"];
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let stdin = io::stdin();
+    if let Err(message) = run(&args, stdin.lock(), &mut io::stdout()) {
+        let _ = writeln!(io::stderr(), "bytekey: {}", message);
+        process::exit(1);
+    }
+}
+
+/// Runs the command given by the arguments, reading keys from `stdin` if none are given.
+fn run<R, W>(args: &[String], stdin: R, out: &mut W) -> Result<(), String>
+where R: BufRead, W: Write {
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (&command[..], args),
+        None => return Err(USAGE.to_string()),
+    };
+    if command == "-h" || command == "--help" {
+        return writeln!(out, "{}", USAGE).map_err(|err| err.to_string());
+    }
+    if !COMMANDS.contains(&command) {
+        return Err(format!("unknown command `{}`\n\n{}", command, USAGE));
+    }
+
+    let mut schema_path = None;
+    let mut inputs = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--schema" => schema_path = args.next(),
+            "-h" | "--help" => return writeln!(out, "{}", USAGE).map_err(|err| err.to_string()),
+            _ => inputs.push(arg.clone()),
+        }
+    }
+    let schema = match schema_path {
+        Some(path) => try!(read_schema(path)),
+        None => return Err(format!("missing --schema\n\n{}", USAGE)),
+    };
+    if inputs.is_empty() {
+        for line in stdin.lines() {
+            let line = try!(line.map_err(|err| err.to_string()));
+            if !line.trim().is_empty() { inputs.push(line); }
+        }
+    }
+
+    for input in inputs.iter() {
+        try!(execute(command, &schema, input, out));
+    }
+    Ok(())
+}
+
+/// Runs a command on a single key, writing its output.
+fn execute<W>(command: &str, schema: &Schema, input: &str, out: &mut W) -> Result<(), String>
+where W: Write {
+    let output = match command {
+        "decode" => {
+            let bytes = try!(parse_hex(input));
+            let value = try!(decode_key(schema, &bytes));
+            format!("{}\n", render(schema, &value))
+        },
+        "encode" => {
+            let json = try!(Json::from_str(input).map_err(|err| err.to_string()));
+            let bytes = try!(encode_json(schema, &json).map_err(|err| err.to_string()));
+            format!("{}\n", bytes.to_hex())
+        },
+        "json" => {
+            let bytes = try!(parse_hex(input));
+            let value = try!(decode_key(schema, &bytes));
+            let json = try!(to_json(schema, &value).map_err(|err| err.to_string()));
+            format!("{}\n", json)
+        },
+        "annotate" => {
+            let bytes = try!(parse_hex(input));
+            let mut decoder = Decoder::buffered(&bytes[..]);
+            decoder.set_tracing(true);
+            let result = schema.decode_value(&mut decoder);
+            try!(write!(out, "{}", decoder.take_trace().hexdump(&bytes))
+                     .map_err(|err| err.to_string()));
+            try!(result.map_err(|err| err.to_string()));
+            return Ok(());
+        },
+        _ => return Err(format!("unknown command `{}`\n\n{}", command, USAGE)),
+    };
+    write!(out, "{}", output).map_err(|err| err.to_string())
+}
+
+fn read_schema(path: &str) -> Result<Schema, String> {
+    let mut src = String::new();
+    try!(File::open(path).and_then(|mut file| file.read_to_string(&mut src))
+                         .map_err(|err| format!("{}: {}", path, err)));
+    Schema::parse(&src).map_err(|err| format!("{}:{}", path, err))
+}
+
+/// Decodes a key, failing if any bytes follow the value.
+fn decode_key(schema: &Schema, bytes: &[u8]) -> Result<Value, String> {
+    let mut decoder = Decoder::buffered(bytes);
+    let value = try!(schema.decode_value(&mut decoder).map_err(|err| err.to_string()));
+    let rest = decoder.into_inner().len();
+    if rest != 0 {
+        return Err(format!("trailing bytes after the key at byte {}", bytes.len() - rest));
+    }
+    Ok(value)
+}
+
+/// Parses a hex string, ignoring whitespace and an optional `0x` prefix.
+fn parse_hex(input: &str) -> Result<Vec<u8>, String> {
+    let input = input.trim();
+    let input = if input.starts_with("0x") { &input[2..] } else { input };
+    let hex = input.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    hex.from_hex().map_err(|err| format!("invalid hex key: {}", err))
+}
+
+/// Renders a value with the field and variant names of its schema.
+fn render(schema: &Schema, value: &Value) -> String {
+    match (schema, value) {
+        (&Schema::Desc(ref schema), value) => render(schema, value),
+        (&Schema::Option(ref schema), &Value::Option(Some(ref value))) |
+        (&Schema::OptionLast(ref schema), &Value::Option(Some(ref value))) => {
+            format!("Some({})", render(schema, value))
+        },
+        (&Schema::Tuple(ref schemas), &Value::Tuple(ref values)) => {
+            format!("({})", render_list(schemas.iter(), values))
+        },
+        (&Schema::Seq(ref schema), &Value::Seq(ref values)) => {
+            format!("[{}]", render_list(values.iter().map(|_| &**schema), values))
+        },
+        (&Schema::Struct(ref name, ref fields), &Value::Tuple(ref values)) => {
+            let fields = fields.iter().zip(values.iter()).map(|(field, value)| {
+                format!("{}: {}", field.0, render(&field.1, value))
+            }).collect::<Vec<_>>();
+            format!("{} {{ {} }}", name, fields.join(", "))
+        },
+        (&Schema::Enum(_, ref variants), &Value::Variant(tag, ref values)) => {
+            let variant = variants.iter().find(|variant| variant.1 == tag).unwrap();
+            match variant.2.len() {
+                0 => variant.0.clone(),
+                1 if values.len() == 1 => match variant.2[0] {
+                    Schema::Struct(..) => render(&variant.2[0], &values[0]),
+                    _ => format!("{}({})", variant.0, render(&variant.2[0], &values[0])),
+                },
+                _ => format!("{}({})", variant.0, render_list(variant.2.iter(), values)),
+            }
+        },
+        (_, &Value::Bool(v)) => v.to_string(),
+        (_, &Value::U8(v)) => v.to_string(),
+        (_, &Value::U16(v)) => v.to_string(),
+        (_, &Value::U32(v)) => v.to_string(),
+        (_, &Value::U64(v)) => v.to_string(),
+        (_, &Value::Usize(v)) => v.to_string(),
+        (_, &Value::I8(v)) => v.to_string(),
+        (_, &Value::I16(v)) => v.to_string(),
+        (_, &Value::I32(v)) => v.to_string(),
+        (_, &Value::I64(v)) => v.to_string(),
+        (_, &Value::Isize(v)) => v.to_string(),
+        (_, &Value::F32(v)) => format!("{:?}", v),
+        (_, &Value::F64(v)) => format!("{:?}", v),
+        (_, &Value::Char(c)) => format!("{:?}", c),
+        (_, &Value::Str(ref s)) => format!("{:?}", s),
+        (_, &Value::Bytes(ref bytes)) => format!("0x{}", bytes.to_hex()),
+        (_, &Value::Option(None)) => "None".to_string(),
+        (_, value) => format!("{:?}", value),
+    }
+}
+
+fn render_list<'a, I>(schemas: I, values: &[Value]) -> String where I: Iterator<Item=&'a Schema> {
+    schemas.zip(values.iter())
+           .map(|(schema, value)| render(schema, value))
+           .collect::<Vec<_>>()
+           .join(", ")
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::{env, fs, io, process};
+    use std::io::{BufRead, Read, Write};
+
+    use bytekey::Schema;
+
+    use super::{execute, parse_hex, render, run};
+
+    const SCHEMA: &'static str = "
+        enum Kind { User(u32), Group { id: u32, name: str } = 5, Any }
+        struct Key { tenant: u32, name: str desc, kind: Kind, parent: option<u8> }
+    ";
+
+    /// A reader which fails the test if it is read from.
+    struct Unreadable;
+
+    impl Read for Unreadable {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            panic!("stdin was read")
+        }
+    }
+
+    impl BufRead for Unreadable {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            panic!("stdin was read")
+        }
+        fn consume(&mut self, _amt: usize) { }
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn exec(command: &str, input: &str) -> Result<String, String> {
+        let schema = Schema::parse(SCHEMA).unwrap();
+        let mut out = Vec::new();
+        try!(execute(command, &schema, input, &mut out));
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(vec!(0x00, 0x2A, 0xFF), parse_hex("002aff").unwrap());
+        assert_eq!(vec!(0x00, 0x2A, 0xFF), parse_hex(" 0x00 2a\tFF\n").unwrap());
+        assert_eq!(Vec::<u8>::new(), parse_hex("").unwrap());
+        assert!(parse_hex("0x0").is_err());
+        assert!(parse_hex("zz").is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let schema = Schema::parse(SCHEMA).unwrap();
+        let bytes = parse_hex("00000003 9d909dff 05 00000007 6100 01 2a").unwrap();
+        let value = ::bytekey::decode_with_schema(&schema, &bytes).unwrap();
+        assert_eq!("Key { tenant: 3, name: \"bob\", kind: Group { id: 7, name: \"a\" }, \
+                    parent: Some(42) }",
+                   render(&schema, &value));
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let inputs = [
+            "{\"tenant\": 3, \"name\": \"bob\", \"kind\": {\"User\": 9}, \"parent\": null}",
+            "{\"tenant\": 0, \"name\": \"\", \"kind\": \"Any\", \"parent\": 1}",
+        ];
+        for input in inputs.iter() {
+            let hex = exec("encode", input).unwrap();
+            let json = exec("json", &hex).unwrap();
+            assert_eq!(hex, exec("encode", json.trim()).unwrap());
+
+            let decoded = exec("decode", &hex).unwrap();
+            assert!(decoded.starts_with("Key { tenant: "), "{}", decoded);
+
+            let annotated = exec("annotate", &hex).unwrap();
+            assert!(annotated.contains("tenant = "), "{}", annotated);
+        }
+    }
+
+    #[test]
+    fn test_bad_input() {
+        assert!(exec("decode", "not hex").is_err());
+        assert!(exec("decode", "00").is_err());
+        assert!(exec("json", "0000000300").is_err());
+
+        // A valid key followed by other bytes is rejected rather than truncated.
+        let err = exec("decode", "00000003ff0200 2a").unwrap_err();
+        assert_eq!("trailing bytes after the key at byte 7", err);
+        assert!(exec("json", "00000003ff0200 2a").is_err());
+        assert!(exec("encode", "{").is_err());
+        assert!(exec("encode", "{\"tenant\": 1}").is_err());
+
+        // The spans decoded before the error are still annotated.
+        let mut out = Vec::new();
+        let schema = Schema::parse(SCHEMA).unwrap();
+        assert!(execute("annotate", &schema, "00000003", &mut out).is_err());
+        assert!(String::from_utf8(out).unwrap().contains("tenant = 3"));
+    }
+
+    #[test]
+    fn test_run() {
+        // Unique per process, so concurrent runs of the test don't share the file.
+        let name = format!("bytekey-test-run-{}.schema", process::id());
+        let path = env::temp_dir().join(name);
+        fs::File::create(&path).and_then(|mut file| file.write_all(SCHEMA.as_bytes())).unwrap();
+        let path = path.to_str().unwrap();
+
+        let mut out = Vec::new();
+        let stdin = &b"\n00000003ff0200\n"[..];
+        run(&args(&["decode", "--schema", path]), stdin, &mut out).unwrap();
+        assert_eq!("Key { tenant: 3, name: \"\", kind: Any, parent: None }\n",
+                   String::from_utf8(out).unwrap());
+
+        let mut out = Vec::new();
+        run(&args(&["json", "--schema", path, "00000003ff0200"]), Unreadable, &mut out).unwrap();
+        assert!(!out.is_empty());
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_run_errors() {
+        let mut out = Vec::new();
+        assert!(run(&args(&[]), Unreadable, &mut out).is_err());
+        let err = run(&args(&["bogus", "--schema", "missing"]), Unreadable, &mut out).unwrap_err();
+        assert!(err.starts_with("unknown command `bogus`"), "{}", err);
+        let err = run(&args(&["decode"]), Unreadable, &mut out).unwrap_err();
+        assert!(err.starts_with("missing --schema"), "{}", err);
+        assert!(run(&args(&["decode", "--schema", "/nonexistent/schema"]), Unreadable, &mut out)
+                    .is_err());
+        assert!(out.is_empty());
+    }
+}
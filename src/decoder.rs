@@ -1,33 +1,102 @@
-use std::{i8, i16, i32, i64};
-use std::io::{self, Read};
-use std::iter::range_inclusive;
+use std::{fmt, i8, i16, i32, i64, str};
+use std::io::{self, BufRead};
+use std::marker::PhantomData;
 use std::mem::transmute;
-use std::num::wrapping::OverflowingOps;
 
 use byteorder::BigEndian;
 use byteorder::ReadBytesExt;
-use rustc_serialize;
+use memchr::memchr;
+use rustc_serialize::{self, Decodable};
 
 use Error;
 use Result;
+use trace::{Counter, Trace, Tracer};
 
 /// A decoder for deserializing bytes in an order preserving format to a value.
 pub struct Decoder<R> {
-    reader: io::BufReader<R>
+    reader: Counter<R>,
+    read_until_nul: fn(&mut Counter<R>, &mut Vec<u8>) -> Result<()>,
+    nulls_last: bool,
+    trace: Option<Tracer>,
 }
 
 impl<R: io::Read> Decoder<R> {
 
-    /// Creates a new ordered bytes encoder whose output will be written to the provided writer.
+    /// Creates a new ordered bytes decoder whose input will be read from the provided reader.
+    ///
+    /// The decoder does not buffer its input, so it never reads past the end of a decoded value.
+    /// Strings are read a byte at a time; use `Decoder::buffered` for buffered readers.
     pub fn new(reader: R) -> Decoder<R> {
-        Decoder { reader: io::BufReader::new(reader) }
+        Decoder {
+            reader: Counter::new(reader),
+            read_until_nul: read_until_nul_unbuffered::<R>,
+            nulls_last: false,
+            trace: None,
+        }
+    }
+
+    /// Sets whether `None` option values sort after `Some` values.
+    ///
+    /// Must match the setting of the encoder. See `Encoder::set_nulls_last`.
+    pub fn set_nulls_last(&mut self, nulls_last: bool) {
+        self.nulls_last = nulls_last;
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader.inner
+    }
+
+    /// Sets whether the decoder records a trace of the spans of the values it decodes.
+    ///
+    /// Struct fields and enum variants are labelled with the names passed to `read_struct_field`
+    /// and `read_enum_variant`, and tuple elements with their index. See `Encoder::set_tracing`.
+    pub fn set_tracing(&mut self, tracing: bool) {
+        self.trace = if tracing { Some(Tracer::new()) } else { None };
+    }
+
+    /// Takes the spans traced since tracing was enabled or the trace was last taken.
+    ///
+    /// Returns an empty trace if tracing is not enabled.
+    pub fn take_trace(&mut self) -> Trace {
+        self.trace.as_mut().map_or_else(Trace::default, Tracer::take)
+    }
+
+    /// Runs the closure within a span with the label.
+    fn span<L, T, F>(&mut self, label: L, f: F) -> Result<T>
+    where L: fmt::Display, F: FnOnce(&mut Self) -> Result<T> {
+        if let Some(ref mut trace) = self.trace {
+            trace.open(label.to_string(), self.reader.pos);
+        }
+        let result = f(self);
+        if let Some(ref mut trace) = self.trace {
+            trace.close(self.reader.pos);
+        }
+        result
+    }
+
+    /// Records the bytes read by the closure as a span of the decoded value.
+    fn leaf<T, F>(&mut self, f: F) -> Result<T>
+    where T: fmt::Debug, F: FnOnce(&mut Self) -> Result<T> {
+        let start = self.reader.pos;
+        let result = f(self);
+        if let Some(ref mut trace) = self.trace {
+            if let Ok(ref value) = result {
+                trace.leaf(start, self.reader.pos, format!("{:?}", value));
+            }
+        }
+        result
     }
 
     pub fn read_var_u64(&mut self) -> Result<u64> {
+        self.leaf(|decoder| decoder.read_var_u64_untraced())
+    }
+
+    fn read_var_u64_untraced(&mut self) -> Result<u64> {
         let header = try!(self.reader.read_u8());
         let n = header >> 4;
         let (mut val, _) = ((header & 0x0F) as u64).overflowing_shl(n as u32 * 8);
-        for i in range_inclusive(1, n) {
+        for i in 1..n + 1 {
             let byte = try!(self.reader.read_u8());
             val += (byte as u64) << ((n - i) * 8);
         }
@@ -35,11 +104,15 @@ impl<R: io::Read> Decoder<R> {
     }
 
     pub fn read_var_i64(&mut self) -> Result<i64> {
+        self.leaf(|decoder| decoder.read_var_i64_untraced())
+    }
+
+    fn read_var_i64_untraced(&mut self) -> Result<i64> {
         let header = try!(self.reader.read_u8());
         let mask = ((header ^ 0x80) as i8 >> 7) as u8;
         let n = ((header >> 3) ^ mask) & 0x0F;
         let (mut val, _) = (((header ^ mask) & 0x07) as u64).overflowing_shl(n as u32 * 8);
-        for i in range_inclusive(1, n) {
+        for i in 1..n + 1 {
             let byte = try!(self.reader.read_u8());
             val += ((byte ^ mask) as u64) << ((n - i) * 8);
         }
@@ -47,6 +120,217 @@ impl<R: io::Read> Decoder<R> {
         val ^= final_mask;
         Ok(val as i64)
     }
+
+    /// Decode a byte string encoded with `Encoder::emit_escaped`.
+    pub fn read_escaped(&mut self) -> Result<Vec<u8>> {
+        self.leaf(|decoder| {
+            let mut bytes = Vec::new();
+            loop {
+                try!(decoder.read_until_nul(&mut bytes));
+                match try!(decoder.reader.read_u8()) {
+                    0xFF => bytes.push(0),
+                    0x01 => return Ok(bytes),
+                    _ => return Err(rustc_serialize::Decoder::error(decoder, "invalid escape sequence")),
+                }
+            }
+        })
+    }
+
+    /// Decode a value encoded with `Encoder::emit_desc`.
+    ///
+    /// The closure decodes the value from a decoder which inverts every bit of the input.
+    /// Recursive, dynamically typed values should be decoded through `Decoder::by_ref` within the
+    /// closure, so that the reader type does not nest without bound.
+    pub fn read_desc<T, F>(&mut self, f: F) -> Result<T>
+    where F: FnOnce(&mut Decoder<Invert<&mut R>>) -> Result<T> {
+        let start = self.reader.pos;
+        if let Some(ref mut trace) = self.trace {
+            trace.open("desc".to_string(), start);
+        }
+        let (result, len) = nested(Invert::new(&mut self.reader.inner), start, self.nulls_last,
+                                   &mut self.trace, f);
+        self.reader.pos += len;
+        if let Some(ref mut trace) = self.trace {
+            trace.close(self.reader.pos);
+        }
+        result
+    }
+
+    /// Runs the closure with a decoder which reads from this decoder's reader through a trait
+    /// object.
+    ///
+    /// This is useful for decoding recursive, dynamically typed values with `read_desc`, which
+    /// would otherwise nest the reader type without bound.
+    pub fn by_ref<T, F>(&mut self, f: F) -> Result<T>
+    where F: FnOnce(&mut Decoder<&mut io::Read>) -> Result<T> {
+        let start = self.reader.pos;
+        let (result, len) = nested(&mut self.reader.inner as &mut io::Read, start, self.nulls_last,
+                                   &mut self.trace, f);
+        self.reader.pos += len;
+        result
+    }
+
+    /// Reads bytes up to the next null byte onto the end of the vector, and consumes the null byte.
+    fn read_until_nul(&mut self, bytes: &mut Vec<u8>) -> Result<()> {
+        (self.read_until_nul)(&mut self.reader, bytes)
+    }
+}
+
+impl<R: io::BufRead> Decoder<R> {
+
+    /// Creates a new ordered bytes decoder which reads directly from the buffer of the provided
+    /// reader.
+    ///
+    /// Null-terminated strings are located in the reader's buffer, so that they are not read a
+    /// byte at a time. The decoder only consumes the bytes of decoded values, so it never consumes
+    /// input past the end of a decoded value.
+    pub fn buffered(reader: R) -> Decoder<R> {
+        Decoder {
+            reader: Counter::new(reader),
+            read_until_nul: read_until_nul_buffered::<R>,
+            nulls_last: false,
+            trace: None,
+        }
+    }
+
+    /// Returns an iterator which decodes consecutive values of type `T` until the end of the input.
+    ///
+    /// Iteration ends when the input is exhausted between two values. If the input ends partway
+    /// through a value, the iterator returns `Error::UnexpectedEof` and then ends. The reader must
+    /// be buffered in order to detect the end of the input without consuming it.
+    ///
+    /// #### Usage
+    ///
+    /// ```
+    /// # use std::io::Cursor;
+    /// # use bytekey::{encode, Decoder};
+    /// let mut bytes = encode(&1u32).unwrap();
+    /// bytes.extend(encode(&2u32).unwrap().into_iter());
+    ///
+    /// let mut decoder = Decoder::buffered(Cursor::new(bytes));
+    /// let values: Vec<u32> = decoder.iter().map(Result::unwrap).collect();
+    /// assert_eq!(vec!(1, 2), values);
+    /// ```
+    pub fn iter<T>(&mut self) -> DecodeIter<R, T> where T: Decodable {
+        DecodeIter { decoder: self, done: false, marker: PhantomData }
+    }
+}
+
+/// An iterator over consecutive values decoded from a reader.
+///
+/// Created by `Decoder::iter`.
+pub struct DecodeIter<'a, R: 'a, T> {
+    decoder: &'a mut Decoder<R>,
+    done: bool,
+    marker: PhantomData<T>,
+}
+
+impl<'a, R, T> Iterator for DecodeIter<'a, R, T> where R: io::BufRead, T: Decodable {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        if self.done { return None; }
+        match self.decoder.reader.fill_buf() {
+            Ok(buf) if buf.is_empty() => {
+                self.done = true;
+                return None;
+            },
+            Ok(_) => (),
+            Err(error) => {
+                self.done = true;
+                return Some(Err(Error::Io(error)));
+            },
+        }
+        let result = Decodable::decode(&mut *self.decoder);
+        self.done = result.is_err();
+        Some(result)
+    }
+}
+
+/// Reads bytes up to the next null byte onto the end of the vector a byte at a time, and consumes
+/// the null byte.
+fn read_until_nul_unbuffered<R>(reader: &mut Counter<R>, bytes: &mut Vec<u8>) -> Result<()>
+where R: io::Read {
+    loop {
+        match try!(reader.read_u8()) {
+            0 => return Ok(()),
+            byte => bytes.push(byte),
+        }
+    }
+}
+
+/// Reads bytes up to the next null byte onto the end of the vector, and consumes the null byte.
+///
+/// The null byte is located directly in the reader's buffer. If the vector is empty and the null
+/// byte is in the reader's current buffer, the vector is allocated exactly once.
+fn read_until_nul_buffered<R>(reader: &mut Counter<R>, bytes: &mut Vec<u8>) -> Result<()>
+where R: io::BufRead {
+    loop {
+        let (done, used) = {
+            let buf = try!(reader.fill_buf());
+            if buf.is_empty() { return Err(Error::UnexpectedEof); }
+            match memchr(0, buf) {
+                Some(idx) if bytes.is_empty() => {
+                    *bytes = buf[..idx].to_vec();
+                    (true, idx + 1)
+                },
+                Some(idx) => {
+                    bytes.extend(buf[..idx].iter().cloned());
+                    (true, idx + 1)
+                },
+                None => {
+                    bytes.extend(buf.iter().cloned());
+                    (false, buf.len())
+                },
+            }
+        };
+        reader.consume(used);
+        if done { return Ok(()); }
+    }
+}
+
+/// Runs the closure with a decoder over the reader, which continues from position `start` of an
+/// enclosing decoder and shares its trace. Returns the result and the number of bytes read.
+fn nested<S, T, F>(reader: S, start: usize, nulls_last: bool, trace: &mut Option<Tracer>, f: F)
+                   -> (Result<T>, usize)
+where S: io::Read, F: FnOnce(&mut Decoder<S>) -> Result<T> {
+    let mut decoder = Decoder::new(reader);
+    decoder.nulls_last = nulls_last;
+    decoder.trace = trace.take().map(|mut trace| {
+        trace.offset += start;
+        trace
+    });
+    let result = f(&mut decoder);
+    *trace = decoder.trace.take().map(|mut trace| {
+        trace.offset -= start;
+        trace
+    });
+    (result, decoder.reader.pos)
+}
+
+/// A reader adapter which inverts every bit read from the underlying reader.
+///
+/// Used by `Decoder::read_desc` to decode values encoded in descending order.
+pub struct Invert<R> {
+    reader: R,
+}
+
+impl<R: io::Read> Invert<R> {
+
+    /// Creates a new inverting reader.
+    pub fn new(reader: R) -> Invert<R> {
+        Invert { reader: reader }
+    }
+}
+
+impl<R: io::Read> io::Read for Invert<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = try!(self.reader.read(buf));
+        for byte in buf[..len].iter_mut() {
+            *byte = !*byte;
+        }
+        Ok(len)
+    }
 }
 
 impl<R> rustc_serialize::Decoder for Decoder<R>
@@ -57,16 +341,16 @@ where R: io::Read {
     fn read_nil(&mut self) -> Result<()> { Ok(()) }
 
     fn read_u8(&mut self) -> Result<u8> {
-        self.reader.read_u8().map_err(From::from)
+        self.leaf(|decoder| decoder.reader.read_u8().map_err(From::from))
     }
     fn read_u16(&mut self) -> Result<u16> {
-        self.reader.read_u16::<BigEndian>().map_err(From::from)
+        self.leaf(|decoder| decoder.reader.read_u16::<BigEndian>().map_err(From::from))
     }
     fn read_u32(&mut self) -> Result<u32> {
-        self.reader.read_u32::<BigEndian>().map_err(From::from)
+        self.leaf(|decoder| decoder.reader.read_u32::<BigEndian>().map_err(From::from))
     }
     fn read_u64(&mut self) -> Result<u64> {
-        self.reader.read_u64::<BigEndian>().map_err(From::from)
+        self.leaf(|decoder| decoder.reader.read_u64::<BigEndian>().map_err(From::from))
     }
     fn read_usize(&mut self) -> Result<usize> {
         let val = try!(self.read_var_u64());
@@ -74,20 +358,28 @@ where R: io::Read {
     }
 
     fn read_i8(&mut self) -> Result<i8> {
-        let val = try!(self.reader.read_i8());
-        Ok(val ^ i8::MIN)
+        self.leaf(|decoder| {
+            let val = try!(decoder.reader.read_i8());
+            Ok(val ^ i8::MIN)
+        })
     }
     fn read_i16(&mut self) -> Result<i16> {
-        let val = try!(self.reader.read_i16::<BigEndian>());
-        Ok(val ^ i16::MIN)
+        self.leaf(|decoder| {
+            let val = try!(decoder.reader.read_i16::<BigEndian>());
+            Ok(val ^ i16::MIN)
+        })
     }
     fn read_i32(&mut self) -> Result<i32> {
-        let val = try!(self.reader.read_i32::<BigEndian>());
-        Ok(val ^ i32::MIN)
+        self.leaf(|decoder| {
+            let val = try!(decoder.reader.read_i32::<BigEndian>());
+            Ok(val ^ i32::MIN)
+        })
     }
     fn read_i64(&mut self) -> Result<i64> {
-        let val = try!(self.reader.read_i64::<BigEndian>());
-        Ok(val ^ i64::MIN)
+        self.leaf(|decoder| {
+            let val = try!(decoder.reader.read_i64::<BigEndian>());
+            Ok(val ^ i64::MIN)
+        })
     }
     fn read_isize(&mut self) -> Result<isize> {
         let val = try!(self.read_var_i64());
@@ -95,88 +387,118 @@ where R: io::Read {
     }
 
     fn read_bool(&mut self) -> Result<bool> {
-        match try!(self.reader.read_u8()) {
-            0 => Ok(false),
-            _ => Ok(true)
-        }
+        self.leaf(|decoder| {
+            match try!(decoder.reader.read_u8()) {
+                0 => Ok(false),
+                _ => Ok(true)
+            }
+        })
     }
 
     fn read_f32(&mut self) -> Result<f32> {
-        let val = try!(self.reader.read_i32::<BigEndian>());
-        let t = ((val ^ i32::MIN) >> 31) | i32::MIN;
-        let f = unsafe { transmute(val ^ t) };
-        Ok(f)
+        self.leaf(|decoder| {
+            let val = try!(decoder.reader.read_i32::<BigEndian>());
+            let t = ((val ^ i32::MIN) >> 31) | i32::MIN;
+            let f: f32 = unsafe { transmute(val ^ t) };
+            Ok(f)
+        })
     }
     fn read_f64(&mut self) -> Result<f64> {
-        let val = try!(self.reader.read_i64::<BigEndian>());
-        let t = ((val ^ i64::MIN) >> 63) | i64::MIN;
-        let f = unsafe { transmute(val ^ t) };
-        Ok(f)
+        self.leaf(|decoder| {
+            let val = try!(decoder.reader.read_i64::<BigEndian>());
+            let t = ((val ^ i64::MIN) >> 63) | i64::MIN;
+            let f: f64 = unsafe { transmute(val ^ t) };
+            Ok(f)
+        })
     }
 
     fn read_char(&mut self) -> Result<char> {
-        match (&mut self.reader).chars().next() {
-            Some(Ok(c)) => Ok(c),
-            Some(Err(io::CharsError::NotUtf8)) => Err(Error::NotUtf8),
-            Some(Err(io::CharsError::Other(error))) => Err(Error::Io(error)),
-            None => Err(Error::UnexpectedEof),
-        }
-    }
-
+        self.leaf(|decoder| {
+            let mut buf = [0u8; 4];
+            buf[0] = try!(decoder.reader.read_u8());
+            let len = match buf[0] {
+                0x00...0x7F => 1,
+                0xC0...0xDF => 2,
+                0xE0...0xEF => 3,
+                0xF0...0xF7 => 4,
+                _ => return Err(Error::NotUtf8),
+            };
+            for byte in buf[1..len].iter_mut() {
+                *byte = try!(decoder.reader.read_u8());
+            }
+            match str::from_utf8(&buf[..len]) {
+                Ok(s) => Ok(s.chars().next().unwrap()),
+                Err(_) => Err(Error::NotUtf8),
+            }
+        })
+    }
+
+    /// Decode a null-terminated string.
+    ///
+    /// The string is validated as UTF-8 in a single pass. When decoding from a buffered reader
+    /// (see `Decoder::buffered`), the terminator is located directly in the reader's buffer, and if
+    /// it is in the reader's current buffer the string is allocated exactly once.
     fn read_str(&mut self) -> Result<String> {
-        let mut string = String::new();
-
-        loop {
-            let c = try!(self.read_char());
-            if c == '\0' { break; }
-            string.push(c);
-        }
-
-        Ok(string)
+        self.leaf(|decoder| {
+            let mut bytes = Vec::new();
+            try!(decoder.read_until_nul(&mut bytes));
+            String::from_utf8(bytes).map_err(|_| Error::NotUtf8)
+        })
     }
 
     fn read_enum<T, F>(&mut self, _name: &str, f: F) -> Result<T>
             where F: FnOnce(&mut Self) -> Result<T> {
         f(self)
     }
-    fn read_enum_variant<T, F>(&mut self, _names: &[&str], mut f: F) -> Result<T>
+    fn read_enum_variant<T, F>(&mut self, names: &[&str], mut f: F) -> Result<T>
             where F: FnMut(&mut Self, usize) -> Result<T> {
-        let id = try!(self.read_usize());
-        f(self, id)
+        // The variant's span starts at its tag, so the tag is read before the span is opened.
+        let start = self.reader.pos;
+        let id = try!(self.read_var_u64_untraced()) as usize;
+        if let Some(ref mut trace) = self.trace {
+            let label = names.get(id).map_or_else(|| id.to_string(), |name| name.to_string());
+            trace.open(label, start);
+            trace.leaf(start, self.reader.pos, format!("{:?}", id));
+        }
+        let result = f(self, id);
+        if let Some(ref mut trace) = self.trace {
+            trace.close(self.reader.pos);
+        }
+        result
     }
-    fn read_enum_variant_arg<T, F>(&mut self, _idx: usize, f: F) -> Result<T>
+    fn read_enum_variant_arg<T, F>(&mut self, idx: usize, f: F) -> Result<T>
             where F: FnOnce(&mut Self) -> Result<T> {
-        f(self)
+        self.span(idx, f)
     }
     fn read_enum_struct_variant<T, F>(&mut self, names: &[&str], f: F) -> Result<T>
             where F: FnMut(&mut Self, usize) -> Result<T> {
         self.read_enum_variant(names, f)
     }
     fn read_enum_struct_variant_field<T, F>(&mut self,
-                                            _name: &str,
-                                            idx: usize,
+                                            name: &str,
+                                            _idx: usize,
                                             f: F)
                                             -> Result<T>
             where F: FnOnce(&mut Self) -> Result<T> {
-        self.read_enum_variant_arg(idx, f)
+        self.span(name, f)
     }
 
     fn read_struct<T, F>(&mut self, _name: &str, _len: usize, f: F) -> Result<T>
             where F: FnOnce(&mut Self) -> Result<T> {
         f(self)
     }
-    fn read_struct_field<T, F>(&mut self, _name: &str, _idx: usize, f: F) -> Result<T>
+    fn read_struct_field<T, F>(&mut self, name: &str, idx: usize, f: F) -> Result<T>
             where F: FnOnce(&mut Self) -> Result<T> {
-        f(self)
+        if name.is_empty() { self.span(idx, f) } else { self.span(name, f) }
     }
 
     fn read_tuple<T, F>(&mut self, _len: usize, f: F) -> Result<T>
             where F: FnOnce(&mut Self) -> Result<T> {
         f(self)
     }
-    fn read_tuple_arg<T, F>(&mut self, _idx: usize, f: F) -> Result<T>
+    fn read_tuple_arg<T, F>(&mut self, idx: usize, f: F) -> Result<T>
             where F: FnOnce(&mut Self) -> Result<T> {
-        f(self)
+        self.span(idx, f)
     }
 
     fn read_tuple_struct<T, F>(&mut self, _name: &str, len: usize, f: F) -> Result<T>
@@ -190,7 +512,13 @@ where R: io::Read {
 
     fn read_option<T, F>(&mut self, mut f: F) -> Result<T>
             where F: FnMut(&mut Self, bool) -> Result<T> {
-        let is_some = try!(self.read_bool());
+        let nulls_last = self.nulls_last;
+        let start = self.reader.pos;
+        let is_some = try!(self.reader.read_u8().map(|marker| (marker != 0) != nulls_last));
+        if let Some(ref mut trace) = self.trace {
+            let label = if is_some { "Some" } else { "None" };
+            trace.leaf(start, self.reader.pos, label.to_string());
+        }
         f(self, is_some)
     }
 
@@ -225,10 +553,100 @@ where R: io::Read {
 mod test {
 
     use std::{f32, f64, isize, usize};
+    use std::io::{BufReader, Cursor};
+
+    use quickcheck::TestResult;
+    use rustc_serialize::Decodable;
 
-    use {encode, decode};
+    use {encode, decode, Decoder, Error};
     use encoder::test::{TestStruct, TestEnum};
 
+    #[test]
+    fn test_into_inner() {
+        let mut bytes = encode(&(42u32, "foo")).unwrap();
+        let len = bytes.len();
+        bytes.extend([0xFF, 0xFF].iter().cloned());
+
+        let mut decoder = Decoder::new(Cursor::new(bytes));
+        let value: (u32, String) = Decodable::decode(&mut decoder).unwrap();
+        assert_eq!((42, "foo".to_string()), value);
+        assert_eq!(len as u64, decoder.into_inner().position());
+    }
+
+    #[test]
+    fn test_iter() {
+        let values = vec![(1u32, "a".to_string()), (2, "bc".to_string()), (3, "".to_string())];
+        let mut bytes = Vec::new();
+        for value in values.iter() {
+            bytes.extend(encode(value).unwrap().into_iter());
+        }
+
+        let mut decoder = Decoder::buffered(Cursor::new(bytes.clone()));
+        let decoded: Vec<(u32, String)> = decoder.iter().map(|value| value.unwrap()).collect();
+        assert_eq!(values, decoded);
+
+        let mut decoder = Decoder::buffered(Cursor::new(Vec::new()));
+        assert!(decoder.iter::<u32>().next().is_none());
+
+        // Truncate the final value.
+        bytes.truncate(bytes.len() - 2);
+        let mut decoder = Decoder::buffered(Cursor::new(bytes));
+        let mut iter = decoder.iter::<(u32, String)>();
+        assert_eq!(values[0], iter.next().unwrap().unwrap());
+        assert_eq!(values[1], iter.next().unwrap().unwrap());
+        match iter.next() {
+            Some(Err(Error::UnexpectedEof)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_str_buffer_boundaries() {
+        let value = "a string which spans several small reader buffers".to_string();
+        let bytes = encode(&(value.clone(), 7u8)).unwrap();
+        for capacity in 1..bytes.len() + 1 {
+            let reader = BufReader::with_capacity(capacity, Cursor::new(bytes.clone()));
+            let mut decoder = Decoder::buffered(reader);
+            let decoded: (String, u8) = Decodable::decode(&mut decoder).unwrap();
+            assert_eq!((value.clone(), 7), decoded);
+        }
+    }
+
+    #[test]
+    fn test_unbuffered() {
+        let mut bytes = encode(&("foo", 'é', 42u32)).unwrap();
+        let len = bytes.len();
+        bytes.extend([0xFF, 0xFF].iter().cloned());
+
+        let mut decoder = Decoder::new(Cursor::new(bytes));
+        let value: (String, char, u32) = Decodable::decode(&mut decoder).unwrap();
+        assert_eq!(("foo".to_string(), 'é', 42), value);
+        assert_eq!(len as u64, decoder.into_inner().position());
+    }
+
+    #[test]
+    fn test_char_errors() {
+        for bytes in vec!(vec!(0xC3), vec!(0xFF), vec!(0xC3, 0x28), vec!(0xED, 0xA0, 0x80)) {
+            match decode::<char>(bytes.clone()) {
+                Err(Error::UnexpectedEof) | Err(Error::NotUtf8) => (),
+                other => panic!("unexpected result for {:?}: {:?}", bytes, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_str_errors() {
+        match decode::<String>(vec!(0x66, 0x6F, 0x6F)) {
+            Err(Error::UnexpectedEof) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match decode::<String>(vec!(0x66, 0xFF, 0x6F, 0x00)) {
+            Err(Error::NotUtf8) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
     #[quickcheck]
     fn check_u8(val: u8) -> bool {
         val == decode(encode(&val).unwrap()).unwrap()
@@ -309,8 +727,9 @@ mod test {
     }
 
     #[quickcheck]
-    fn check_f32(val: f32) -> bool {
-        val == decode(encode(&val).unwrap()).unwrap()
+    fn check_f32(val: f32) -> TestResult {
+        if val.is_nan() { return TestResult::discard() }
+        TestResult::from_bool(val == decode(encode(&val).unwrap()).unwrap())
     }
     #[test]
     fn test_f32() {
@@ -329,8 +748,9 @@ mod test {
     }
 
     #[quickcheck]
-    fn check_f64(val: f64) -> bool {
-        val == decode(encode(&val).unwrap()).unwrap()
+    fn check_f64(val: f64) -> TestResult {
+        if val.is_nan() { return TestResult::discard() }
+        TestResult::from_bool(val == decode(encode(&val).unwrap()).unwrap())
     }
     #[test]
     fn test_f64() {
@@ -354,13 +774,15 @@ mod test {
     }
 
     #[quickcheck]
-    fn check_string(val: String) -> bool {
-        val == decode::<String>(encode(&val).unwrap()).unwrap()
+    fn check_string(val: String) -> TestResult {
+        if val.contains('\0') { return TestResult::discard() }
+        TestResult::from_bool(val == decode::<String>(encode(&val).unwrap()).unwrap())
     }
 
     #[quickcheck]
-    fn check_option(val: Option<String>) -> bool {
-        val == decode(encode(&val).unwrap()).unwrap()
+    fn check_option(val: Option<String>) -> TestResult {
+        if val.as_ref().map_or(false, |s| s.contains('\0')) { return TestResult::discard() }
+        TestResult::from_bool(val == decode(encode(&val).unwrap()).unwrap())
     }
 
     #[quickcheck]
@@ -369,8 +791,9 @@ mod test {
     }
 
      #[quickcheck]
-    fn check_tuple(val: (usize, char, String)) -> bool {
-        val == decode(encode(&val).unwrap()).unwrap()
+    fn check_tuple(val: (usize, char, String)) -> TestResult {
+        if val.2.contains('\0') { return TestResult::discard() }
+        TestResult::from_bool(val == decode(encode(&val).unwrap()).unwrap())
     }
 
     #[quickcheck]
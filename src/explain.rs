@@ -0,0 +1,280 @@
+//! Explanations of the order of encoded keys.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use rustc_serialize::Decodable;
+
+use {Decoder, Error, Result, Schema, Span, Trace};
+
+/// The first difference between two encoded keys, which decides their order.
+///
+/// Returned by `explain_order` and `explain_order_of`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Difference {
+    /// The order of the first key relative to the second.
+    pub ordering: Ordering,
+    /// The offset of the first byte which differs between the keys.
+    pub offset: usize,
+    /// The dotted path of the field containing the differing byte.
+    pub field: String,
+    /// The value of the field in the first key, or `None` if the first key ends at the offset.
+    pub a: Option<String>,
+    /// The value of the field in the second key, or `None` if the second key ends at the offset.
+    pub b: Option<String>,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self.ordering {
+            Ordering::Less => "<",
+            Ordering::Equal => "=",
+            Ordering::Greater => ">",
+        };
+        let field = if self.field.is_empty() { "value" } else { &self.field };
+        write!(f, "{} differs at byte {}: {} {} {}",
+               field,
+               self.offset,
+               self.a.as_ref().map_or("<end of key>", |a| a),
+               op,
+               self.b.as_ref().map_or("<end of key>", |b| b))
+    }
+}
+
+/// Explains the order of two keys encoded with the schema.
+///
+/// Both keys are decoded, and the first differing byte is located in the decoded fields. Returns
+/// `None` if the keys are equal.
+///
+/// A key may end early, like the prefix or range bound of a scan, and is explained as far as it
+/// decodes. Returns an error if either key is invalid, or ends within the value at the differing
+/// byte.
+///
+/// Fields are reported by their dotted path, as in `Trace::hexdump`, but without the `desc` label
+/// of descending values. If the keys differ in their enum variant, the field is the path of the
+/// enum, and the values are the variant tags.
+///
+/// #### Usage
+///
+/// ```
+/// # use bytekey::{encode_value, explain_order, Schema, Value};
+/// let schema = Schema::parse("struct Key { tenant: u32, name: str desc }").unwrap();
+/// let key = |name: &str| {
+///     let value = Value::Tuple(vec!(Value::U32(1), Value::Str(name.to_string())));
+///     encode_value(&schema, &value).unwrap()
+/// };
+///
+/// let difference = explain_order(&schema, &key("bob"), &key("carol")).unwrap().unwrap();
+/// assert_eq!("name differs at byte 4: \"bob\" > \"carol\"", difference.to_string());
+///
+/// // The end of a key sorts before any field which follows it.
+/// let difference = explain_order(&schema, &key("bob")[..4], &key("bob")).unwrap().unwrap();
+/// assert_eq!("name differs at byte 4: <end of key> < \"bob\"", difference.to_string());
+/// ```
+pub fn explain_order(schema: &Schema, a: &[u8], b: &[u8]) -> Result<Option<Difference>> {
+    explain(a, b, |bytes| {
+        let mut decoder = Decoder::buffered(bytes);
+        decoder.set_tracing(true);
+        let result = schema.decode_value(&mut decoder).map(|_| ());
+        (decoder.take_trace(), result)
+    })
+}
+
+/// Explains the order of two encoded keys of type `T`.
+///
+/// Like `explain_order`, but the keys are decoded as `T` rather than with a schema, so fields are
+/// labelled with the names passed to `read_struct_field` and `read_enum_variant`.
+///
+/// #### Usage
+///
+/// ```
+/// # use bytekey::{encode, explain_order_of};
+/// let a = encode(&(1u32, "bob")).unwrap();
+/// let b = encode(&(1u32, "alice")).unwrap();
+///
+/// let difference = explain_order_of::<(u32, String)>(&a, &b).unwrap().unwrap();
+/// assert_eq!("1 differs at byte 4: \"bob\" > \"alice\"", difference.to_string());
+/// ```
+pub fn explain_order_of<T>(a: &[u8], b: &[u8]) -> Result<Option<Difference>> where T: Decodable {
+    explain(a, b, |bytes| {
+        let mut decoder = Decoder::buffered(bytes);
+        decoder.set_tracing(true);
+        let result = T::decode(&mut decoder).map(|_| ());
+        (decoder.take_trace(), result)
+    })
+}
+
+/// Locates the first difference between the keys in the traces returned by the closure.
+///
+/// The closure returns the trace of the values decoded before any error, along with the result of
+/// decoding.
+fn explain<F>(a: &[u8], b: &[u8], trace: F) -> Result<Option<Difference>>
+where F: Fn(&[u8]) -> (Trace, Result<()>) {
+    let offset = match a.iter().zip(b.iter()).position(|(a, b)| a != b) {
+        Some(offset) => offset,
+        None if a.len() == b.len() => return Ok(None),
+        None => ::std::cmp::min(a.len(), b.len()),
+    };
+    let (a_trace, a_result) = trace(a);
+    let (b_trace, b_result) = trace(b);
+    let a_leaf = leaf_at(&a_trace, offset);
+    let b_leaf = leaf_at(&b_trace, offset);
+
+    try!(decoded_to(a, offset, a_leaf.is_some(), a_result));
+    try!(decoded_to(b, offset, b_leaf.is_some(), b_result));
+
+    // The keys are identical before the offset, so both leaves have the same path.
+    let field = a_leaf.as_ref().or(b_leaf.as_ref()).map_or(String::new(), |leaf| leaf.0.clone());
+    Ok(Some(Difference {
+        ordering: a.cmp(b),
+        offset: offset,
+        field: field,
+        a: a_leaf.and_then(|(_, span)| span.value.clone()),
+        b: b_leaf.and_then(|(_, span)| span.value.clone()),
+    }))
+}
+
+/// Checks that a key decoded far enough to explain the byte at the offset.
+///
+/// A key which ends early is explained if it ends at the offset, or decodes a value containing it.
+fn decoded_to(key: &[u8], offset: usize, has_leaf: bool, result: Result<()>) -> Result<()> {
+    match result {
+        Err(Error::UnexpectedEof) if has_leaf || key.len() == offset => Ok(()),
+        result => result,
+    }
+}
+
+/// Returns the path and span of the primitive value containing the byte offset.
+///
+/// The `desc` labels of descending values are left out of the path.
+fn leaf_at(trace: &Trace, offset: usize) -> Option<(String, &Span)> {
+    trace.leaves().into_iter().find(|&(_, span)| span.start <= offset && offset < span.end)
+         .map(|(path, span)| {
+             let path = path.split('.').filter(|&label| label != "desc").collect::<Vec<_>>();
+             (path.join("."), span)
+         })
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::cmp::Ordering;
+
+    use rustc_serialize::{self, Decodable};
+
+    use {encode, encode_value, explain_order, explain_order_of, Schema, Value};
+    use super::Difference;
+
+    fn difference(ordering: Ordering, offset: usize, field: &str, a: &str, b: &str) -> Difference {
+        Difference {
+            ordering: ordering,
+            offset: offset,
+            field: field.to_string(),
+            a: Some(a.to_string()),
+            b: Some(b.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_struct() {
+        let schema = Schema::parse("struct Key { tenant: u32, name: str, created: i64 desc }").unwrap();
+        let key = |tenant: u32, name: &str, created: i64| {
+            let value = Value::Tuple(vec!(Value::U32(tenant),
+                                          Value::Str(name.to_string()),
+                                          Value::I64(created)));
+            encode_value(&schema, &value).unwrap()
+        };
+
+        let a = key(3, "bob", 10);
+        assert_eq!(None, explain_order(&schema, &a, &a).unwrap());
+        assert_eq!(Some(difference(Ordering::Less, 3, "tenant", "3", "4")),
+                   explain_order(&schema, &a, &key(4, "bob", 10)).unwrap());
+        assert_eq!(Some(difference(Ordering::Less, 4, "name", "\"bob\"", "\"carol\"")),
+                   explain_order(&schema, &a, &key(3, "carol", 10)).unwrap());
+        assert_eq!(Some(difference(Ordering::Greater, 15, "created", "10", "11")),
+                   explain_order(&schema, &a, &key(3, "bob", 11)).unwrap());
+    }
+
+    #[test]
+    fn test_enum() {
+        let schema = Schema::parse("enum Key { User(u32), Group { id: u32 } = 5 }").unwrap();
+        let user = encode_value(&schema, &Value::Variant(0, vec!(Value::U32(7)))).unwrap();
+        let group = encode_value(&schema, &Value::Variant(5, vec!(Value::Tuple(vec!(Value::U32(7))))))
+                        .unwrap();
+        assert_eq!(Some(difference(Ordering::Less, 0, "User", "0", "5")),
+                   explain_order(&schema, &user, &group).unwrap());
+    }
+
+    #[test]
+    fn test_prefix() {
+        let schema = Schema::parse("struct Key(seq<u8>);").unwrap();
+        let a = encode_value(&schema, &Value::Tuple(vec!(Value::Seq(vec!(Value::U8(1)))))).unwrap();
+        let b = encode_value(&schema, &Value::Tuple(vec!(Value::Seq(vec!(Value::U8(1), Value::U8(2))))))
+                    .unwrap();
+        assert_eq!(Some(difference(Ordering::Less, 2, "0", "0", "1")),
+                   explain_order(&schema, &a, &b).unwrap());
+        assert!(explain_order(&schema, &a, &[2]).is_err());
+    }
+
+    #[test]
+    fn test_partial_keys() {
+        let schema = Schema::parse("struct Key { tenant: u32, name: str, created: i64 desc }").unwrap();
+        let key = encode_value(&schema, &Value::Tuple(vec!(Value::U32(3),
+                                                           Value::Str("bob".to_string()),
+                                                           Value::I64(10)))).unwrap();
+
+        // A prefix ending between fields.
+        assert_eq!(Some(Difference {
+                        ordering: Ordering::Less,
+                        offset: 4,
+                        field: "name".to_string(),
+                        a: None,
+                        b: Some("\"bob\"".to_string()),
+                    }),
+                   explain_order(&schema, &key[..4], &key).unwrap());
+
+        // A range bound which differs within a field of a truncated key.
+        assert_eq!(Some(difference(Ordering::Less, 3, "tenant", "3", "4")),
+                   explain_order(&schema, &key, &[0, 0, 0, 4]).unwrap());
+        assert_eq!(Some(difference(Ordering::Less, 4, "name", "\"bob\"", "\"c\"")),
+                   explain_order(&schema, &key, &[0, 0, 0, 3, b'c', 0]).unwrap());
+
+        // A difference past the end of what decodes is still an error.
+        assert!(explain_order(&schema, &[0, 0, 0, 3, 0xFF, 1], &[0, 0, 0, 3, 0xFF, 2]).is_err());
+    }
+
+    struct Key {
+        _tenant: u32,
+        _created: Option<i64>,
+    }
+
+    impl Decodable for Key {
+        fn decode<D>(d: &mut D) -> Result<Key, D::Error> where D: rustc_serialize::Decoder {
+            d.read_struct("Key", 2, |d| {
+                Ok(Key {
+                    _tenant: try!(d.read_struct_field("_tenant", 0, Decodable::decode)),
+                    _created: try!(d.read_struct_field("_created", 1, Decodable::decode)),
+                })
+            })
+        }
+    }
+
+    #[test]
+    fn test_decodable() {
+        let a = encode(&(3u32, Some(10i64))).unwrap();
+        assert_eq!(None, explain_order_of::<Key>(&a, &a).unwrap());
+        assert_eq!(Some(difference(Ordering::Less, 3, "_tenant", "3", "4")),
+                   explain_order_of::<Key>(&a, &encode(&(4u32, Some(10i64))).unwrap()).unwrap());
+        assert_eq!(Some(difference(Ordering::Greater, 4, "_created", "Some", "None")),
+                   explain_order_of::<Key>(&a, &encode(&(3u32, None::<i64>)).unwrap()).unwrap());
+        assert_eq!(Some(Difference {
+                        ordering: Ordering::Greater,
+                        offset: 1,
+                        field: "_tenant".to_string(),
+                        a: Some("3".to_string()),
+                        b: None,
+                    }),
+                   explain_order_of::<Key>(&a, &[0]).unwrap());
+        assert!(explain_order_of::<Key>(&a, &[0, 0, 0, 3, 1, 0x81]).is_err());
+    }
+}
@@ -0,0 +1,975 @@
+//! Encoding compatible with the FoundationDB tuple layer.
+//!
+//! The tuple layer is a self-describing, order-preserving encoding of tuples. Every element is
+//! prefixed by a type code, so that keys of different types sort in a fixed order:
+//!
+//! <table>
+//!     <tr><th>code</th><th>type</th><th>Rust type</th></tr>
+//!     <tr><td>0x00</td><td>null</td><td><code>None</code></td></tr>
+//!     <tr><td>0x01</td><td>byte string</td><td><code>Bytes</code></td></tr>
+//!     <tr><td>0x02</td><td>unicode string</td><td><code>String</code>, <code>char</code></td></tr>
+//!     <tr><td>0x05</td><td>nested tuple</td><td>tuples, structs, <code>Vec</code></td></tr>
+//!     <tr><td>0x0C - 0x1C</td><td>integer</td><td>integers</td></tr>
+//!     <tr><td>0x20</td><td>float</td><td><code>f32</code></td></tr>
+//!     <tr><td>0x21</td><td>double</td><td><code>f64</code></td></tr>
+//!     <tr><td>0x26, 0x27</td><td>false, true</td><td><code>bool</code></td></tr>
+//!     <tr><td>0x33</td><td>versionstamp</td><td><code>Versionstamp</code></td></tr>
+//! </table>
+//!
+//! Strings and byte strings are terminated by `0x00`, with embedded null bytes escaped as
+//! `0x00 0xFF`. Integers are encoded as the minimal big-endian bytes of their magnitude, with the
+//! type code `0x14` offset by the number of bytes, and negative integers stored as the one's
+//! complement. Floats are stored with the sign bit flipped if positive, or every bit flipped if
+//! negative. Nested tuples are terminated by `0x00`, and nulls within nested tuples are escaped as
+//! `0x00 0xFF`.
+//!
+//! A packed value is a tuple of elements. Top-level tuples, structs, and sequences are packed as
+//! their elements, and any other value is packed as a single element tuple. Enums and maps are not
+//! supported by the tuple layer. `Vec<u8>` packs as a nested tuple of integers; wrap byte strings in
+//! `Bytes` to pack them as byte strings.
+//!
+//! #### Usage
+//!
+//! ```
+//! # use bytekey::fdb::{pack, range, unpack};
+//! let key = pack(&("users", 42u32)).unwrap();
+//! assert_eq!(vec!(0x02, b'u', b's', b'e', b'r', b's', 0x00, 0x15, 42), key);
+//! assert_eq!(("users".to_string(), 42u32), unpack(&key).unwrap());
+//!
+//! let (start, end) = range(&("users",)).unwrap();
+//! assert!(start < key && key < end);
+//! ```
+
+use std::{i8, i16, i32, i64, u8, u16, u32};
+use std::mem::transmute;
+
+use rustc_serialize::{self, Decodable, Encodable};
+
+use {Error, Result};
+use util::{error, single_char, Int};
+
+const NULL: u8 = 0x00;
+const BYTES: u8 = 0x01;
+const STRING: u8 = 0x02;
+const NESTED: u8 = 0x05;
+const INT_ZERO: u8 = 0x14;
+const FLOAT: u8 = 0x20;
+const DOUBLE: u8 = 0x21;
+const FALSE: u8 = 0x26;
+const TRUE: u8 = 0x27;
+const UUID: u8 = 0x30;
+const VERSIONSTAMP: u8 = 0x33;
+
+/// The struct names which mark `Bytes` and `Versionstamp` values to the encoder and decoder.
+///
+/// These names are reserved: a value encoded or decoded under one of them must have the shape of
+/// `Bytes` or `Versionstamp`, a sequence of `u8` or 12 `u8` fields, or it is rejected.
+const BYTES_NAME: &'static str = "__bytekey_fdb_bytes";
+const VERSIONSTAMP_NAME: &'static str = "__bytekey_fdb_versionstamp";
+
+/// Packs a value into a tuple-layer key.
+///
+/// Returns an error if the value contains an incomplete versionstamp. Use `pack_with_versionstamp`
+/// to pack keys for versionstamped operations.
+pub fn pack<T>(value: &T) -> Result<Vec<u8>> where T: Encodable {
+    let encoder = try!(encode(value));
+    if encoder.versionstamp.is_some() {
+        return Err(error("incomplete versionstamp; use pack_with_versionstamp"));
+    }
+    Ok(encoder.buf)
+}
+
+/// Packs a value containing exactly one incomplete versionstamp.
+///
+/// The offset of the versionstamp is appended to the key as 4 little-endian bytes, as expected by
+/// the `SetVersionstampedKey` atomic operation.
+pub fn pack_with_versionstamp<T>(value: &T) -> Result<Vec<u8>> where T: Encodable {
+    let mut encoder = try!(encode(value));
+    let offset = match encoder.versionstamp {
+        Some(offset) => offset as u32,
+        None => return Err(error("no incomplete versionstamp")),
+    };
+    encoder.buf.extend((0..4).map(|n| (offset >> (n * 8)) as u8));
+    Ok(encoder.buf)
+}
+
+/// Unpacks a tuple-layer key into a value.
+///
+/// Returns an error if the key does not match the type of the value, or if the key contains
+/// elements after the value.
+pub fn unpack<T>(bytes: &[u8]) -> Result<T> where T: Decodable {
+    let mut decoder = Decoder { bytes: bytes, pos: 0, depth: 0, pending: None };
+    let value = try!(Decodable::decode(&mut decoder));
+    if decoder.pos != bytes.len() {
+        return Err(error("trailing elements after the unpacked value"));
+    }
+    Ok(value)
+}
+
+/// Returns the range of keys which begin with the packed prefix, excluding the prefix itself.
+///
+/// The range is `[start, end)`, where `start` is the prefix followed by `0x00`, and `end` the
+/// prefix followed by `0xFF`.
+pub fn range<T>(prefix: &T) -> Result<(Vec<u8>, Vec<u8>)> where T: Encodable {
+    let prefix = try!(pack(prefix));
+    let mut start = prefix.clone();
+    start.push(0x00);
+    let mut end = prefix;
+    end.push(0xFF);
+    Ok((start, end))
+}
+
+/// A byte string, which is packed with the byte string type code.
+///
+/// A plain `Vec<u8>` packs as a nested tuple of integers, one per byte, rather than as a byte
+/// string.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bytes(pub Vec<u8>);
+
+impl Encodable for Bytes {
+    fn encode<S>(&self, s: &mut S) -> ::std::result::Result<(), S::Error>
+    where S: rustc_serialize::Encoder {
+        s.emit_struct(BYTES_NAME, 1, |s| s.emit_struct_field("bytes", 0, |s| self.0.encode(s)))
+    }
+}
+
+impl Decodable for Bytes {
+    fn decode<D>(d: &mut D) -> ::std::result::Result<Bytes, D::Error>
+    where D: rustc_serialize::Decoder {
+        d.read_struct(BYTES_NAME, 1, |d| d.read_struct_field("bytes", 0, Decodable::decode))
+         .map(Bytes)
+    }
+}
+
+/// A 96-bit versionstamp: a 10 byte transaction version followed by a 2 byte user version.
+///
+/// An incomplete versionstamp has a placeholder transaction version of all `0xFF` bytes, which
+/// the database replaces with the commit version when the key is written with
+/// `SetVersionstampedKey`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Versionstamp {
+    bytes: [u8; 12],
+}
+
+impl Versionstamp {
+
+    /// Creates a complete versionstamp.
+    pub fn new(tr_version: [u8; 10], user_version: u16) -> Versionstamp {
+        let mut bytes = [0u8; 12];
+        for (dst, &src) in bytes.iter_mut().zip(tr_version.iter()) {
+            *dst = src;
+        }
+        bytes[10] = (user_version >> 8) as u8;
+        bytes[11] = user_version as u8;
+        Versionstamp { bytes: bytes }
+    }
+
+    /// Creates an incomplete versionstamp with the user version.
+    pub fn incomplete(user_version: u16) -> Versionstamp {
+        Versionstamp::new([0xFF; 10], user_version)
+    }
+
+    /// Returns whether the transaction version has been set.
+    pub fn is_complete(&self) -> bool {
+        self.bytes[..10].iter().any(|&b| b != 0xFF)
+    }
+
+    /// Returns the transaction version.
+    pub fn tr_version(&self) -> &[u8] {
+        &self.bytes[..10]
+    }
+
+    /// Returns the user version.
+    pub fn user_version(&self) -> u16 {
+        (self.bytes[10] as u16) << 8 | self.bytes[11] as u16
+    }
+}
+
+impl Encodable for Versionstamp {
+    fn encode<S>(&self, s: &mut S) -> ::std::result::Result<(), S::Error>
+    where S: rustc_serialize::Encoder {
+        s.emit_struct(VERSIONSTAMP_NAME, 12, |s| {
+            for (idx, &byte) in self.bytes.iter().enumerate() {
+                try!(s.emit_struct_field("", idx, |s| s.emit_u8(byte)));
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Decodable for Versionstamp {
+    fn decode<D>(d: &mut D) -> ::std::result::Result<Versionstamp, D::Error>
+    where D: rustc_serialize::Decoder {
+        d.read_struct(VERSIONSTAMP_NAME, 12, |d| {
+            let mut bytes = [0u8; 12];
+            for (idx, byte) in bytes.iter_mut().enumerate() {
+                *byte = try!(d.read_struct_field("", idx, |d| d.read_u8()));
+            }
+            Ok(Versionstamp { bytes: bytes })
+        })
+    }
+}
+
+fn encode<T>(value: &T) -> Result<Encoder> where T: Encodable {
+    let mut encoder = Encoder { buf: Vec::new(), depth: 0, raw: None, versionstamp: None };
+    try!(value.encode(&mut encoder));
+    Ok(encoder)
+}
+
+/// Returns the error for a value of the wrong shape under a reserved struct name.
+fn reserved(name: &str) -> Error {
+    error(&format!("`{}` is reserved for `Bytes` and `Versionstamp` values", name))
+}
+
+/// Returns the number of bytes following an integer type code.
+fn int_len(code: u8) -> usize {
+    (if code < INT_ZERO { INT_ZERO - code } else { code - INT_ZERO }) as usize
+}
+
+/// Appends the bytes with null bytes escaped, and a null terminator.
+fn write_escaped(buf: &mut Vec<u8>, bytes: &[u8]) {
+    for &byte in bytes.iter() {
+        buf.push(byte);
+        if byte == 0x00 { buf.push(0xFF); }
+    }
+    buf.push(0x00);
+}
+
+/// A tuple-layer encoder.
+struct Encoder {
+    buf: Vec<u8>,
+    /// The number of enclosing tuples. Elements of the top-level tuple have depth 1.
+    depth: usize,
+    /// The bytes of the `Bytes` or `Versionstamp` value being encoded.
+    raw: Option<Vec<u8>>,
+    /// The offset of the transaction version of the incomplete versionstamp.
+    versionstamp: Option<usize>,
+}
+
+impl Encoder {
+
+    fn write_int(&mut self, negative: bool, magnitude: u64) -> Result<()> {
+        let len = (0..8).take_while(|&n| magnitude >> (n * 8) != 0).count();
+        let val = if negative { !magnitude } else { magnitude };
+        if negative {
+            self.buf.push(INT_ZERO - len as u8);
+        } else {
+            self.buf.push(INT_ZERO + len as u8);
+        }
+        for n in (0..len).rev() {
+            self.buf.push((val >> (n * 8)) as u8);
+        }
+        Ok(())
+    }
+
+    fn write_unsigned(&mut self, v: u64) -> Result<()> {
+        self.write_int(false, v)
+    }
+
+    fn write_signed(&mut self, v: i64) -> Result<()> {
+        if v >= 0 {
+            self.write_int(false, v as u64)
+        } else if v == i64::MIN {
+            self.write_int(true, 1 << 63)
+        } else {
+            self.write_int(true, (-v) as u64)
+        }
+    }
+
+    fn write_nested<F>(&mut self, f: F) -> Result<()> where F: FnOnce(&mut Encoder) -> Result<()> {
+        if self.raw.is_some() {
+            return f(self);
+        }
+        let nested = self.depth > 0;
+        if nested { self.buf.push(NESTED); }
+        self.depth += 1;
+        try!(f(self));
+        self.depth -= 1;
+        if nested { self.buf.push(0x00); }
+        Ok(())
+    }
+}
+
+impl rustc_serialize::Encoder for Encoder {
+
+    type Error = Error;
+
+    fn emit_nil(&mut self) -> Result<()> { self.write_nested(|_| Ok(())) }
+
+    fn emit_u8(&mut self, v: u8) -> Result<()> {
+        match self.raw {
+            Some(ref mut raw) => { raw.push(v); Ok(()) },
+            None => self.write_unsigned(v as u64),
+        }
+    }
+    fn emit_u16(&mut self, v: u16) -> Result<()> { self.write_unsigned(v as u64) }
+    fn emit_u32(&mut self, v: u32) -> Result<()> { self.write_unsigned(v as u64) }
+    fn emit_u64(&mut self, v: u64) -> Result<()> { self.write_unsigned(v) }
+    fn emit_usize(&mut self, v: usize) -> Result<()> { self.write_unsigned(v as u64) }
+
+    fn emit_i8(&mut self, v: i8) -> Result<()> { self.write_signed(v as i64) }
+    fn emit_i16(&mut self, v: i16) -> Result<()> { self.write_signed(v as i64) }
+    fn emit_i32(&mut self, v: i32) -> Result<()> { self.write_signed(v as i64) }
+    fn emit_i64(&mut self, v: i64) -> Result<()> { self.write_signed(v) }
+    fn emit_isize(&mut self, v: isize) -> Result<()> { self.write_signed(v as i64) }
+
+    fn emit_bool(&mut self, v: bool) -> Result<()> {
+        self.buf.push(if v { TRUE } else { FALSE });
+        Ok(())
+    }
+
+    fn emit_f32(&mut self, v: f32) -> Result<()> {
+        let bits = unsafe { transmute::<f32, u32>(v) };
+        let bits = if bits >> 31 == 1 { !bits } else { bits ^ (1 << 31) };
+        self.buf.push(FLOAT);
+        self.buf.extend((0..4).rev().map(|n| (bits >> (n * 8)) as u8));
+        Ok(())
+    }
+
+    fn emit_f64(&mut self, v: f64) -> Result<()> {
+        let bits = unsafe { transmute::<f64, u64>(v) };
+        let bits = if bits >> 63 == 1 { !bits } else { bits ^ (1 << 63) };
+        self.buf.push(DOUBLE);
+        self.buf.extend((0..8).rev().map(|n| (bits >> (n * 8)) as u8));
+        Ok(())
+    }
+
+    fn emit_char(&mut self, v: char) -> Result<()> {
+        self.emit_str(&v.to_string())
+    }
+
+    fn emit_str(&mut self, v: &str) -> Result<()> {
+        self.buf.push(STRING);
+        write_escaped(&mut self.buf, v.as_bytes());
+        Ok(())
+    }
+
+    fn emit_enum<F>(&mut self, name: &str, _f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        Err(error(&format!("enum {} is not supported by the tuple layer", name)))
+    }
+    fn emit_enum_variant<F>(&mut self, _name: &str, _id: usize, _len: usize, _f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        Err(error("enums are not supported by the tuple layer"))
+    }
+    fn emit_enum_variant_arg<F>(&mut self, _idx: usize, _f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        Err(error("enums are not supported by the tuple layer"))
+    }
+    fn emit_enum_struct_variant<F>(&mut self,
+                                   _name: &str,
+                                   _id: usize,
+                                   _len: usize,
+                                   _f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        Err(error("enums are not supported by the tuple layer"))
+    }
+    fn emit_enum_struct_variant_field<F>(&mut self, _name: &str, _idx: usize, _f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        Err(error("enums are not supported by the tuple layer"))
+    }
+
+    fn emit_struct<F>(&mut self, name: &str, _len: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        if name == BYTES_NAME || name == VERSIONSTAMP_NAME {
+            if self.raw.is_some() {
+                return Err(reserved(name));
+            }
+            let len = self.buf.len();
+            self.raw = Some(Vec::new());
+            let result = f(self);
+            let raw = self.raw.take().unwrap();
+            try!(result);
+            // Any value but a `u8` is written straight to the buffer.
+            if self.buf.len() != len || (name == VERSIONSTAMP_NAME && raw.len() != 12) {
+                return Err(reserved(name));
+            }
+            if name == BYTES_NAME {
+                self.buf.push(BYTES);
+                write_escaped(&mut self.buf, &raw);
+            } else {
+                if raw[..10].iter().all(|&b| b == 0xFF) {
+                    if self.versionstamp.is_some() {
+                        return Err(error("multiple incomplete versionstamps"));
+                    }
+                    self.versionstamp = Some(self.buf.len() + 1);
+                }
+                self.buf.push(VERSIONSTAMP);
+                self.buf.extend(raw.into_iter());
+            }
+            Ok(())
+        } else {
+            self.write_nested(f)
+        }
+    }
+    fn emit_struct_field<F>(&mut self, _name: &str, _idx: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+
+    fn emit_tuple<F>(&mut self, _len: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        self.write_nested(f)
+    }
+    fn emit_tuple_arg<F>(&mut self, _idx: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+    fn emit_tuple_struct<F>(&mut self, name: &str, len: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        self.emit_struct(name, len, f)
+    }
+    fn emit_tuple_struct_arg<F>(&mut self, _idx: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+
+    fn emit_option<F>(&mut self, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+    fn emit_option_none(&mut self) -> Result<()> {
+        self.buf.push(NULL);
+        if self.depth > 1 { self.buf.push(0xFF); }
+        Ok(())
+    }
+    fn emit_option_some<F>(&mut self, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+
+    fn emit_seq<F>(&mut self, _len: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        self.write_nested(f)
+    }
+    fn emit_seq_elt<F>(&mut self, _idx: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+
+    fn emit_map<F>(&mut self, _len: usize, _f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        Err(error("maps are not supported by the tuple layer"))
+    }
+    fn emit_map_elt_key<F>(&mut self, _idx: usize, _f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        Err(error("maps are not supported by the tuple layer"))
+    }
+    fn emit_map_elt_val<F>(&mut self, _idx: usize, _f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        Err(error("maps are not supported by the tuple layer"))
+    }
+}
+
+/// A tuple-layer decoder.
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// The number of enclosing tuples. Elements of the top-level tuple have depth 1.
+    depth: usize,
+    /// The remaining bytes of the `Bytes` or `Versionstamp` value being decoded.
+    pending: Option<(Vec<u8>, usize)>,
+}
+
+impl<'a> Decoder<'a> {
+
+    fn peek(&self) -> Result<u8> {
+        self.bytes.get(self.pos).cloned().ok_or(Error::UnexpectedEof)
+    }
+
+    fn next(&mut self) -> Result<u8> {
+        let byte = try!(self.peek());
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn expect(&mut self, code: u8, ty: &str) -> Result<()> {
+        if try!(self.next()) == code {
+            Ok(())
+        } else {
+            self.pos -= 1;
+            Err(error(&format!("expected {} at byte {}", ty, self.pos)))
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.pos + len > self.bytes.len() {
+            return Err(Error::UnexpectedEof);
+        }
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Reads escaped bytes up to and including the null terminator.
+    fn read_escaped(&mut self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        loop {
+            match try!(self.next()) {
+                0x00 if self.bytes.get(self.pos) == Some(&0xFF) => {
+                    self.pos += 1;
+                    bytes.push(0x00);
+                },
+                0x00 => return Ok(bytes),
+                byte => bytes.push(byte),
+            }
+        }
+    }
+
+    /// Returns the offset of the end of the element at the offset.
+    fn element_end(&self, pos: usize, nested: bool) -> Result<usize> {
+        let code = match self.bytes.get(pos) {
+            Some(&code) => code,
+            None => return Err(Error::UnexpectedEof),
+        };
+        let end = match code {
+            NULL if nested => pos + 2,
+            NULL => pos + 1,
+            BYTES | STRING => {
+                let mut end = pos + 1;
+                loop {
+                    match self.bytes.get(end) {
+                        Some(&0x00) if self.bytes.get(end + 1) == Some(&0xFF) => end += 2,
+                        Some(&0x00) => break end + 1,
+                        Some(_) => end += 1,
+                        None => return Err(Error::UnexpectedEof),
+                    }
+                }
+            },
+            NESTED => {
+                let mut end = pos + 1;
+                loop {
+                    match self.bytes.get(end) {
+                        Some(&0x00) if self.bytes.get(end + 1) != Some(&0xFF) => break end + 1,
+                        Some(_) => end = try!(self.element_end(end, true)),
+                        None => return Err(Error::UnexpectedEof),
+                    }
+                }
+            },
+            0x0C...0x1C => pos + 1 + int_len(code),
+            FLOAT => pos + 5,
+            DOUBLE => pos + 9,
+            FALSE | TRUE => pos + 1,
+            UUID => pos + 17,
+            VERSIONSTAMP => pos + 13,
+            code => return Err(error(&format!("unknown type code 0x{:02x} at byte {}", code, pos))),
+        };
+        if end > self.bytes.len() { Err(Error::UnexpectedEof) } else { Ok(end) }
+    }
+
+    fn read_int(&mut self) -> Result<Int> {
+        let code = try!(self.next());
+        if code < 0x0C || code > 0x1C {
+            self.pos -= 1;
+            return Err(error(&format!("expected an integer at byte {}", self.pos)));
+        }
+        let len = int_len(code);
+        if len > 8 {
+            return Err(error("integer does not fit in 64 bits"));
+        }
+        let val = try!(self.take(len)).iter().fold(0u64, |val, &b| (val << 8) | b as u64);
+        if code >= INT_ZERO {
+            Ok(Int::Positive(val))
+        } else if len == 8 {
+            Ok(Int::Negative(!val))
+        } else {
+            Ok(Int::Negative(((1 << (len * 8)) - 1) ^ val))
+        }
+    }
+
+    fn read_unsigned(&mut self, max: u64) -> Result<u64> {
+        self.read_int().and_then(|int| int.unsigned(max))
+    }
+
+    fn read_signed(&mut self, min: i64, max: i64) -> Result<i64> {
+        self.read_int().and_then(|int| int.signed(min, max))
+    }
+
+    fn read_nested<T, F>(&mut self, f: F) -> Result<T>
+    where F: FnOnce(&mut Decoder<'a>) -> Result<T> {
+        if self.pending.is_some() {
+            return f(self);
+        }
+        let nested = self.depth > 0;
+        if nested { try!(self.expect(NESTED, "a nested tuple")); }
+        self.depth += 1;
+        let value = try!(f(self));
+        self.depth -= 1;
+        if nested { try!(self.expect(0x00, "the end of a nested tuple")); }
+        Ok(value)
+    }
+}
+
+impl<'a> rustc_serialize::Decoder for Decoder<'a> {
+
+    type Error = Error;
+
+    fn read_nil(&mut self) -> Result<()> { self.read_nested(|_| Ok(())) }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        if let Some((ref bytes, ref mut pos)) = self.pending {
+            *pos += 1;
+            return bytes.get(*pos - 1).cloned().ok_or(Error::UnexpectedEof);
+        }
+        self.read_unsigned(u8::MAX as u64).map(|v| v as u8)
+    }
+    fn read_u16(&mut self) -> Result<u16> { self.read_unsigned(u16::MAX as u64).map(|v| v as u16) }
+    fn read_u32(&mut self) -> Result<u32> { self.read_unsigned(u32::MAX as u64).map(|v| v as u32) }
+    fn read_u64(&mut self) -> Result<u64> { self.read_unsigned(!0) }
+    fn read_usize(&mut self) -> Result<usize> {
+        self.read_unsigned(!0usize as u64).map(|v| v as usize)
+    }
+
+    fn read_i8(&mut self) -> Result<i8> {
+        self.read_signed(i8::MIN as i64, i8::MAX as i64).map(|v| v as i8)
+    }
+    fn read_i16(&mut self) -> Result<i16> {
+        self.read_signed(i16::MIN as i64, i16::MAX as i64).map(|v| v as i16)
+    }
+    fn read_i32(&mut self) -> Result<i32> {
+        self.read_signed(i32::MIN as i64, i32::MAX as i64).map(|v| v as i32)
+    }
+    fn read_i64(&mut self) -> Result<i64> { self.read_signed(i64::MIN, i64::MAX) }
+    fn read_isize(&mut self) -> Result<isize> {
+        self.read_signed(::std::isize::MIN as i64, ::std::isize::MAX as i64).map(|v| v as isize)
+    }
+
+    fn read_bool(&mut self) -> Result<bool> {
+        match try!(self.next()) {
+            FALSE => Ok(false),
+            TRUE => Ok(true),
+            _ => {
+                self.pos -= 1;
+                Err(error(&format!("expected a boolean at byte {}", self.pos)))
+            },
+        }
+    }
+
+    fn read_f32(&mut self) -> Result<f32> {
+        try!(self.expect(FLOAT, "a float"));
+        let bits = try!(self.take(4)).iter().fold(0u32, |val, &b| (val << 8) | b as u32);
+        let bits = if bits >> 31 == 1 { bits ^ (1 << 31) } else { !bits };
+        Ok(unsafe { transmute::<u32, f32>(bits) })
+    }
+
+    fn read_f64(&mut self) -> Result<f64> {
+        try!(self.expect(DOUBLE, "a double"));
+        let bits = try!(self.take(8)).iter().fold(0u64, |val, &b| (val << 8) | b as u64);
+        let bits = if bits >> 63 == 1 { bits ^ (1 << 63) } else { !bits };
+        Ok(unsafe { transmute::<u64, f64>(bits) })
+    }
+
+    fn read_char(&mut self) -> Result<char> {
+        single_char(&try!(self.read_str()))
+    }
+
+    fn read_str(&mut self) -> Result<String> {
+        try!(self.expect(STRING, "a string"));
+        String::from_utf8(try!(self.read_escaped())).map_err(|_| Error::NotUtf8)
+    }
+
+    fn read_enum<T, F>(&mut self, name: &str, _f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        Err(error(&format!("enum {} is not supported by the tuple layer", name)))
+    }
+    fn read_enum_variant<T, F>(&mut self, _names: &[&str], _f: F) -> Result<T>
+            where F: FnMut(&mut Self, usize) -> Result<T> {
+        Err(error("enums are not supported by the tuple layer"))
+    }
+    fn read_enum_variant_arg<T, F>(&mut self, _idx: usize, _f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        Err(error("enums are not supported by the tuple layer"))
+    }
+    fn read_enum_struct_variant<T, F>(&mut self, _names: &[&str], _f: F) -> Result<T>
+            where F: FnMut(&mut Self, usize) -> Result<T> {
+        Err(error("enums are not supported by the tuple layer"))
+    }
+    fn read_enum_struct_variant_field<T, F>(&mut self, _name: &str, _idx: usize, _f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        Err(error("enums are not supported by the tuple layer"))
+    }
+
+    fn read_struct<T, F>(&mut self, name: &str, _len: usize, f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        if name == BYTES_NAME || name == VERSIONSTAMP_NAME {
+            if self.pending.is_some() {
+                return Err(reserved(name));
+            }
+            let bytes = if name == BYTES_NAME {
+                try!(self.expect(BYTES, "a byte string"));
+                try!(self.read_escaped())
+            } else {
+                try!(self.expect(VERSIONSTAMP, "a versionstamp"));
+                try!(self.take(12)).to_vec()
+            };
+            let (len, pos) = (bytes.len(), self.pos);
+            self.pending = Some((bytes, 0));
+            let result = f(self);
+            let read = self.pending.take().map_or(0, |(_, read)| read);
+            let value = try!(result);
+            // Any value but a `u8` is read past the pending bytes.
+            if self.pos != pos || read != len {
+                return Err(reserved(name));
+            }
+            Ok(value)
+        } else {
+            self.read_nested(f)
+        }
+    }
+    fn read_struct_field<T, F>(&mut self, _name: &str, _idx: usize, f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        f(self)
+    }
+
+    fn read_tuple<T, F>(&mut self, _len: usize, f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        self.read_nested(f)
+    }
+    fn read_tuple_arg<T, F>(&mut self, _idx: usize, f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        f(self)
+    }
+    fn read_tuple_struct<T, F>(&mut self, name: &str, len: usize, f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        self.read_struct(name, len, f)
+    }
+    fn read_tuple_struct_arg<T, F>(&mut self, _idx: usize, f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        f(self)
+    }
+
+    fn read_option<T, F>(&mut self, mut f: F) -> Result<T>
+            where F: FnMut(&mut Self, bool) -> Result<T> {
+        let nested = self.depth > 1;
+        match (self.bytes.get(self.pos), self.bytes.get(self.pos + 1)) {
+            (Some(&NULL), Some(&0xFF)) if nested => {
+                self.pos += 2;
+                f(self, false)
+            },
+            (Some(&NULL), _) if !nested => {
+                self.pos += 1;
+                f(self, false)
+            },
+            _ => f(self, true),
+        }
+    }
+
+    fn read_seq<T, F>(&mut self, f: F) -> Result<T>
+            where F: FnOnce(&mut Self, usize) -> Result<T> {
+        let pending = self.pending.as_ref().map(|&(ref bytes, pos)| bytes.len() - pos);
+        if let Some(len) = pending {
+            return f(self, len);
+        }
+        let nested = self.depth > 0;
+        let mut pos = self.pos + if nested { 1 } else { 0 };
+        let mut len = 0;
+        loop {
+            match self.bytes.get(pos) {
+                None if !nested => break,
+                Some(&0x00) if nested && self.bytes.get(pos + 1) != Some(&0xFF) => break,
+                _ => {
+                    pos = try!(self.element_end(pos, nested));
+                    len += 1;
+                },
+            }
+        }
+        self.read_nested(|decoder| f(decoder, len))
+    }
+    fn read_seq_elt<T, F>(&mut self, _idx: usize, f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        f(self)
+    }
+
+    fn read_map<T, F>(&mut self, _f: F) -> Result<T>
+            where F: FnOnce(&mut Self, usize) -> Result<T> {
+        Err(error("maps are not supported by the tuple layer"))
+    }
+    fn read_map_elt_key<T, F>(&mut self, _idx: usize, _f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        Err(error("maps are not supported by the tuple layer"))
+    }
+    fn read_map_elt_val<T, F>(&mut self, _idx: usize, _f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        Err(error("maps are not supported by the tuple layer"))
+    }
+
+    fn error(&mut self, err: &str) -> Error {
+        error(err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::{f32, f64, i64, u64};
+
+    use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
+
+    use super::{pack, pack_with_versionstamp, range, unpack, Bytes, Versionstamp};
+    use super::{BYTES_NAME, VERSIONSTAMP_NAME};
+
+    fn check<T>(value: T, expected: &[u8])
+    where T: Encodable + Decodable + PartialEq + ::std::fmt::Debug {
+        assert_eq!(expected, &pack(&value).unwrap()[..]);
+        assert_eq!(value, unpack(expected).unwrap());
+    }
+
+    /// Test vectors from the tuple layer specification, and the bindings' tuple tests.
+    #[test]
+    fn test_vectors() {
+        check((None::<u8>,), b"\x00");
+        check((Bytes(b"foo\x00bar".to_vec()),), b"\x01foo\x00\xffbar\x00");
+        check(("foo\x00bar".to_string(),), b"\x02foo\x00\xffbar\x00");
+        check(("F\u{d4}O\u{0}bar".to_string(),), b"\x02F\xc3\x94O\x00\xffbar\x00");
+        check(((Bytes(b"foo\x00bar".to_vec()), None::<u8>, ()),),
+              b"\x05\x01foo\x00\xffbar\x00\x00\xff\x05\x00\x00");
+        check((-5551212i64,), b"\x11\xab\x4b\x93");
+        check((0i64,), b"\x14");
+        check((1i64,), b"\x15\x01");
+        check((-1i64,), b"\x13\xfe");
+        check((255i64,), b"\x15\xff");
+        check((256i64,), b"\x16\x01\x00");
+        check((-255i64,), b"\x13\x00");
+        check((-256i64,), b"\x12\xfe\xff");
+        check((10000i64,), b"\x16\x27\x10");
+        check((-10000i64,), b"\x12\xd8\xef");
+        check((i64::MAX,), b"\x1c\x7f\xff\xff\xff\xff\xff\xff\xff");
+        check((i64::MIN,), b"\x0c\x7f\xff\xff\xff\xff\xff\xff\xff");
+        check((u64::MAX,), b"\x1c\xff\xff\xff\xff\xff\xff\xff\xff");
+        // 3.14 and -3.14.
+        check((f32::from_bits(0x4048f5c3),), b"\x20\xc0\x48\xf5\xc3");
+        check((f32::from_bits(0xc048f5c3),), b"\x20\x3f\xb7\x0a\x3c");
+        check((0.0f32,), b"\x20\x80\x00\x00\x00");
+        check((-42.0f64,), b"\x21\x3f\xba\xff\xff\xff\xff\xff\xff");
+        check((f64::INFINITY,), b"\x21\xff\xf0\x00\x00\x00\x00\x00\x00");
+        check((f32::NEG_INFINITY,), b"\x20\x00\x7f\xff\xff");
+        check((false, true), b"\x26\x27");
+        check((Versionstamp::new([0xAA; 10], 0x0102),),
+              b"\x33\xaa\xaa\xaa\xaa\xaa\xaa\xaa\xaa\xaa\xaa\x01\x02");
+    }
+
+    #[test]
+    fn test_nested() {
+        check(("a".to_string(), vec!(1u8, 2), vec!(Some(3i32), None)),
+              b"\x02a\x00\x05\x15\x01\x15\x02\x00\x05\x15\x03\x00\xff\x00");
+        check(vec!("a".to_string(), "b".to_string()), b"\x02a\x00\x02b\x00");
+        check(vec!(Vec::<u8>::new(), vec!(0)), b"\x05\x00\x05\x14\x00");
+        check(42u16, b"\x15\x2a");
+    }
+
+    #[test]
+    fn test_order() {
+        let keys = vec!(pack(&(None::<u8>,)).unwrap(),
+                        pack(&(Bytes(vec!(0xFF)),)).unwrap(),
+                        pack(&("",)).unwrap(),
+                        pack(&(-1000i64,)).unwrap(),
+                        pack(&(0i64,)).unwrap(),
+                        pack(&(1000i64,)).unwrap(),
+                        pack(&(1.0f32,)).unwrap(),
+                        pack(&(-1.0f64,)).unwrap(),
+                        pack(&(false,)).unwrap(),
+                        pack(&(true,)).unwrap());
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+    }
+
+    #[test]
+    fn test_range() {
+        let (start, end) = range(&("users", 3u8)).unwrap();
+        assert_eq!(b"\x02users\x00\x15\x03\x00".to_vec(), start);
+        assert_eq!(b"\x02users\x00\x15\x03\xff".to_vec(), end);
+        let key = pack(&("users", 3u8, "bob")).unwrap();
+        assert!(start < key && key < end);
+        assert!(pack(&("users", 4u8)).unwrap() >= end);
+    }
+
+    #[test]
+    fn test_versionstamp() {
+        let key = ("events", Versionstamp::incomplete(7));
+        assert!(pack(&key).is_err());
+        let expected = b"\x02events\x00\x33\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\
+                         \x00\x07\x09\x00\x00\x00";
+        assert_eq!(&expected[..], &pack_with_versionstamp(&key).unwrap()[..]);
+        assert!(pack_with_versionstamp(&("events",)).is_err());
+        assert!(pack_with_versionstamp(&(Versionstamp::incomplete(0), Versionstamp::incomplete(1)))
+                    .is_err());
+
+        let stamp = Versionstamp::new([1, 2, 3, 4, 5, 6, 7, 8, 9, 10], 7);
+        assert!(stamp.is_complete() && !Versionstamp::incomplete(7).is_complete());
+        assert_eq!(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10], stamp.tr_version());
+        assert_eq!(7, stamp.user_version());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(unpack::<(u8,)>(b"\x16\x01\x00").is_err());
+        assert!(unpack::<(u64,)>(b"\x13\xfe").is_err());
+        assert!(unpack::<(i8,)>(b"\x15\x80").is_err());
+        assert!(unpack::<(String,)>(b"\x15\x01").is_err());
+        assert!(unpack::<(String,)>(b"\x02foo").is_err());
+        assert!(unpack::<(u8,)>(b"\x15\x01\x15\x02").is_err());
+        assert!(unpack::<Vec<u8>>(b"\x15\x01\x99").is_err());
+    }
+
+    /// A value which emits an enum variant and a map element without the enclosing enum or map.
+    struct Unenclosed(bool);
+
+    impl Encodable for Unenclosed {
+        fn encode<S>(&self, s: &mut S) -> Result<(), S::Error> where S: Encoder {
+            if self.0 {
+                s.emit_enum_variant("A", 0, 0, |_| Ok(()))
+            } else {
+                s.emit_map_elt_key(0, |_| Ok(()))
+            }
+        }
+    }
+
+    #[test]
+    fn test_unenclosed() {
+        assert!(pack(&(Unenclosed(true),)).is_err());
+        assert!(pack(&(Unenclosed(false),)).is_err());
+    }
+
+    /// A value which encodes bytes and an optional string under a reserved struct name.
+    struct Impostor(&'static str, Vec<u8>, Option<&'static str>);
+
+    impl Encodable for Impostor {
+        fn encode<S>(&self, s: &mut S) -> Result<(), S::Error> where S: Encoder {
+            s.emit_struct(self.0, 2, |s| {
+                try!(s.emit_struct_field("bytes", 0, |s| self.1.encode(s)));
+                match self.2 {
+                    Some(string) => s.emit_struct_field("string", 1, |s| s.emit_str(string)),
+                    None => Ok(()),
+                }
+            })
+        }
+    }
+
+    /// A value which decodes a byte and a string under the reserved name of `Bytes`.
+    #[derive(Debug)]
+    struct ByteAndString(u8, String);
+
+    impl Decodable for ByteAndString {
+        fn decode<D>(d: &mut D) -> Result<ByteAndString, D::Error> where D: Decoder {
+            d.read_struct(BYTES_NAME, 2, |d| {
+                Ok(ByteAndString(try!(d.read_struct_field("byte", 0, |d| d.read_u8())),
+                                 try!(d.read_struct_field("string", 1, |d| d.read_str()))))
+            })
+        }
+    }
+
+    #[test]
+    fn test_reserved_names() {
+        assert_eq!(pack(&(Bytes(vec!(1, 2)),)).unwrap(),
+                   pack(&(Impostor(BYTES_NAME, vec!(1, 2), None),)).unwrap());
+        assert!(pack(&(Impostor(BYTES_NAME, vec!(1, 2), Some("a")),)).is_err());
+        assert!(pack(&(Impostor(VERSIONSTAMP_NAME, vec!(1, 2), None),)).is_err());
+        assert!(pack(&(Impostor(VERSIONSTAMP_NAME, vec!(0; 12), Some("a")),)).is_err());
+        assert!(pack(&(Impostor(VERSIONSTAMP_NAME, vec!(0; 12), None),)).is_ok());
+
+        assert!(unpack::<(ByteAndString,)>(b"\x01a\x00\x02b\x00").is_err());
+        assert!(unpack::<(Bytes,)>(b"\x01a\x00").is_ok());
+        assert!(unpack::<(Versionstamp,)>(b"\x01a\x00").is_err());
+    }
+}
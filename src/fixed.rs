@@ -0,0 +1,186 @@
+//! Fixed-width strings.
+
+use std::{cmp, fmt, result, str};
+use std::hash::{Hash, Hasher};
+
+use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
+
+use Error;
+use Result;
+
+/// A string which is encoded into exactly `N` bytes.
+///
+/// The width of the encoding is given by the byte array type of the string's buffer, so a
+/// `FixedStr<[u8; 16]>` always encodes to 16 bytes. This is useful for stores which require
+/// fixed-length keys, and for compatibility with SQL `CHAR(n)` columns.
+///
+/// ##### Encoding
+///
+/// The string is encoded as its UTF-8 bytes, padded to the full width with null bytes. For strings
+/// which fit within the width, the encoding sorts identically to the null-terminated string
+/// encoding. As with the null-terminated encoding, strings should not contain null bytes; trailing
+/// null bytes are removed when decoding.
+///
+/// #### Usage
+///
+/// ```
+/// # use bytekey::{encode, decode, FixedStr};
+/// let code = FixedStr::<[u8; 4]>::new("ab").unwrap();
+/// assert_eq!(vec!(0x61, 0x62, 0x00, 0x00), encode(&code).unwrap());
+/// assert_eq!("ab", decode::<FixedStr<[u8; 4]>>(encode(&code).unwrap()).unwrap().as_str());
+/// assert!(FixedStr::<[u8; 4]>::new("abcde").is_err());
+/// ```
+#[derive(Clone, Copy)]
+pub struct FixedStr<N> where N: Width {
+    buf: N,
+}
+
+/// A byte array type which can be used as the buffer of a `FixedStr`.
+///
+/// Implemented for byte arrays of 1 through 32 bytes, and of 48, 64, 128, 255, and 256 bytes.
+pub trait Width: Copy {
+
+    /// Returns a buffer filled with null bytes.
+    fn zeroed() -> Self;
+
+    /// Returns the contents of the buffer.
+    fn bytes(&self) -> &[u8];
+
+    /// Returns the mutable contents of the buffer.
+    fn bytes_mut(&mut self) -> &mut [u8];
+}
+
+macro_rules! width {
+    ($($len:expr),+) => { $(
+        impl Width for [u8; $len] {
+            fn zeroed() -> [u8; $len] { [0; $len] }
+            fn bytes(&self) -> &[u8] { &self[..] }
+            fn bytes_mut(&mut self) -> &mut [u8] { &mut self[..] }
+        }
+    )+ }
+}
+
+width!(1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25,
+       26, 27, 28, 29, 30, 31, 32, 48, 64, 128, 255, 256);
+
+impl<N> FixedStr<N> where N: Width {
+
+    /// Creates a new fixed-width string.
+    ///
+    /// Returns `Error::TooLong` if the UTF-8 representation of the string does not fit in the
+    /// width.
+    pub fn new(s: &str) -> Result<FixedStr<N>> {
+        let mut buf = N::zeroed();
+        let max = buf.bytes().len();
+        if s.len() > max {
+            return Err(Error::TooLong { len: s.len(), max: max });
+        }
+        for (dst, &src) in buf.bytes_mut().iter_mut().zip(s.as_bytes().iter()) {
+            *dst = src;
+        }
+        Ok(FixedStr { buf: buf })
+    }
+
+    /// Returns the string, without padding.
+    pub fn as_str(&self) -> &str {
+        str::from_utf8(unpadded(self.buf.bytes())).unwrap()
+    }
+}
+
+impl<N> Encodable for FixedStr<N> where N: Width {
+    fn encode<S>(&self, s: &mut S) -> result::Result<(), S::Error>
+    where S: Encoder {
+        for &byte in self.buf.bytes().iter() {
+            try!(s.emit_u8(byte));
+        }
+        Ok(())
+    }
+}
+
+impl<N> Decodable for FixedStr<N> where N: Width {
+    fn decode<D>(d: &mut D) -> result::Result<FixedStr<N>, D::Error>
+    where D: Decoder {
+        let mut buf = N::zeroed();
+        for byte in buf.bytes_mut().iter_mut() {
+            *byte = try!(d.read_u8());
+        }
+        match str::from_utf8(unpadded(buf.bytes())) {
+            Ok(_) => Ok(FixedStr { buf: buf }),
+            Err(_) => Err(d.error("byte stream did not contain valid utf8")),
+        }
+    }
+}
+
+/// Returns the bytes with trailing null padding removed.
+fn unpadded(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().rposition(|&b| b != 0).map_or(0, |idx| idx + 1);
+    &bytes[..len]
+}
+
+impl<N> fmt::Debug for FixedStr<N> where N: Width {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl<N> fmt::Display for FixedStr<N> where N: Width {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl<N> PartialEq for FixedStr<N> where N: Width {
+    fn eq(&self, other: &FixedStr<N>) -> bool { self.buf.bytes() == other.buf.bytes() }
+}
+
+impl<N> Eq for FixedStr<N> where N: Width { }
+
+impl<N> PartialOrd for FixedStr<N> where N: Width {
+    fn partial_cmp(&self, other: &FixedStr<N>) -> Option<cmp::Ordering> { Some(self.cmp(other)) }
+}
+
+impl<N> Ord for FixedStr<N> where N: Width {
+    fn cmp(&self, other: &FixedStr<N>) -> cmp::Ordering { self.buf.bytes().cmp(other.buf.bytes()) }
+}
+
+impl<N> Hash for FixedStr<N> where N: Width {
+    fn hash<H>(&self, state: &mut H) where H: Hasher { self.buf.bytes().hash(state) }
+}
+
+#[cfg(test)]
+mod test {
+
+    use {encode, decode, Error};
+    use super::FixedStr;
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!(vec!(0x00, 0x00, 0x00), encode(&FixedStr::<[u8; 3]>::new("").unwrap()).unwrap());
+        assert_eq!(vec!(0x66, 0x6F, 0x6F), encode(&FixedStr::<[u8; 3]>::new("foo").unwrap()).unwrap());
+        assert_eq!(256, encode(&FixedStr::<[u8; 256]>::new("foo").unwrap()).unwrap().len());
+    }
+
+    #[test]
+    fn test_too_long() {
+        match FixedStr::<[u8; 3]>::new("fooz") {
+            Err(Error::TooLong { len: 4, max: 3 }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        // Multi-byte characters count by their encoded length.
+        assert!(FixedStr::<[u8; 3]>::new("éé").is_err());
+    }
+
+    #[quickcheck]
+    fn check_fixed_str(a: String, b: String) -> bool {
+        match (FixedStr::<[u8; 16]>::new(&a), FixedStr::<[u8; 16]>::new(&b)) {
+            (Ok(fixed_a), Ok(fixed_b)) => {
+                if a.contains('\0') || b.contains('\0') { return true; }
+                encode(&a).unwrap().cmp(&encode(&b).unwrap())
+                    == encode(&fixed_a).unwrap().cmp(&encode(&fixed_b).unwrap())
+                    && fixed_a == decode(encode(&fixed_a).unwrap()).unwrap()
+                    && a == fixed_a.as_str()
+            },
+            _ => a.len() > 16 || b.len() > 16,
+        }
+    }
+}
@@ -0,0 +1,393 @@
+//! Conversion between dynamically typed values and JSON.
+
+use std::{f64, i64};
+use std::collections::BTreeMap;
+
+use rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+use rustc_serialize::json::Json;
+
+use {Error, Result, Schema, Type, Value};
+
+/// Converts a value of the schema to JSON.
+///
+/// The conversion is lossless, and `from_json` converts the JSON back to the same value:
+///
+/// * Integers are numbers, and booleans are booleans.
+/// * Floats are numbers, except for non-finite floats, which are the strings `"NaN"`, `"inf"`, and
+///   `"-inf"`.
+/// * Strings and characters are strings, and byte strings are base64 strings.
+/// * `None` is `null`, and `Some` is the value. If the value is itself an option, `Some` is a one
+///   element array, so that `Some(None)` is distinct from `None`.
+/// * Tuples and sequences are arrays, and structs are objects.
+/// * Enum variants without fields are the variant name. Variants with fields are an object with
+///   the variant name as the only key, and the field, or an array of the fields, as the value.
+///   The fields of a struct-like variant are an object.
+/// * `Desc` values are converted as the value.
+///
+/// Returns `Error::Schema` if the value does not match the schema.
+///
+/// #### Usage
+///
+/// ```
+/// # use bytekey::{to_json, Schema, Value};
+/// let schema = Schema::parse("struct Key { tenant: u32, name: str }").unwrap();
+/// let value = Value::Tuple(vec!(Value::U32(3), Value::Str("bob".to_string())));
+/// assert_eq!("{\"name\":\"bob\",\"tenant\":3}", to_json(&schema, &value).unwrap().to_string());
+/// ```
+pub fn to_json(schema: &Schema, value: &Value) -> Result<Json> {
+    Ok(match (schema, value) {
+        (&Schema::Scalar(ty), value) => try!(scalar_to_json(ty, value)),
+        (&Schema::Option(_), &Value::Option(None)) |
+        (&Schema::OptionLast(_), &Value::Option(None)) => Json::Null,
+        (&Schema::Option(ref schema), &Value::Option(Some(ref value))) |
+        (&Schema::OptionLast(ref schema), &Value::Option(Some(ref value))) => {
+            let json = try!(to_json(schema, value));
+            if is_option(schema) { Json::Array(vec!(json)) } else { json }
+        },
+        (&Schema::Desc(ref schema), value) => try!(to_json(schema, value)),
+        (&Schema::Tuple(ref schemas), &Value::Tuple(ref values)) if schemas.len() == values.len() => {
+            Json::Array(try!(list_to_json(schemas, values)))
+        },
+        (&Schema::Seq(ref schema), &Value::Seq(ref values)) => {
+            Json::Array(try!(values.iter().map(|value| to_json(schema, value)).collect()))
+        },
+        (&Schema::Struct(_, ref fields), &Value::Tuple(ref values)) if fields.len() == values.len() => {
+            let mut object = BTreeMap::new();
+            for (field, value) in fields.iter().zip(values.iter()) {
+                object.insert(field.0.clone(), try!(to_json(&field.1, value)));
+            }
+            Json::Object(object)
+        },
+        (&Schema::Enum(_, ref variants), &Value::Variant(tag, ref values)) => {
+            let variant = match variants.iter().find(|variant| variant.1 == tag) {
+                Some(variant) => variant,
+                None => return Err(Error::UnknownTag { tag: tag }),
+            };
+            let fields = match (variant.2.len(), values.len()) {
+                (0, 0) => return Ok(Json::String(variant.0.clone())),
+                (1, 1) => try!(to_json(&variant.2[0], &values[0])),
+                (len, n) if len == n => Json::Array(try!(list_to_json(&variant.2, values))),
+                _ => return Err(mismatch(schema, value)),
+            };
+            let mut object = BTreeMap::new();
+            object.insert(variant.0.clone(), fields);
+            Json::Object(object)
+        },
+        _ => return Err(mismatch(schema, value)),
+    })
+}
+
+/// Converts JSON to a value of the schema.
+///
+/// Accepts the JSON produced by `to_json`. Additionally, enum variants without fields may be given
+/// as an object with a `null` value, and floats as integers.
+///
+/// Returns `Error::Schema` if the JSON does not match the schema.
+///
+/// #### Usage
+///
+/// ```
+/// # extern crate rustc_serialize;
+/// # extern crate bytekey;
+/// # use bytekey::{from_json, Schema, Value};
+/// # use rustc_serialize::json::Json;
+/// # fn main() {
+/// let schema = Schema::parse("enum Key { User(u32), Group { id: u32 } }").unwrap();
+/// let json = Json::from_str("{\"Group\": {\"id\": 7}}").unwrap();
+/// assert_eq!(Value::Variant(1, vec!(Value::Tuple(vec!(Value::U32(7))))),
+///            from_json(&schema, &json).unwrap());
+/// # }
+/// ```
+pub fn from_json(schema: &Schema, json: &Json) -> Result<Value> {
+    Ok(match (schema, json) {
+        (&Schema::Scalar(ty), json) => try!(scalar_from_json(ty, json)),
+        (&Schema::Option(_), &Json::Null) |
+        (&Schema::OptionLast(_), &Json::Null) => Value::Option(None),
+        (&Schema::Option(ref schema), json) | (&Schema::OptionLast(ref schema), json) => {
+            let json = match *json {
+                Json::Array(ref items) if is_option(schema) && items.len() == 1 => &items[0],
+                _ if is_option(schema) => return Err(json_mismatch(schema, json)),
+                ref json => json,
+            };
+            Value::Option(Some(Box::new(try!(from_json(schema, json)))))
+        },
+        (&Schema::Desc(ref schema), json) => try!(from_json(schema, json)),
+        (&Schema::Tuple(ref schemas), &Json::Array(ref items)) if schemas.len() == items.len() => {
+            Value::Tuple(try!(list_from_json(schemas, items)))
+        },
+        (&Schema::Seq(ref schema), &Json::Array(ref items)) => {
+            Value::Seq(try!(items.iter().map(|item| from_json(schema, item)).collect()))
+        },
+        (&Schema::Struct(ref name, ref fields), &Json::Object(ref object)) => {
+            if let Some(key) = object.keys().find(|key| fields.iter().all(|field| &field.0 != *key)) {
+                return Err(Error::Schema(format!("unknown field `{}` of struct {}", key, name)));
+            }
+            let mut values = Vec::with_capacity(fields.len());
+            for field in fields.iter() {
+                match object.get(&field.0) {
+                    Some(json) => values.push(try!(from_json(&field.1, json))),
+                    None => {
+                        return Err(Error::Schema(format!("missing field `{}` of struct {}",
+                                                         field.0, name)));
+                    },
+                }
+            }
+            Value::Tuple(values)
+        },
+        (&Schema::Enum(ref name, ref variants), json) => {
+            let (variant_name, fields) = match *json {
+                Json::String(ref variant_name) => (variant_name, None),
+                Json::Object(ref object) if object.len() == 1 => {
+                    let (variant_name, fields) = object.iter().next().unwrap();
+                    (variant_name, Some(fields))
+                },
+                _ => return Err(json_mismatch(schema, json)),
+            };
+            let variant = match variants.iter().find(|variant| &variant.0 == variant_name) {
+                Some(variant) => variant,
+                None => {
+                    return Err(Error::Schema(format!("unknown variant `{}` of enum {}",
+                                                     variant_name, name)));
+                },
+            };
+            let values = match (variant.2.len(), fields) {
+                (0, None) | (0, Some(&Json::Null)) => Vec::new(),
+                (1, Some(json)) => vec!(try!(from_json(&variant.2[0], json))),
+                (len, Some(&Json::Array(ref items))) if len > 1 && len == items.len() => {
+                    try!(list_from_json(&variant.2, items))
+                },
+                _ => {
+                    return Err(Error::Schema(format!("invalid fields for variant `{}` of enum {}",
+                                                     variant_name, name)));
+                },
+            };
+            Value::Variant(variant.1, values)
+        },
+        _ => return Err(json_mismatch(schema, json)),
+    })
+}
+
+fn scalar_to_json(ty: Type, value: &Value) -> Result<Json> {
+    Ok(match (ty, value) {
+        (Type::Bool, &Value::Bool(v)) => Json::Boolean(v),
+        (Type::U8, &Value::U8(v)) => Json::U64(v as u64),
+        (Type::U16, &Value::U16(v)) => Json::U64(v as u64),
+        (Type::U32, &Value::U32(v)) => Json::U64(v as u64),
+        (Type::U64, &Value::U64(v)) => Json::U64(v),
+        (Type::Usize, &Value::Usize(v)) => Json::U64(v as u64),
+        (Type::I8, &Value::I8(v)) => Json::I64(v as i64),
+        (Type::I16, &Value::I16(v)) => Json::I64(v as i64),
+        (Type::I32, &Value::I32(v)) => Json::I64(v as i64),
+        (Type::I64, &Value::I64(v)) => Json::I64(v),
+        (Type::Isize, &Value::Isize(v)) => Json::I64(v as i64),
+        (Type::F32, &Value::F32(v)) => float_to_json(v as f64),
+        (Type::F64, &Value::F64(v)) => float_to_json(v),
+        (Type::Char, &Value::Char(v)) => Json::String(v.to_string()),
+        (Type::Str, &Value::Str(ref v)) => Json::String(v.clone()),
+        (Type::Bytes, &Value::Bytes(ref v)) => Json::String(v.to_base64(STANDARD)),
+        (ty, value) => return Err(mismatch(&Schema::Scalar(ty), value)),
+    })
+}
+
+fn scalar_from_json(ty: Type, json: &Json) -> Result<Value> {
+    let value = match (ty, json) {
+        (Type::Bool, &Json::Boolean(v)) => Some(Value::Bool(v)),
+        (Type::F32, json) => float_from_json(json).map(|v| Value::F32(v as f32)),
+        (Type::F64, json) => float_from_json(json).map(Value::F64),
+        (Type::Char, &Json::String(ref v)) if v.chars().count() == 1 => {
+            v.chars().next().map(Value::Char)
+        },
+        (Type::Str, &Json::String(ref v)) => Some(Value::Str(v.clone())),
+        (Type::Bytes, &Json::String(ref v)) => {
+            let bytes = try!(v.from_base64().map_err(|err| {
+                Error::Schema(format!("invalid base64 byte string: {}", err))
+            }));
+            Some(Value::Bytes(bytes))
+        },
+        (ty, json) => int_from_json(ty, json),
+    };
+    value.ok_or_else(|| json_mismatch(&Schema::Scalar(ty), json))
+}
+
+fn float_to_json(v: f64) -> Json {
+    if v.is_nan() {
+        Json::String("NaN".to_string())
+    } else if v.is_infinite() {
+        Json::String(if v > 0.0 { "inf" } else { "-inf" }.to_string())
+    } else {
+        Json::F64(v)
+    }
+}
+
+fn float_from_json(json: &Json) -> Option<f64> {
+    match *json {
+        Json::String(ref v) if v == "NaN" => Some(f64::NAN),
+        Json::String(ref v) if v == "inf" => Some(f64::INFINITY),
+        Json::String(ref v) if v == "-inf" => Some(f64::NEG_INFINITY),
+        ref json => json.as_f64(),
+    }
+}
+
+/// Converts a JSON number to an integer value of the type, if it is in range.
+fn int_from_json(ty: Type, json: &Json) -> Option<Value> {
+    let (unsigned, signed) = match *json {
+        Json::U64(v) => (Some(v), if v <= i64::MAX as u64 { Some(v as i64) } else { None }),
+        Json::I64(v) => (if v >= 0 { Some(v as u64) } else { None }, Some(v)),
+        _ => return None,
+    };
+    macro_rules! unsigned {
+        ($variant:ident, $ty:ident) => {
+            unsigned.and_then(|v| {
+                if v <= ::std::$ty::MAX as u64 { Some(Value::$variant(v as $ty)) } else { None }
+            })
+        }
+    }
+    macro_rules! signed {
+        ($variant:ident, $ty:ident) => {
+            signed.and_then(|v| {
+                if v >= ::std::$ty::MIN as i64 && v <= ::std::$ty::MAX as i64 {
+                    Some(Value::$variant(v as $ty))
+                } else {
+                    None
+                }
+            })
+        }
+    }
+    match ty {
+        Type::U8 => unsigned!(U8, u8),
+        Type::U16 => unsigned!(U16, u16),
+        Type::U32 => unsigned!(U32, u32),
+        Type::U64 => unsigned!(U64, u64),
+        Type::Usize => unsigned!(Usize, usize),
+        Type::I8 => signed!(I8, i8),
+        Type::I16 => signed!(I16, i16),
+        Type::I32 => signed!(I32, i32),
+        Type::I64 => signed!(I64, i64),
+        Type::Isize => signed!(Isize, isize),
+        _ => None,
+    }
+}
+
+fn list_to_json(schemas: &[Schema], values: &[Value]) -> Result<Vec<Json>> {
+    schemas.iter().zip(values.iter()).map(|(schema, value)| to_json(schema, value)).collect()
+}
+
+fn list_from_json(schemas: &[Schema], items: &[Json]) -> Result<Vec<Value>> {
+    schemas.iter().zip(items.iter()).map(|(schema, item)| from_json(schema, item)).collect()
+}
+
+/// Returns whether JSON `null` is a value of the schema.
+fn is_option(schema: &Schema) -> bool {
+    match *schema {
+        Schema::Option(_) | Schema::OptionLast(_) => true,
+        Schema::Desc(ref schema) => is_option(schema),
+        _ => false,
+    }
+}
+
+fn mismatch(schema: &Schema, value: &Value) -> Error {
+    Error::Schema(format!("expected a value of schema {:?}, found {:?}", schema, value))
+}
+
+fn json_mismatch(schema: &Schema, json: &Json) -> Error {
+    Error::Schema(format!("expected a value of schema {:?}, found {}", schema, json))
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::{f32, f64, u64};
+
+    use rustc_serialize::json::Json;
+
+    use {decode_json, encode_json, from_json, to_json, Schema, Value};
+
+    fn check(schema: &Schema, value: Value, json: &str) {
+        let encoded = to_json(schema, &value).unwrap();
+        assert_eq!(json, encoded.to_string());
+        assert_eq!(value, from_json(schema, &Json::from_str(json).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_scalars() {
+        let schema = Schema::parse("struct S(u64, i64, f32, f64, char, str, bytes, bool);").unwrap();
+        check(&schema,
+              Value::Tuple(vec!(Value::U64(u64::MAX), Value::I64(-5), Value::F32(1.5),
+                                Value::F64(-0.25), Value::Char('"'),
+                                Value::Str("tab\t\"quote\" \u{e9}".to_string()),
+                                Value::Bytes(vec!(0, 1, 0xFF)), Value::Bool(true))),
+              "[18446744073709551615,-5,1.5,-0.25,\"\\\"\",\
+               \"tab\\t\\\"quote\\\" \u{e9}\",\"AAH/\",true]");
+
+        let schema = Schema::parse("struct S(f32, f64);").unwrap();
+        check(&schema,
+              Value::Tuple(vec!(Value::F32(f32::INFINITY), Value::F64(f64::NEG_INFINITY))),
+              "[\"inf\",\"-inf\"]");
+        match from_json(&schema, &Json::from_str("[\"NaN\", 0]").unwrap()).unwrap() {
+            Value::Tuple(ref values) => match values[0] {
+                Value::F32(v) => assert!(v.is_nan()),
+                ref value => panic!("unexpected value: {:?}", value),
+            },
+            value => panic!("unexpected value: {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_struct_and_enum() {
+        let schema = Schema::parse("
+            struct Id(u8, u8);
+            enum Kind { User(u32), Group { id: u32, name: str } = 5, Pair(str, Id), Any }
+            struct Key { tenant: u32 desc, kind: Kind, parent: option<option<u8>> }
+        ").unwrap();
+        let key = |kind: Value, parent: Value| {
+            Value::Tuple(vec!(Value::U32(3), kind, parent))
+        };
+        let some = |value: Value| Value::Option(Some(Box::new(value)));
+
+        check(&schema,
+              key(Value::Variant(0, vec!(Value::U32(7))), Value::Option(None)),
+              "{\"kind\":{\"User\":7},\"parent\":null,\"tenant\":3}");
+        check(&schema,
+              key(Value::Variant(5, vec!(Value::Tuple(vec!(Value::U32(7),
+                                                         Value::Str("bob".to_string()))))),
+                  some(Value::Option(None))),
+              "{\"kind\":{\"Group\":{\"id\":7,\"name\":\"bob\"}},\"parent\":[null],\"tenant\":3}");
+        check(&schema,
+              key(Value::Variant(2, vec!(Value::Str("a".to_string()),
+                                         Value::Tuple(vec!(Value::U8(1), Value::U8(2))))),
+                  some(some(Value::U8(9)))),
+              "{\"kind\":{\"Pair\":[\"a\",[1,2]]},\"parent\":[9],\"tenant\":3}");
+        check(&schema,
+              key(Value::Variant(3, vec!()), Value::Option(None)),
+              "{\"kind\":\"Any\",\"parent\":null,\"tenant\":3}");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let schema = Schema::parse("struct Key { tenant: u32, name: str desc, data: seq<bytes> }")
+                         .unwrap();
+        let json = Json::from_str("{\"tenant\": 3, \"name\": \"bob\", \"data\": [\"AA==\", \"\"]}")
+                       .unwrap();
+        let bytes = encode_json(&schema, &json).unwrap();
+        assert_eq!(json, decode_json(&schema, &bytes).unwrap());
+    }
+
+    #[test]
+    fn test_errors() {
+        let schema = Schema::parse("enum Kind { A, B(u8) } struct Key { tenant: u8, kind: Kind }")
+                         .unwrap();
+        let errors = [
+            "{\"tenant\": 256, \"kind\": \"A\"}",
+            "{\"tenant\": -1, \"kind\": \"A\"}",
+            "{\"tenant\": 1}",
+            "{\"tenant\": 1, \"kind\": \"A\", \"extra\": 0}",
+            "{\"tenant\": 1, \"kind\": \"C\"}",
+            "{\"tenant\": 1, \"kind\": \"B\"}",
+            "{\"tenant\": 1, \"kind\": {\"A\": 1}}",
+            "[1, \"A\"]",
+        ];
+        for json in errors.iter() {
+            assert!(from_json(&schema, &Json::from_str(json).unwrap()).is_err(), "{}", json);
+        }
+    }
+}
@@ -0,0 +1,569 @@
+//! Native key encoding traits.
+//!
+//! `KeyEncode` and `KeyDecode` encode values directly with an `Encoder` and `Decoder`, bypassing
+//! the generic `rustc-serialize` machinery. The encoded format is identical to the format produced
+//! by `encode` and `decode`, so the two can be mixed freely.
+
+use std::io::{Read, Write};
+use std::result;
+
+use rustc_serialize::{Decodable, Encodable};
+use rustc_serialize::Decoder as RustcDecoder;
+use rustc_serialize::Encoder as RustcEncoder;
+
+use {Array, Decoder, Encoder, Error, FixedStr, Natural, Result, Width};
+#[cfg(feature = "latin1-collation")] use Latin1Collated;
+
+/// A value which can be encoded into a key.
+pub trait KeyEncode {
+
+    /// Encodes the value with the encoder.
+    fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write;
+}
+
+/// A value which can be decoded from a key.
+pub trait KeyDecode: Sized {
+
+    /// Decodes a value from the decoder.
+    fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<Self> where R: Read;
+}
+
+/// An adapter which encodes and decodes a `rustc-serialize` type as a key.
+///
+/// #### Usage
+///
+/// ```
+/// # extern crate bytekey;
+/// # use bytekey::{encode, encode_key, Rustc};
+/// # fn main() {
+/// let key = (1u32, "foo".to_string());
+/// assert_eq!(encode(&key).unwrap(), encode_key(&Rustc(&key)).unwrap());
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Rustc<T>(pub T);
+
+impl<T> KeyEncode for Rustc<T> where T: Encodable {
+    fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+        self.0.encode(encoder)
+    }
+}
+
+impl<T> KeyDecode for Rustc<T> where T: Decodable {
+    fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<Rustc<T>> where R: Read {
+        Decodable::decode(decoder).map(Rustc)
+    }
+}
+
+macro_rules! primitive {
+    ($($ty:ty, $emit:ident, $read:ident;)+) => { $(
+        impl KeyEncode for $ty {
+            fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+                encoder.$emit(*self)
+            }
+        }
+
+        impl KeyDecode for $ty {
+            fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<$ty> where R: Read {
+                decoder.$read()
+            }
+        }
+    )+ }
+}
+
+primitive! {
+    u8, emit_u8, read_u8;
+    u16, emit_u16, read_u16;
+    u32, emit_u32, read_u32;
+    u64, emit_u64, read_u64;
+    usize, emit_usize, read_usize;
+    i8, emit_i8, read_i8;
+    i16, emit_i16, read_i16;
+    i32, emit_i32, read_i32;
+    i64, emit_i64, read_i64;
+    isize, emit_isize, read_isize;
+    f32, emit_f32, read_f32;
+    f64, emit_f64, read_f64;
+    bool, emit_bool, read_bool;
+    char, emit_char, read_char;
+}
+
+impl KeyEncode for () {
+    fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+        encoder.emit_nil()
+    }
+}
+
+impl KeyDecode for () {
+    fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<()> where R: Read {
+        decoder.read_nil()
+    }
+}
+
+impl KeyEncode for str {
+    fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+        encoder.emit_str(self)
+    }
+}
+
+impl KeyEncode for String {
+    fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+        encoder.emit_str(self)
+    }
+}
+
+impl KeyDecode for String {
+    fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<String> where R: Read {
+        decoder.read_str()
+    }
+}
+
+impl<T> KeyEncode for Option<T> where T: KeyEncode {
+    fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+        match *self {
+            None => encoder.emit_option_none(),
+            Some(ref value) => encoder.emit_option_some(|encoder| value.encode_key(encoder)),
+        }
+    }
+}
+
+impl<T> KeyDecode for Option<T> where T: KeyDecode {
+    fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<Option<T>> where R: Read {
+        decoder.read_option(|decoder, is_some| {
+            if is_some { T::decode_key(decoder).map(Some) } else { Ok(None) }
+        })
+    }
+}
+
+impl<'a, T: ?Sized> KeyEncode for &'a T where T: KeyEncode {
+    fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+        (**self).encode_key(encoder)
+    }
+}
+
+impl<T: ?Sized> KeyEncode for Box<T> where T: KeyEncode {
+    fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+        (**self).encode_key(encoder)
+    }
+}
+
+impl<T> KeyDecode for Box<T> where T: KeyDecode {
+    fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<Box<T>> where R: Read {
+        T::decode_key(decoder).map(Box::new)
+    }
+}
+
+macro_rules! tuple {
+    () => ();
+    ($first:ident, $($rest:ident,)*) => {
+        impl<$first, $($rest),*> KeyEncode for ($first, $($rest,)*)
+        where $first: KeyEncode, $($rest: KeyEncode),* {
+            #[allow(non_snake_case)]
+            fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+                let (ref $first, $(ref $rest,)*) = *self;
+                try!($first.encode_key(encoder));
+                $(try!($rest.encode_key(encoder));)*
+                Ok(())
+            }
+        }
+
+        impl<$first, $($rest),*> KeyDecode for ($first, $($rest,)*)
+        where $first: KeyDecode, $($rest: KeyDecode),* {
+            fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<($first, $($rest,)*)>
+            where R: Read {
+                Ok((try!($first::decode_key(decoder)), $(try!($rest::decode_key(decoder)),)*))
+            }
+        }
+
+        tuple!($($rest,)*);
+    }
+}
+
+tuple!(T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11,);
+
+/// Fixed-size arrays are encoded as the concatenation of their elements, like tuples.
+macro_rules! array {
+    () => ();
+    ($len:expr, $($idx:expr,)*) => {
+        impl<T> KeyEncode for [T; $len] where T: KeyEncode {
+            fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+                for elt in self.iter() {
+                    try!(elt.encode_key(encoder));
+                }
+                Ok(())
+            }
+        }
+
+        impl<T> KeyDecode for [T; $len] where T: KeyDecode {
+            #[allow(unused_variables)]
+            fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<[T; $len]> where R: Read {
+                Ok([$({ let _ = $idx; try!(T::decode_key(decoder)) },)*])
+            }
+        }
+
+        array!($($idx,)*);
+    }
+}
+
+array!(32, 31, 30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 18, 17,
+       16, 15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,);
+
+impl<A> KeyEncode for Array<A> where A: KeyEncode {
+    fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+        self.0.encode_key(encoder)
+    }
+}
+
+impl<A> KeyDecode for Array<A> where A: KeyDecode {
+    fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<Array<A>> where R: Read {
+        A::decode_key(decoder).map(Array)
+    }
+}
+
+impl<S> KeyEncode for Natural<S> where S: AsRef<str> {
+    fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+        self.encode(encoder)
+    }
+}
+
+impl KeyDecode for Natural<String> {
+    fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<Natural<String>> where R: Read {
+        Decodable::decode(decoder)
+    }
+}
+
+impl<N> KeyEncode for FixedStr<N> where N: Width {
+    fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+        self.encode(encoder)
+    }
+}
+
+impl<N> KeyDecode for FixedStr<N> where N: Width {
+    fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<FixedStr<N>> where R: Read {
+        Decodable::decode(decoder)
+    }
+}
+
+/// Implements the key traits for types which implement the `rustc-serialize` traits.
+#[cfg(feature = "latin1-collation")]
+macro_rules! via_rustc {
+    ($($ty:ty),+) => { $(
+        impl KeyEncode for $ty {
+            fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+                self.encode(encoder)
+            }
+        }
+
+        impl KeyDecode for $ty {
+            fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<$ty> where R: Read {
+                Decodable::decode(decoder)
+            }
+        }
+    )+ }
+}
+
+#[cfg(feature = "latin1-collation")] via_rustc!(Latin1Collated);
+
+/// A wrapper which encodes a value in descending order.
+///
+/// See `Encoder::emit_desc`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Desc<T>(pub T);
+
+impl<T> KeyEncode for Desc<T> where T: KeyEncode {
+    fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+        encoder.emit_desc(|encoder| self.0.encode_key(encoder))
+    }
+}
+
+impl<T> KeyDecode for Desc<T> where T: KeyDecode {
+    fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<Desc<T>> where R: Read {
+        decoder.read_desc(|decoder| T::decode_key(decoder)).map(Desc)
+    }
+}
+
+/// A wrapper which variable-length encodes an integer.
+///
+/// Unsigned integers are encoded with `Encoder::emit_var_u64`, and signed integers with
+/// `Encoder::emit_var_i64`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VarInt<T>(pub T);
+
+macro_rules! varint {
+    ($($ty:ident, $emit:ident, $read:ident, $wide:ty;)+) => { $(
+        impl KeyEncode for VarInt<$ty> {
+            fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+                encoder.$emit(self.0 as $wide)
+            }
+        }
+
+        impl<'a> KeyEncode for VarInt<&'a $ty> {
+            fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+                encoder.$emit(*self.0 as $wide)
+            }
+        }
+
+        impl KeyDecode for VarInt<$ty> {
+            #[allow(unused_comparisons)]
+            fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<VarInt<$ty>> where R: Read {
+                let val = try!(decoder.$read());
+                if val < ::std::$ty::MIN as $wide || val > ::std::$ty::MAX as $wide {
+                    return Err(decoder.error("variable-length integer out of range"));
+                }
+                Ok(VarInt(val as $ty))
+            }
+        }
+    )+ }
+}
+
+varint! {
+    u8, emit_var_u64, read_var_u64, u64;
+    u16, emit_var_u64, read_var_u64, u64;
+    u32, emit_var_u64, read_var_u64, u64;
+    u64, emit_var_u64, read_var_u64, u64;
+    usize, emit_var_u64, read_var_u64, u64;
+    i8, emit_var_i64, read_var_i64, i64;
+    i16, emit_var_i64, read_var_i64, i64;
+    i32, emit_var_i64, read_var_i64, i64;
+    i64, emit_var_i64, read_var_i64, i64;
+    isize, emit_var_i64, read_var_i64, i64;
+}
+
+/// A wrapper which encodes a string or byte string with null bytes escaped.
+///
+/// See `Encoder::emit_escaped`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Escaped<T>(pub T);
+
+impl<T> KeyEncode for Escaped<T> where T: AsRef<[u8]> {
+    fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+        encoder.emit_escaped(self.0.as_ref())
+    }
+}
+
+impl KeyDecode for Escaped<Vec<u8>> {
+    fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<Escaped<Vec<u8>>> where R: Read {
+        decoder.read_escaped().map(Escaped)
+    }
+}
+
+impl KeyDecode for Escaped<String> {
+    fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<Escaped<String>> where R: Read {
+        let bytes = try!(decoder.read_escaped());
+        String::from_utf8(bytes).map(Escaped).map_err(|_| Error::NotUtf8)
+    }
+}
+
+/// A wrapper which encodes an optional value with `None` sorting after all `Some` values.
+///
+/// The marker bytes of `Option` are swapped: `None` is encoded as `1`, and `Some` as `0` followed
+/// by the value. Wrapped in `Desc`, this sorts like SQL `DESC NULLS FIRST`. `OptionLast` always
+/// sorts `None` last, regardless of `Encoder::set_nulls_last`.
+///
+/// #### Usage
+///
+/// ```
+/// # use bytekey::{encode, decode, OptionLast};
+/// assert!(encode(&OptionLast(Some(42u32))).unwrap() < encode(&OptionLast::<u32>(None)).unwrap());
+///
+/// let value: Option<u32> = decode::<OptionLast<u32>>(encode(&OptionLast(Some(42u32))).unwrap())
+///     .unwrap()
+///     .into();
+/// assert_eq!(Some(42), value);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct OptionLast<T>(pub Option<T>);
+
+impl<T> From<Option<T>> for OptionLast<T> {
+    fn from(option: Option<T>) -> OptionLast<T> { OptionLast(option) }
+}
+
+impl<T> From<OptionLast<T>> for Option<T> {
+    fn from(option: OptionLast<T>) -> Option<T> { option.0 }
+}
+
+impl<T> Encodable for OptionLast<T> where T: Encodable {
+    fn encode<S>(&self, s: &mut S) -> result::Result<(), S::Error> where S: RustcEncoder {
+        match self.0 {
+            None => s.emit_bool(true),
+            Some(ref value) => {
+                try!(s.emit_bool(false));
+                value.encode(s)
+            },
+        }
+    }
+}
+
+impl<T> Decodable for OptionLast<T> where T: Decodable {
+    fn decode<D>(d: &mut D) -> result::Result<OptionLast<T>, D::Error> where D: RustcDecoder {
+        if try!(d.read_bool()) {
+            Ok(OptionLast(None))
+        } else {
+            Decodable::decode(d).map(|value| OptionLast(Some(value)))
+        }
+    }
+}
+
+impl<T> KeyEncode for OptionLast<T> where T: KeyEncode {
+    fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+        match self.0 {
+            None => encoder.emit_bool(true),
+            Some(ref value) => {
+                try!(encoder.emit_bool(false));
+                value.encode_key(encoder)
+            },
+        }
+    }
+}
+
+impl<T> KeyDecode for OptionLast<T> where T: KeyDecode {
+    fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<OptionLast<T>> where R: Read {
+        if try!(decoder.read_bool()) {
+            Ok(OptionLast(None))
+        } else {
+            T::decode_key(decoder).map(|value| OptionLast(Some(value)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use quickcheck::TestResult;
+    use rustc_serialize::{Decodable, Encodable};
+
+    use {encode, decode, encode_key, decode_key, Array, Decoder, Desc, Encoder, Escaped, OptionLast,
+         Rustc, VarInt};
+    use encoder::test::{TestStruct, TestEnum};
+
+    #[quickcheck]
+    fn check_primitives(a: (u8, u16, u32, u64), b: (usize, i8, i16, i32),
+                        c: (i64, isize, f32, f64), d: (bool, char)) -> TestResult {
+        if c.2.is_nan() || c.3.is_nan() { return TestResult::discard() }
+        let value = (a, b, c, d, ());
+        TestResult::from_bool(encode(&value).unwrap() == encode_key(&value).unwrap()
+            && value == decode_key(&encode_key(&value).unwrap()).unwrap())
+    }
+
+    #[quickcheck]
+    fn check_string(val: Option<String>) -> TestResult {
+        if val.as_ref().map_or(false, |s| s.contains('\0')) { return TestResult::discard() }
+        TestResult::from_bool(encode(&val).unwrap() == encode_key(&val).unwrap()
+            && val == decode_key(&encode_key(&val).unwrap()).unwrap())
+    }
+
+    #[quickcheck]
+    fn check_rustc(a: TestStruct, b: TestEnum) -> bool {
+        let value = (Rustc(a.clone()), Rustc(b.clone()));
+        encode(&(a, b)).unwrap() == encode_key(&value).unwrap()
+            && value == decode_key(&encode_key(&value).unwrap()).unwrap()
+    }
+
+    #[quickcheck]
+    fn check_array(a: (u32, u32, u32), b: String) -> TestResult {
+        if b.contains('\0') { return TestResult::discard() }
+        let value = ([a.0, a.1, a.2], [b.clone()]);
+        TestResult::from_bool(encode(&(Array([a.0, a.1, a.2]), Array([b]))).unwrap() == encode_key(&value).unwrap()
+            && value == decode_key(&encode_key(&value).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_str() {
+        assert_eq!(encode(&"fizz").unwrap(), encode_key("fizz").unwrap());
+        assert_eq!(encode(&"fizz").unwrap(), encode_key(&Box::new("fizz".to_string())).unwrap());
+    }
+
+    #[quickcheck]
+    fn check_desc(a: (u32, String), b: (u32, String)) -> bool {
+        if a.1.contains('\0') || b.1.contains('\0') { return true; }
+        let (desc_a, desc_b) = (Desc(a.clone()), Desc(b.clone()));
+        b.cmp(&a) == encode_key(&desc_a).unwrap().cmp(&encode_key(&desc_b).unwrap())
+            && desc_a == decode_key(&encode_key(&desc_a).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_desc_prefix() {
+        let values = vec!["b", "ab", "a", ""];
+        for pair in values.windows(2) {
+            let a = encode_key(&(Desc(pair[0]), 0u8)).unwrap();
+            let b = encode_key(&(Desc(pair[1]), 0xFFu8)).unwrap();
+            assert!(a < b, "{:?} should sort before {:?}", pair[0], pair[1]);
+        }
+    }
+
+    #[quickcheck]
+    fn check_escaped(a: Vec<u8>, b: Vec<u8>) -> bool {
+        let (escaped_a, escaped_b) = (Escaped(a.clone()), Escaped(b.clone()));
+        let (key_a, key_b) = (encode_key(&(&escaped_a, 1u8)).unwrap(),
+                              encode_key(&(&escaped_b, 0u8)).unwrap());
+        let ordered = if a == b { key_a > key_b } else { a.cmp(&b) == key_a.cmp(&key_b) };
+        ordered && escaped_a == decode_key(&encode_key(&escaped_a).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_escaped() {
+        assert_eq!(vec!(0x61, 0x00, 0xFF, 0x62, 0x00, 0x01), encode_key(&Escaped("a\0b")).unwrap());
+        assert_eq!(Escaped("a\0b".to_string()),
+                   decode_key(&encode_key(&Escaped("a\0b")).unwrap()).unwrap());
+    }
+
+    #[quickcheck]
+    fn check_varint(a: u64, b: i32) -> bool {
+        let value = (VarInt(a), VarInt(b));
+        encode(&(a as usize, b as isize)).unwrap() == encode_key(&value).unwrap()
+            && value == decode_key(&encode_key(&value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_varint_range() {
+        assert!(decode_key::<VarInt<u8>>(&encode_key(&VarInt(255u64)).unwrap()).is_ok());
+        assert!(decode_key::<VarInt<u8>>(&encode_key(&VarInt(256u64)).unwrap()).is_err());
+        assert!(decode_key::<VarInt<i8>>(&encode_key(&VarInt(-129i64)).unwrap()).is_err());
+    }
+
+    #[quickcheck]
+    fn check_option_last(a: Option<u32>, b: Option<u32>) -> bool {
+        let expected = match (a, b) {
+            (None, None) => a.cmp(&b),
+            (None, Some(_)) => b.cmp(&a),
+            (Some(_), None) => b.cmp(&a),
+            (Some(_), Some(_)) => a.cmp(&b),
+        };
+        let (last_a, last_b) = (OptionLast(a), OptionLast(b));
+        expected == encode_key(&last_a).unwrap().cmp(&encode_key(&last_b).unwrap())
+            && last_a == decode_key(&encode_key(&last_a).unwrap()).unwrap()
+    }
+
+    #[quickcheck]
+    fn check_option_last_rustc(a: Option<String>) -> TestResult {
+        if a.as_ref().map_or(false, |s| s.contains('\0')) { return TestResult::discard() }
+        let value = OptionLast(a);
+        TestResult::from_bool(encode(&value).unwrap() == encode_key(&value).unwrap()
+            && value == decode(encode(&value).unwrap()).unwrap())
+    }
+
+    #[quickcheck]
+    fn check_desc_nulls_first(a: Option<u32>, b: Option<u32>) -> bool {
+        let expected = match (a, b) {
+            (None, _) | (_, None) => a.cmp(&b),
+            (Some(_), Some(_)) => b.cmp(&a),
+        };
+        let (desc_a, desc_b) = (Desc(OptionLast(a)), Desc(OptionLast(b)));
+        let decoded: Desc<OptionLast<u32>> = decode_key(&encode_key(&desc_a).unwrap()).unwrap();
+        expected == encode_key(&desc_a).unwrap().cmp(&encode_key(&desc_b).unwrap())
+            && a == Option::from(decoded.0)
+    }
+
+    #[quickcheck]
+    fn check_nulls_last(a: Option<u32>) -> bool {
+        let mut encoder = Encoder::new(Vec::new());
+        encoder.set_nulls_last(true);
+        a.encode(&mut encoder).unwrap();
+        let bytes = encoder.finish().unwrap();
+
+        let mut decoder = Decoder::buffered(&bytes[..]);
+        decoder.set_nulls_last(true);
+        bytes == encode_key(&OptionLast(a)).unwrap()
+            && a == Decodable::decode(&mut decoder).unwrap()
+    }
+}
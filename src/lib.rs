@@ -3,29 +3,69 @@
 //! such as [leveldb](https://github.com/google/leveldb). `bytekey` attempts to encode values into
 //! the fewest number of bytes possible while preserving ordering. Type information is *not*
 //! serialized alongside values, and thus the type of serialized data must be known in order to
-//! perform decoding (`bytekey` does not implement a self-describing format).
+//! perform decoding, unless values are encoded with the self-describing format of the `tagged`
+//! module.
 //!
 //! #### Supported Data Types
 //!
 //! `bytekey` encoding currently supports all Rust primitives, strings, options, structs, enums, and
 //! tuples. `isize` and `usize` types are variable-length encoded. Sequence (`Vec`) and map types are
-//! not currently supported (but could be in the future). See `Encoder` for details on the
-//! serialization format.
+//! not currently supported (but could be in the future). Fixed-size arrays are supported by wrapping
+//! them in `Array`, and `FixedStr` encodes strings into a fixed number of bytes. See `Encoder` for
+//! details on the serialization format.
+//!
+//! The `Natural` string wrapper encodes strings in natural sort order, comparing embedded numbers by
+//! value (`"img2.png"` sorts before `"img10.png"`).
+//!
+//! Composite keys whose columns are only known at runtime can be encoded from rows of dynamic
+//! `Value`s with a `RowEncoder`, according to a `SortSpec` giving the type, direction, and nulls
+//! position of each column. Keys can also be decoded to and encoded from `Value`s without
+//! compile-time types with `decode_with_schema` and `encode_value`, given a `Schema` describing
+//! the key layout. Schemas can be parsed from a short textual description, such as
+//! `struct UserKey { tenant: u32, name: str desc, created: i64 }`. The `bytekey` command-line tool
+//! uses a schema file to decode, encode, and annotate hex encoded keys. Keys can also be converted
+//! to and from JSON with `decode_json` and `encode_json`.
+//!
+//! An `Encoder` or `Decoder` with tracing enabled records a `Trace` of the byte span of every
+//! field, variant, and value it encodes or decodes, which renders as an annotated hexdump.
+//! `explain_order` and `explain_order_of` use traces to find the field which decides the order of
+//! two keys.
+//!
+//! The `tagged` module encodes every value with a type code, so that keys can be decoded to a
+//! `Value` without knowing their type, and keys of different types can share a keyspace.
+//!
+//! The `fdb` module implements the self-describing FoundationDB tuple layer encoding, for keys
+//! shared with FoundationDB clients in other languages, and the `memcmp` module the memcomparable
+//! index key format of MyRocks, for reading and writing keys of MyRocks tables.
+//!
+//! `SemVer` is encoded with `encode_key` in SemVer precedence order (`1.0.0-alpha` sorts before
+//! `1.0.0`).
+//!
+//! With the `latin1-collation` feature enabled, the `Latin1Collated` string wrapper encodes Latin-1
+//! strings as approximate Unicode Collation Algorithm sort keys, for keys which should sort in
+//! human alphabetical order.
+//!
+//! Types may also implement the `KeyEncode` and `KeyDecode` traits, which encode values directly
+//! with an `Encoder` and `Decoder` instead of through `rustc-serialize`, and produce the same
+//! format. `KeyEncode` and `KeyDecode` are implemented for the standard types, including
+//! fixed-size arrays, and the `Rustc` adapter implements them for any `rustc-serialize` type. With
+//! the `serde` feature enabled, `Encoder` and `Decoder` also implement the `serde` data model in the
+//! same format, and the `Serde` adapter implements the traits for any `serde` type.
+//! The `Desc`, `VarInt`, `Escaped`, and `OptionLast` wrappers change how a single value is encoded,
+//! and the companion `bytekey_derive` crate derives `KeyEncode` and `KeyDecode` for structs with
+//! per-field `#[bytekey(desc)]`, `#[bytekey(varint)]`, `#[bytekey(escaped)]`, and
+//! `#[bytekey(nulls_last)]` attributes which apply the same wrappers.
 //!
 //! #### Usage
 //!
 //! ```
-//! extern crate rustc_serialize;
 //! extern crate bytekey;
 //! use bytekey::{encode, decode};
 //!
-//! #[derive(RustcEncodable, RustcDecodable, Show, PartialEq)]
-//! struct MyKey { a: u32, b: String }
-//!
 //! # fn main() {
-//! let a = MyKey { a: 1, b: "foo".to_string() };
-//! let b = MyKey { a: 2, b: "foo".to_string() };
-//! let c = MyKey { a: 2, b: "fooz".to_string() };
+//! let a = (1u32, "foo".to_string());
+//! let b = (2u32, "foo".to_string());
+//! let c = (2u32, "fooz".to_string());
 //!
 //! assert!(encode(&a).unwrap() < encode(&b).unwrap());
 //! assert!(encode(&b).unwrap() < encode(&c).unwrap());
@@ -54,26 +94,65 @@
 //! This will allow you to seamlessly add a new variant when you need to change the key format in a
 //! backwards-compatible manner (the different key types will sort seperately). If your enum has
 //! less than 16 variants, then the overhead is just a single byte in encoded output.
-
-#![feature(core, custom_attribute, io, plugin, unicode)]
-#![cfg_attr(test, feature(std_misc))]
-#![cfg_attr(test, plugin(quickcheck_macros))]
+//! * Enums which derive `KeyEncode` and `KeyDecode` with `bytekey_derive` can pin each variant to
+//! an explicit tag with `#[bytekey(tag = N)]`. Variants with explicit tags may be freely reordered,
+//! and removed variants' tags should not be reused. Decoding an unknown tag fails with
+//! `Error::UnknownTag`.
 
 extern crate byteorder;
+extern crate memchr;
 extern crate rustc_serialize;
+#[cfg(feature = "semver")] extern crate semver;
+#[cfg(feature = "serde")] extern crate serde;
+#[cfg(all(test, feature = "serde"))] #[macro_use] extern crate serde_derive;
 
 #[cfg(test)] extern crate quickcheck;
-#[cfg(test)] extern crate rand;
+#[cfg(test)] #[macro_use] extern crate quickcheck_macros;
 
+pub use array::Array;
 pub use encoder::Encoder;
-pub use decoder::Decoder;
+pub use explain::{explain_order, explain_order_of, Difference};
+pub use decoder::{Decoder, DecodeIter, Invert};
+pub use fixed::{FixedStr, Width};
+pub use json::{from_json, to_json};
+pub use key::{Desc, Escaped, KeyDecode, KeyEncode, OptionLast, Rustc, VarInt};
+pub use size::SizeEncoder;
+pub use natural::Natural;
+pub use row::{Column, Direction, Nulls, RowEncoder, SortSpec, Type};
+pub use parse::{parse_definitions, ParseError};
+pub use schema::Schema;
+pub use trace::{Span, Trace};
+pub use value::Value;
+pub use version::{Identifier, SemVer};
+#[cfg(feature = "latin1-collation")] pub use collation::Latin1Collated;
+#[cfg(feature = "serde")] pub use serde_key::Serde;
 
+mod array;
 mod encoder;
 mod decoder;
+mod explain;
+mod fixed;
+mod json;
+mod key;
+mod size;
+mod natural;
+mod parse;
+mod row;
+mod schema;
+mod trace;
+mod util;
+mod value;
+mod version;
+#[cfg(feature = "latin1-collation")] mod collation;
+#[cfg(feature = "serde")] mod serde_key;
+
+pub mod fdb;
+pub mod memcmp;
+pub mod tagged;
 
 use rustc_serialize::{Encodable, Decodable};
+use rustc_serialize::json::Json;
 use std::{error, fmt, io, result};
-use std::error::Error as StdError;
 
 /// Encode data into a byte vector.
 ///
@@ -88,13 +167,77 @@ use std::error::Error as StdError;
 pub fn encode<T>(value: &T) -> Result<Vec<u8>>
 where T: Encodable {
     let mut writer = Vec::new();
-    {
-        let mut encoder = Encoder::new(&mut writer);
-        try!(value.encode(&mut encoder));
-    }
+    try!(encode_into(value, &mut writer));
     Ok(writer)
 }
 
+/// Encode data onto the end of a byte vector.
+///
+/// Reusing the vector across calls avoids allocating a new vector for every encoded value.
+///
+/// #### Usage
+///
+/// ```
+/// # use bytekey::encode_into;
+/// let mut buf = vec!(0xFF);
+/// encode_into(&42u32, &mut buf).unwrap();
+/// assert_eq!(vec!(0xFF, 0x00, 0x00, 0x00, 0x2A), buf);
+/// ```
+pub fn encode_into<T>(value: &T, buf: &mut Vec<u8>) -> Result<()>
+where T: Encodable {
+    let mut encoder = Encoder::new(buf);
+    try!(value.encode(&mut encoder));
+    encoder.finish().map(|_| ())
+}
+
+/// Encode data into a byte slice, returning the number of bytes written.
+///
+/// Returns `Error::BufferTooSmall` if the encoded value does not fit, in which case the contents of
+/// the slice are unspecified. The encoded size is only computed on this error path.
+///
+/// #### Usage
+///
+/// ```
+/// # use bytekey::encode_to_slice;
+/// let mut buf = [0u8; 8];
+/// assert_eq!(5, encode_to_slice(&"fizz", &mut buf).unwrap());
+/// assert_eq!(&[0x66, 0x69, 0x7A, 0x7A, 0x00], &buf[..5]);
+/// assert!(encode_to_slice(&"fizzbuzz", &mut buf).is_err());
+/// ```
+pub fn encode_to_slice<T>(value: &T, buf: &mut [u8]) -> Result<usize>
+where T: Encodable {
+    let mut encoder = Encoder::new(io::Cursor::new(buf));
+    let result = match value.encode(&mut encoder) {
+        Ok(()) => encoder.finish(),
+        Err(error) => Err(error),
+    };
+    match result {
+        Ok(cursor) => Ok(cursor.position() as usize),
+        Err(Error::Io(ref error)) if error.kind() == io::ErrorKind::WriteZero => {
+            Err(Error::BufferTooSmall { needed: try!(encoded_len(value)) })
+        },
+        Err(error) => Err(error),
+    }
+}
+
+/// Compute the encoded size of a value, without encoding it.
+///
+/// This is useful for checking a value against a store's key length limit before encoding it.
+///
+/// #### Usage
+///
+/// ```
+/// # use bytekey::{encode, encoded_len};
+/// assert_eq!(4, encoded_len(&42u32).unwrap());
+/// assert_eq!(encode(&(42u8, "fizz")).unwrap().len(), encoded_len(&(42u8, "fizz")).unwrap());
+/// ```
+pub fn encoded_len<T>(value: &T) -> Result<usize>
+where T: Encodable {
+    let mut encoder = SizeEncoder::new();
+    try!(value.encode(&mut encoder));
+    Ok(encoder.len())
+}
+
 /// Decode data from a byte vector.
 ///
 /// #### Usage
@@ -105,7 +248,97 @@ where T: Encodable {
 /// ```
 pub fn decode<T>(bytes: Vec<u8>) -> Result<T>
 where T: Decodable {
-    Decodable::decode(&mut Decoder::new(io::Cursor::new(bytes)))
+    Decodable::decode(&mut Decoder::buffered(io::Cursor::new(bytes)))
+}
+
+/// Encode a value into a byte vector with its `KeyEncode` implementation.
+///
+/// The encoded format is identical to the format produced by `encode`.
+///
+/// #### Usage
+///
+/// ```
+/// # use bytekey::{encode, encode_key};
+/// assert_eq!(encode(&(42u8, "fizz")).unwrap(), encode_key(&(42u8, "fizz")).unwrap());
+/// ```
+pub fn encode_key<T: ?Sized>(value: &T) -> Result<Vec<u8>>
+where T: KeyEncode {
+    let mut encoder = Encoder::new(Vec::new());
+    try!(value.encode_key(&mut encoder));
+    encoder.finish()
+}
+
+/// Decode a value from a byte slice with its `KeyDecode` implementation.
+///
+/// #### Usage
+///
+/// ```
+/// # use bytekey::{encode_key, decode_key};
+/// assert_eq!(42usize, decode_key::<usize>(&encode_key(&42usize).unwrap()).unwrap());
+/// ```
+pub fn decode_key<T>(bytes: &[u8]) -> Result<T>
+where T: KeyDecode {
+    T::decode_key(&mut Decoder::buffered(bytes))
+}
+
+/// Encode a dynamically typed value described by the schema into a byte vector.
+///
+/// Returns `Error::Schema` if the value does not match the schema.
+pub fn encode_value(schema: &Schema, value: &Value) -> Result<Vec<u8>> {
+    let mut encoder = Encoder::new(Vec::new());
+    try!(schema.encode_value(value, &mut encoder));
+    encoder.finish()
+}
+
+/// Decode a dynamically typed value described by the schema from a byte slice.
+///
+/// #### Usage
+///
+/// ```
+/// # use bytekey::{encode, decode_with_schema, Schema, Type, Value};
+/// let schema = Schema::Option(Box::new(Schema::Scalar(Type::Char)));
+/// assert_eq!(Value::Option(Some(Box::new(Value::Char('a')))),
+///            decode_with_schema(&schema, &encode(&Some('a')).unwrap()).unwrap());
+/// ```
+pub fn decode_with_schema(schema: &Schema, bytes: &[u8]) -> Result<Value> {
+    schema.decode_value(&mut Decoder::buffered(bytes))
+}
+
+/// Encode a key described by the schema from JSON.
+///
+/// See `to_json` for the JSON representation of values.
+///
+/// #### Usage
+///
+/// ```
+/// # extern crate rustc_serialize;
+/// # extern crate bytekey;
+/// # use bytekey::{encode, encode_json, Schema};
+/// # use rustc_serialize::json::Json;
+/// # fn main() {
+/// let schema = Schema::parse("struct Key { tenant: u32, name: str }").unwrap();
+/// let json = Json::from_str("{\"tenant\": 3, \"name\": \"bob\"}").unwrap();
+/// assert_eq!(encode(&(3u32, "bob")).unwrap(), encode_json(&schema, &json).unwrap());
+/// # }
+/// ```
+pub fn encode_json(schema: &Schema, json: &Json) -> Result<Vec<u8>> {
+    encode_value(schema, &try!(from_json(schema, json)))
+}
+
+/// Decode a key described by the schema to JSON.
+///
+/// See `to_json` for the JSON representation of values.
+///
+/// #### Usage
+///
+/// ```
+/// # use bytekey::{encode, decode_json, Schema};
+/// let schema = Schema::parse("struct Key { tenant: u32, name: str }").unwrap();
+/// let json = decode_json(&schema, &encode(&(3u32, "bob")).unwrap()).unwrap();
+/// assert_eq!("{\"name\":\"bob\",\"tenant\":3}", json.to_string());
+/// ```
+pub fn decode_json(schema: &Schema, bytes: &[u8]) -> Result<Json> {
+    to_json(schema, &try!(decode_with_schema(schema, bytes)))
 }
 
 /// A short-hand for `result::Result<T, bytekey::decoder::Error>`.
@@ -114,7 +347,9 @@ pub type Result<T> = result::Result<T, Error>;
 /// An error type for bytekey decoding and encoding.
 ///
 /// This is a thin wrapper over the standard `io::Error` type. Namely, it
-/// adds two additional error cases: an unexpected EOF, and invalid utf8.
+/// adds additional error cases for an unexpected EOF, invalid utf8, values which do not fit in a
+/// fixed-width encoding, output buffers which are too small for the encoded value, unknown enum
+/// variant tags, and dynamic values which do not match their schema.
 #[derive(Debug)]
 pub enum Error {
 
@@ -126,28 +361,30 @@ pub enum Error {
     /// decode a meaningful value.
     UnexpectedEof,
 
+    /// Variant representing that a value is longer than the maximum length of its fixed-width
+    /// encoding.
+    TooLong { len: usize, max: usize },
+
+    /// Variant representing that an output buffer is too small to hold the encoded value. `needed`
+    /// is the encoded length of the value.
+    BufferTooSmall { needed: usize },
+
+    /// Variant representing that a decoded enum variant tag does not belong to any variant of the
+    /// enum.
+    UnknownTag { tag: u64 },
+
+    /// Variant representing that a dynamic value does not match the type it is encoded as.
+    Schema(String),
+
     /// Variant representing that an I/O error occurred.
     Io(io::Error),
 }
 
 impl From<io::Error> for Error {
-    fn from(error: io::Error) -> Error { Error::Io(error) }
-}
-
-impl From<io::CharsError> for Error {
-    fn from(error: io::CharsError) -> Error {
-        match error {
-            io::CharsError::NotUtf8 => Error::NotUtf8,
-            io::CharsError::Other(error) => Error::Io(error),
-        }
-    }
-}
-
-impl From<byteorder::Error> for Error {
-    fn from(error: byteorder::Error) -> Error {
-        match error {
-            byteorder::Error::UnexpectedEOF => Error::UnexpectedEof,
-            byteorder::Error::Io(error) => Error::Io(error),
+    fn from(error: io::Error) -> Error {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => Error::UnexpectedEof,
+            _ => Error::Io(error),
         }
     }
 }
@@ -157,6 +394,12 @@ impl fmt::Display for Error {
         match *self {
             Error::NotUtf8 => write!(f, "byte stream did not contain valid utf8"),
             Error::UnexpectedEof => write!(f, "unexpected end of file"),
+            Error::TooLong { len, max } =>
+                write!(f, "value of length {} exceeds the maximum length of {}", len, max),
+            Error::BufferTooSmall { needed } =>
+                write!(f, "buffer too small for encoded value of length {}", needed),
+            Error::UnknownTag { tag } => write!(f, "unknown enum variant tag {}", tag),
+            Error::Schema(ref msg) => write!(f, "schema mismatch: {}", msg),
             Error::Io(ref err) => err.fmt(f),
         }
     }
@@ -167,6 +410,10 @@ impl error::Error for Error {
         match *self {
             Error::NotUtf8 => "invalid utf8 encoding",
             Error::UnexpectedEof => "unexpected end of file",
+            Error::TooLong { .. } => "value exceeds the maximum length",
+            Error::BufferTooSmall { .. } => "buffer too small for encoded value",
+            Error::UnknownTag { .. } => "unknown enum variant tag",
+            Error::Schema(_) => "value does not match schema",
             Error::Io(ref err) => err.description(),
         }
     }
@@ -175,6 +422,10 @@ impl error::Error for Error {
         match *self {
             Error::NotUtf8 => None,
             Error::UnexpectedEof => None,
+            Error::TooLong { .. } => None,
+            Error::BufferTooSmall { .. } => None,
+            Error::UnknownTag { .. } => None,
+            Error::Schema(_) => None,
             Error::Io(ref err) => err.cause(),
         }
     }
@@ -0,0 +1,356 @@
+//! Encoding compatible with the MyRocks memcomparable key format.
+//!
+//! MyRocks encodes the columns of an index key so that keys compare bytewise in index order. Each
+//! column is encoded according to its SQL type:
+//!
+//! * Nullable columns are prefixed by a flag byte, `0x00` for `NULL` and `0x01` otherwise, so
+//!   `NULL` sorts first. A `NULL` column has no further bytes.
+//! * Integers are encoded in big-endian format in the width of their SQL type, with the sign bit
+//!   flipped for signed types.
+//! * `VARCHAR` and `VARBINARY` values are split into groups of 8 bytes, with the final group padded
+//!   with null bytes. Each group is followed by a marker byte: `9` if another group follows, and
+//!   otherwise the number of bytes of the final group which are used. The empty string is a single
+//!   group of padding with marker `0`.
+//!
+//! Strings are compared by their bytes, as with a binary collation. Columns with other collations
+//! are stored by MyRocks as collation weights, which are not supported.
+//!
+//! #### Usage
+//!
+//! ```
+//! # use bytekey::Value;
+//! # use bytekey::memcmp::{decode, encode, Column, Type};
+//! let columns = [Column::new(Type::Int), Column::nullable(Type::Varchar)];
+//! let row = vec!(Value::I64(-1), Value::Option(Some(Box::new(Value::Str("abc".to_string())))));
+//!
+//! let key = encode(&columns, &row).unwrap();
+//! assert_eq!(vec!(0x7F, 0xFF, 0xFF, 0xFF,
+//!                 0x01, 0x61, 0x62, 0x63, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03), key);
+//! assert_eq!(row, decode(&columns, &key).unwrap());
+//! ```
+
+use {Error, Result, Value};
+use util::error;
+
+/// The number of bytes in each group of a `VARCHAR` value.
+const GROUP_LEN: usize = 8;
+
+/// The marker following a group of a `VARCHAR` value which is followed by another group.
+const MORE_GROUPS: u8 = 9;
+
+/// The SQL type of a column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Type {
+    TinyInt,
+    SmallInt,
+    MediumInt,
+    Int,
+    BigInt,
+    UnsignedTinyInt,
+    UnsignedSmallInt,
+    UnsignedMediumInt,
+    UnsignedInt,
+    UnsignedBigInt,
+    /// A string with a binary collation, which decodes as a `Value::Str`.
+    Varchar,
+    /// A byte string, which decodes as a `Value::Bytes`.
+    Varbinary,
+}
+
+impl Type {
+
+    /// Returns the width in bytes, and whether the type is signed, of an integer type.
+    fn int(self) -> Option<(usize, bool)> {
+        match self {
+            Type::TinyInt => Some((1, true)),
+            Type::SmallInt => Some((2, true)),
+            Type::MediumInt => Some((3, true)),
+            Type::Int => Some((4, true)),
+            Type::BigInt => Some((8, true)),
+            Type::UnsignedTinyInt => Some((1, false)),
+            Type::UnsignedSmallInt => Some((2, false)),
+            Type::UnsignedMediumInt => Some((3, false)),
+            Type::UnsignedInt => Some((4, false)),
+            Type::UnsignedBigInt => Some((8, false)),
+            Type::Varchar | Type::Varbinary => None,
+        }
+    }
+}
+
+/// A column of an index key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Column {
+    pub ty: Type,
+    pub nullable: bool,
+}
+
+impl Column {
+
+    /// Creates a new column which is `NOT NULL`.
+    pub fn new(ty: Type) -> Column {
+        Column { ty: ty, nullable: false }
+    }
+
+    /// Creates a new nullable column.
+    ///
+    /// Values of nullable columns are `Value::Option`s.
+    pub fn nullable(ty: Type) -> Column {
+        Column { ty: ty, nullable: true }
+    }
+}
+
+/// Encodes a row of values as a memcomparable key of the columns.
+///
+/// Integer columns accept any integer `Value` in the range of the column's type, and `VARCHAR`
+/// and `VARBINARY` columns accept `Value::Str` and `Value::Bytes`. Returns `Error::Schema` if a
+/// value does not match its column.
+pub fn encode(columns: &[Column], row: &[Value]) -> Result<Vec<u8>> {
+    if columns.len() != row.len() {
+        let msg = format!("expected {} columns, found {}", columns.len(), row.len());
+        return Err(Error::Schema(msg));
+    }
+    let mut buf = Vec::new();
+    for (column, value) in columns.iter().zip(row.iter()) {
+        let value = match (column.nullable, value) {
+            (true, &Value::Option(None)) => {
+                buf.push(0x00);
+                continue;
+            },
+            (true, &Value::Option(Some(ref value))) => {
+                buf.push(0x01);
+                &**value
+            },
+            (true, value) => return Err(mismatch(column, value)),
+            (false, value) => value,
+        };
+        try!(encode_column(column, value, &mut buf));
+    }
+    Ok(buf)
+}
+
+/// Decodes a memcomparable key of the columns to a row of values.
+///
+/// Signed integer columns decode as `Value::I64`, and unsigned integer columns as `Value::U64`.
+pub fn decode(columns: &[Column], bytes: &[u8]) -> Result<Vec<Value>> {
+    let mut pos = 0;
+    let mut row = Vec::with_capacity(columns.len());
+    for column in columns.iter() {
+        if column.nullable {
+            match bytes.get(pos) {
+                Some(&0x00) => {
+                    pos += 1;
+                    row.push(Value::Option(None));
+                    continue;
+                },
+                Some(&0x01) => pos += 1,
+                Some(&flag) => {
+                    return Err(error(&format!("invalid null flag 0x{:02x} at byte {}", flag, pos)))
+                },
+                None => return Err(Error::UnexpectedEof),
+            }
+        }
+        let value = try!(decode_column(column, bytes, &mut pos));
+        row.push(if column.nullable { Value::Option(Some(Box::new(value))) } else { value });
+    }
+    if pos != bytes.len() {
+        return Err(error(&format!("trailing bytes after the key at byte {}", pos)));
+    }
+    Ok(row)
+}
+
+fn mismatch(column: &Column, value: &Value) -> Error {
+    Error::Schema(format!("value {:?} does not match column {:?}", value, column))
+}
+
+/// Returns the value of an integer `Value` as its sign and magnitude.
+fn int_value(value: &Value) -> Option<(bool, u64)> {
+    let signed = |v: i64| if v < 0 { (true, !(v as u64) + 1) } else { (false, v as u64) };
+    match *value {
+        Value::U8(v) => Some((false, v as u64)),
+        Value::U16(v) => Some((false, v as u64)),
+        Value::U32(v) => Some((false, v as u64)),
+        Value::U64(v) => Some((false, v)),
+        Value::Usize(v) => Some((false, v as u64)),
+        Value::I8(v) => Some(signed(v as i64)),
+        Value::I16(v) => Some(signed(v as i64)),
+        Value::I32(v) => Some(signed(v as i64)),
+        Value::I64(v) => Some(signed(v)),
+        Value::Isize(v) => Some(signed(v as i64)),
+        _ => None,
+    }
+}
+
+fn encode_column(column: &Column, value: &Value, buf: &mut Vec<u8>) -> Result<()> {
+    if let Some((len, signed)) = column.ty.int() {
+        let bits = len as u32 * 8;
+        let (negative, magnitude) = try!(int_value(value).ok_or_else(|| mismatch(column, value)));
+        let val = match (signed, negative) {
+            (false, false) if bits == 64 || magnitude >> bits == 0 => magnitude,
+            (true, false) if magnitude < 1 << (bits - 1) => magnitude ^ (1 << (bits - 1)),
+            (true, true) if magnitude <= 1 << (bits - 1) => (1 << (bits - 1)) - magnitude,
+            _ => return Err(mismatch(column, value)),
+        };
+        buf.extend((0..len).rev().map(|n| (val >> (n * 8)) as u8));
+        return Ok(());
+    }
+    let bytes = match (column.ty, value) {
+        (Type::Varchar, &Value::Str(ref s)) | (Type::Varbinary, &Value::Str(ref s)) => s.as_bytes(),
+        (Type::Varchar, &Value::Bytes(ref b)) | (Type::Varbinary, &Value::Bytes(ref b)) => &b[..],
+        _ => return Err(mismatch(column, value)),
+    };
+    let mut groups = bytes.chunks(GROUP_LEN).peekable();
+    if groups.peek().is_none() {
+        buf.extend([0; GROUP_LEN + 1].iter().cloned());
+    }
+    while let Some(group) = groups.next() {
+        buf.extend(group.iter().cloned());
+        buf.extend((group.len()..GROUP_LEN).map(|_| 0));
+        buf.push(if groups.peek().is_some() { MORE_GROUPS } else { group.len() as u8 });
+    }
+    Ok(())
+}
+
+fn decode_column(column: &Column, bytes: &[u8], pos: &mut usize) -> Result<Value> {
+    if let Some((len, signed)) = column.ty.int() {
+        if *pos + len > bytes.len() {
+            return Err(Error::UnexpectedEof);
+        }
+        let val = bytes[*pos..*pos + len].iter().fold(0u64, |val, &b| (val << 8) | b as u64);
+        *pos += len;
+        if !signed {
+            return Ok(Value::U64(val));
+        }
+        // Flip the sign bit, and sign-extend to 64 bits.
+        let shift = 64 - len as u32 * 8;
+        return Ok(Value::I64((((val ^ (1 << (len * 8 - 1))) << shift) as i64) >> shift));
+    }
+    let mut value = Vec::new();
+    loop {
+        if *pos + GROUP_LEN + 1 > bytes.len() {
+            return Err(Error::UnexpectedEof);
+        }
+        let group = &bytes[*pos..*pos + GROUP_LEN];
+        let marker = bytes[*pos + GROUP_LEN];
+        *pos += GROUP_LEN + 1;
+        if marker == MORE_GROUPS {
+            value.extend(group.iter().cloned());
+            continue;
+        }
+        // Only an empty value is encoded with an empty final group.
+        let used = marker as usize;
+        let empty = used == 0 && !value.is_empty();
+        if empty || used > GROUP_LEN || group[used..].iter().any(|&b| b != 0) {
+            return Err(error(&format!("invalid group marker {} at byte {}", marker, *pos - 1)));
+        }
+        value.extend(group[..used].iter().cloned());
+        break;
+    }
+    match column.ty {
+        Type::Varchar => String::from_utf8(value).map(Value::Str).map_err(|_| Error::NotUtf8),
+        _ => Ok(Value::Bytes(value)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use {Error, Value};
+    use super::{decode, encode, Column, Type};
+
+    fn some(value: Value) -> Value {
+        Value::Option(Some(Box::new(value)))
+    }
+
+    fn check(column: Column, value: Value, expected: &[u8]) {
+        let columns = [column];
+        let row = vec!(value);
+        assert_eq!(expected, &encode(&columns, &row).unwrap()[..]);
+        assert_eq!(row, decode(&columns, expected).unwrap());
+    }
+
+    #[test]
+    fn test_ints() {
+        check(Column::new(Type::TinyInt), Value::I64(0), &[0x80]);
+        check(Column::new(Type::TinyInt), Value::I64(-128), &[0x00]);
+        check(Column::new(Type::TinyInt), Value::I64(127), &[0xFF]);
+        check(Column::new(Type::SmallInt), Value::I64(-2), &[0x7F, 0xFE]);
+        check(Column::new(Type::MediumInt), Value::I64(-8388608), &[0x00, 0x00, 0x00]);
+        check(Column::new(Type::MediumInt), Value::I64(1), &[0x80, 0x00, 0x01]);
+        check(Column::new(Type::Int), Value::I64(1), &[0x80, 0x00, 0x00, 0x01]);
+        check(Column::new(Type::Int), Value::I64(-1), &[0x7F, 0xFF, 0xFF, 0xFF]);
+        check(Column::new(Type::BigInt), Value::I64(::std::i64::MIN), &[0x00; 8]);
+        check(Column::new(Type::BigInt), Value::I64(258), &[0x80, 0, 0, 0, 0, 0, 0x01, 0x02]);
+        check(Column::new(Type::UnsignedTinyInt), Value::U64(255), &[0xFF]);
+        check(Column::new(Type::UnsignedMediumInt), Value::U64(0x123456), &[0x12, 0x34, 0x56]);
+        check(Column::new(Type::UnsignedInt), Value::U64(1), &[0x00, 0x00, 0x00, 0x01]);
+        check(Column::new(Type::UnsignedBigInt), Value::U64(::std::u64::MAX), &[0xFF; 8]);
+    }
+
+    #[test]
+    fn test_varchar() {
+        check(Column::new(Type::Varchar), Value::Str("".to_string()), &[0; 9]);
+        check(Column::new(Type::Varchar),
+              Value::Str("abc".to_string()),
+              &[0x61, 0x62, 0x63, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03]);
+        check(Column::new(Type::Varchar),
+              Value::Str("abcdefgh".to_string()),
+              &[0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x08]);
+        check(Column::new(Type::Varchar),
+              Value::Str("abcdefghi".to_string()),
+              &[0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x09,
+                0x69, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        check(Column::new(Type::Varbinary),
+              Value::Bytes(vec!(0x00, 0xFF)),
+              &[0x00, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02]);
+    }
+
+    #[test]
+    fn test_nullable() {
+        check(Column::nullable(Type::Int), Value::Option(None), &[0x00]);
+        check(Column::nullable(Type::Int), some(Value::I64(5)), &[0x01, 0x80, 0x00, 0x00, 0x05]);
+        check(Column::nullable(Type::Varchar), Value::Option(None), &[0x00]);
+        check(Column::nullable(Type::Varchar),
+              some(Value::Str("a".to_string())),
+              &[0x01, 0x61, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn test_order() {
+        let columns = [Column::nullable(Type::Varchar), Column::new(Type::SmallInt)];
+        let rows = vec!(vec!(Value::Option(None), Value::I64(7)),
+                        vec!(some(Value::Str("".to_string())), Value::I64(-300)),
+                        vec!(some(Value::Str("".to_string())), Value::I64(2)),
+                        vec!(some(Value::Str("abc".to_string())), Value::I64(0)),
+                        vec!(some(Value::Str("abc\0".to_string())), Value::I64(0)),
+                        vec!(some(Value::Str("abcdefgh".to_string())), Value::I64(0)),
+                        vec!(some(Value::Str("abcdefgh\0".to_string())), Value::I64(0)),
+                        vec!(some(Value::Str("abd".to_string())), Value::I64(0)));
+        let keys: Vec<_> = rows.iter().map(|row| encode(&columns, row).unwrap()).collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+    }
+
+    #[test]
+    fn test_errors() {
+        let int = [Column::new(Type::TinyInt)];
+        assert!(encode(&int, &[Value::I64(128)]).is_err());
+        assert!(encode(&int, &[Value::I64(-129)]).is_err());
+        assert!(encode(&[Column::new(Type::UnsignedInt)], &[Value::I8(-1)]).is_err());
+        assert!(encode(&int, &[Value::Str("1".to_string())]).is_err());
+        assert!(encode(&int, &[]).is_err());
+        assert!(encode(&[Column::nullable(Type::Int)], &[Value::I32(1)]).is_err());
+
+        let varchar = [Column::new(Type::Varchar)];
+        match decode(&varchar, &[0x61, 0x00, 0x00]) {
+            Err(Error::UnexpectedEof) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(decode(&varchar, &[0x61, 0x62, 0, 0, 0, 0, 0, 0, 0x01]).is_err());
+        assert!(decode(&varchar, &[0x61, 0, 0, 0, 0, 0, 0, 0, 0x0A]).is_err());
+        assert!(decode(&varchar, &[0x61, 0x61, 0x61, 0x61, 0x61, 0x61, 0x61, 0x61, 0x09,
+                                   0, 0, 0, 0, 0, 0, 0, 0, 0x00]).is_err());
+        assert!(decode(&[Column::nullable(Type::Int)], &[0x02]).is_err());
+        assert!(decode(&int, &[0x80, 0x80]).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+//! Natural sort order for strings containing embedded numbers.
+
+use std::result;
+
+use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
+
+/// Marker byte which introduces a run of digits.
+///
+/// Text runs never contain ASCII digits, so a digit run sorts against surrounding text exactly as
+/// its first digit would.
+const DIGITS: u8 = b'0';
+
+/// A string which is encoded in natural ('human') sort order.
+///
+/// Runs of ASCII digits embedded in the string are compared by numeric value instead of character
+/// by character, so that `"img2.png"` sorts before `"img10.png"`. Decoding returns the exact
+/// original string, including any leading zeros.
+///
+/// ##### Encoding
+///
+/// The string is split into alternating text and digit runs. Text runs are encoded as their
+/// UTF-8 bytes. Each digit run is encoded as a `0x30` marker byte, followed by the number of
+/// significant digits (variable-length encoded, see `Encoder::emit_var_u64`) and the significant
+/// digits themselves. The string is terminated with a single null byte, and followed by the number
+/// of leading zeros of each digit run in order (also variable-length encoded). Leading zeros are
+/// only a final tiebreak between strings which are otherwise equal, so `"a1"` sorts before
+/// `"a01"`, but `"a01"` sorts before `"a1b"`.
+///
+/// As with the plain string encoding, strings should not contain null bytes unless the value is
+/// the final component of the key.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Natural<S>(pub S);
+
+impl<S> Encodable for Natural<S> where S: AsRef<str> {
+    fn encode<E>(&self, e: &mut E) -> result::Result<(), E::Error>
+    where E: Encoder {
+        let bytes = self.0.as_ref().as_bytes();
+        let mut zeros = Vec::new();
+        let mut idx = 0;
+        while idx < bytes.len() {
+            if !is_digit(bytes[idx]) {
+                try!(e.emit_u8(bytes[idx]));
+                idx += 1;
+                continue;
+            }
+
+            let start = idx;
+            while idx < bytes.len() && bytes[idx] == b'0' { idx += 1; }
+            zeros.push(idx - start);
+            let significant = idx;
+            while idx < bytes.len() && is_digit(bytes[idx]) { idx += 1; }
+
+            try!(e.emit_u8(DIGITS));
+            try!(e.emit_usize(idx - significant));
+            for &digit in bytes[significant..idx].iter() {
+                try!(e.emit_u8(digit));
+            }
+        }
+        try!(e.emit_u8(0));
+        for &zeros in zeros.iter() {
+            try!(e.emit_usize(zeros));
+        }
+        Ok(())
+    }
+}
+
+impl Decodable for Natural<String> {
+    fn decode<D>(d: &mut D) -> result::Result<Natural<String>, D::Error>
+    where D: Decoder {
+        let mut bytes = Vec::new();
+        // The offsets of the digit runs, where their leading zeros are inserted.
+        let mut runs = Vec::new();
+        loop {
+            match try!(d.read_u8()) {
+                0 => break,
+                DIGITS => {
+                    runs.push(bytes.len());
+                    for _ in 0..try!(d.read_usize()) {
+                        let digit = try!(d.read_u8());
+                        if !is_digit(digit) { return Err(d.error("invalid natural digit run")); }
+                        bytes.push(digit);
+                    }
+                },
+                byte => bytes.push(byte),
+            }
+        }
+
+        let mut string = Vec::with_capacity(bytes.len());
+        let mut start = 0;
+        for &run in runs.iter() {
+            string.extend_from_slice(&bytes[start..run]);
+            for _ in 0..try!(d.read_usize()) { string.push(b'0'); }
+            start = run;
+        }
+        string.extend_from_slice(&bytes[start..]);
+        match String::from_utf8(string) {
+            Ok(string) => Ok(Natural(string)),
+            Err(_) => Err(d.error("byte stream did not contain valid utf8")),
+        }
+    }
+}
+
+fn is_digit(byte: u8) -> bool {
+    byte >= b'0' && byte <= b'9'
+}
+
+#[cfg(test)]
+mod test {
+
+    use quickcheck::TestResult;
+
+    use {encode, decode};
+    use super::Natural;
+
+    fn key(s: &str) -> Vec<u8> {
+        encode(&Natural(s)).unwrap()
+    }
+
+    #[test]
+    fn test_order() {
+        let names = vec![
+            "", "0", "00", "1", "01", "2", "9", "10", "99", "100", "1000000000000000000000000",
+            "a", "a1", "a01", "a1b", "a2", "a10", "a10.5", "a10.10", "a10b", "ab", "img2.png",
+            "img10.png", "img100.png", "v1.2.9", "v1.2.10", "v1.10.0",
+        ];
+        for pair in names.windows(2) {
+            assert!(key(pair[0]) < key(pair[1]), "{:?} should sort before {:?}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!(vec!(0x69, 0x6D, 0x67, 0x30, 0x02, 0x31, 0x30, 0x00, 0x00), key("img10"));
+        assert_eq!(vec!(0x30, 0x00, 0x00, 0x03), key("000"));
+        assert_eq!(vec!(0x30, 0x01, 0x31, 0x2E, 0x30, 0x01, 0x32, 0x00, 0x01, 0x00), key("01.2"));
+    }
+
+    #[test]
+    fn test_leading_zeros() {
+        assert!(key("a1") < key("a01"));
+        assert!(key("a01") < key("a1b"));
+        assert!(key("file01.txt") < key("file1.zip"));
+        assert!(key("file1.txt") < key("file01.txt"));
+    }
+
+    #[quickcheck]
+    fn check_natural(val: String) -> TestResult {
+        if val.contains('\0') { return TestResult::discard() }
+        let natural = Natural(val);
+        TestResult::from_bool(natural == decode(encode(&natural).unwrap()).unwrap())
+    }
+}
@@ -0,0 +1,478 @@
+//! A textual language for key schemas.
+
+use std::{error, fmt, result};
+use std::str::Chars;
+use std::iter::Peekable;
+
+use {Schema, Type};
+
+/// An error which occurred while parsing a schema.
+///
+/// Lines and columns are counted from 1, and columns count characters.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl error::Error for ParseError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+type ParseResult<T> = result::Result<T, ParseError>;
+
+impl Schema {
+
+    /// Parses a schema from its textual description.
+    ///
+    /// The description is a sequence of struct and enum definitions, and the schema of the last
+    /// definition is returned. See `parse_definitions` for the syntax.
+    ///
+    /// #### Usage
+    ///
+    /// ```
+    /// # use bytekey::{encode_key, decode_with_schema, Desc, Schema, Value};
+    /// let schema = Schema::parse("struct UserKey { tenant: u32, name: str desc, created: i64 }")
+    ///     .unwrap();
+    /// let bytes = encode_key(&(3u32, Desc("bob"), 0i64)).unwrap();
+    /// assert_eq!(Value::Tuple(vec!(Value::U32(3), Value::Str("bob".to_string()), Value::I64(0))),
+    ///            decode_with_schema(&schema, &bytes).unwrap());
+    /// ```
+    pub fn parse(src: &str) -> ParseResult<Schema> {
+        let mut definitions = try!(parse_definitions(src));
+        match definitions.pop() {
+            Some((_, schema)) => Ok(schema),
+            None => Err(ParseError { line: 1, column: 1, message: "empty schema".to_string() }),
+        }
+    }
+}
+
+/// Parses the struct and enum definitions of a textual schema description.
+///
+/// Returns the name and schema of each definition, in order. Types refer to earlier definitions by
+/// name, and the referenced schema is included in place.
+///
+/// ##### Syntax
+///
+/// ```text
+/// // Comments run to the end of the line.
+/// struct UserKey { tenant: u32, name: str desc, created: i64 }
+/// struct Pair(u32, str);
+/// struct Marker;
+/// enum Key {
+///     User(UserKey),
+///     Group(u64) = 5,
+///     Tombstone { deleted: option<i64> },
+/// }
+/// ```
+///
+/// The primitive types are `bool`, `u8`, `u16`, `u32`, `u64`, `usize`, `i8`, `i16`, `i32`, `i64`,
+/// `isize`, `f32`, `f64`, `char`, `str`, and `bytes` (an escaped byte string). Types may also be
+/// tuples such as `(u32, str)`, options such as `option<str>` (`None` first) or `option_last<str>`
+/// (`None` last), sequences such as `seq<u32>`, or the name of an earlier definition. A type
+/// followed by `desc` is encoded in descending order.
+///
+/// Enum variants are tagged with their declaration index, unless they are given an explicit tag
+/// with `= N`.
+pub fn parse_definitions(src: &str) -> ParseResult<Vec<(String, Schema)>> {
+    let mut parser = Parser { lexer: Lexer::new(src), peeked: None, definitions: Vec::new() };
+    loop {
+        let token = try!(parser.next());
+        match token.kind {
+            Kind::Eof => return Ok(parser.definitions),
+            Kind::Ident(ref keyword) if keyword == "struct" => try!(parser.parse_struct()),
+            Kind::Ident(ref keyword) if keyword == "enum" => try!(parser.parse_enum()),
+            _ => return Err(token.error("expected `struct` or `enum`")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Kind {
+    Ident(String),
+    Int(u64),
+    Punct(char),
+    Eof,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: Kind,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, message: &str) -> ParseError {
+        let found = match self.kind {
+            Kind::Ident(ref ident) => format!("`{}`", ident),
+            Kind::Int(int) => format!("`{}`", int),
+            Kind::Punct(c) => format!("`{}`", c),
+            Kind::Eof => "end of input".to_string(),
+        };
+        ParseError {
+            line: self.line,
+            column: self.column,
+            message: format!("{}, found {}", message, found),
+        }
+    }
+}
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Lexer<'a> {
+
+    fn new(src: &'a str) -> Lexer<'a> {
+        Lexer { chars: src.chars().peekable(), line: 1, column: 1 }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        match c {
+            Some('\n') => { self.line += 1; self.column = 1; },
+            Some(_) => self.column += 1,
+            None => (),
+        }
+        c
+    }
+
+    fn next(&mut self) -> ParseResult<Token> {
+        loop {
+            match self.chars.peek().cloned() {
+                Some(c) if c.is_whitespace() => { self.bump(); },
+                Some('/') => {
+                    let (line, column) = (self.line, self.column);
+                    self.bump();
+                    if self.bump() != Some('/') {
+                        return Err(ParseError { line: line, column: column,
+                                                message: "unexpected character `/`".to_string() });
+                    }
+                    while self.chars.peek().map_or(false, |&c| c != '\n') { self.bump(); }
+                },
+                _ => break,
+            }
+        }
+
+        let (line, column) = (self.line, self.column);
+        let token = |kind| Ok(Token { kind: kind, line: line, column: column });
+        match self.chars.peek().cloned() {
+            None => token(Kind::Eof),
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(c) = self.chars.peek().cloned() {
+                    if !(c.is_alphanumeric() || c == '_') { break; }
+                    ident.push(c);
+                    self.bump();
+                }
+                token(Kind::Ident(ident))
+            },
+            Some(c) if c.is_digit(10) => {
+                let mut int = 0u64;
+                while let Some(digit) = self.chars.peek().and_then(|c| c.to_digit(10)) {
+                    int = match int.checked_mul(10).and_then(|int| int.checked_add(digit as u64)) {
+                        Some(int) => int,
+                        None => return Err(ParseError { line: line, column: column,
+                                                        message: "integer too large".to_string() }),
+                    };
+                    self.bump();
+                }
+                token(Kind::Int(int))
+            },
+            Some(c) if "{}()<>,:;=".contains(c) => {
+                self.bump();
+                token(Kind::Punct(c))
+            },
+            Some(c) => Err(ParseError { line: line, column: column,
+                                        message: format!("unexpected character `{}`", c) }),
+        }
+    }
+}
+
+struct Parser<'a> {
+    lexer: Lexer<'a>,
+    peeked: Option<Token>,
+    definitions: Vec<(String, Schema)>,
+}
+
+impl<'a> Parser<'a> {
+
+    fn next(&mut self) -> ParseResult<Token> {
+        match self.peeked.take() {
+            Some(token) => Ok(token),
+            None => self.lexer.next(),
+        }
+    }
+
+    fn peek(&mut self) -> ParseResult<&Token> {
+        if self.peeked.is_none() {
+            self.peeked = Some(try!(self.lexer.next()));
+        }
+        Ok(self.peeked.as_ref().unwrap())
+    }
+
+    /// Consumes the next token if it is the punctuation character.
+    fn eat(&mut self, c: char) -> ParseResult<bool> {
+        if try!(self.peek()).kind == Kind::Punct(c) {
+            try!(self.next());
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn expect(&mut self, c: char) -> ParseResult<()> {
+        let token = try!(self.next());
+        if token.kind == Kind::Punct(c) { Ok(()) } else { Err(token.error(&format!("expected `{}`", c))) }
+    }
+
+    fn ident(&mut self, what: &str) -> ParseResult<(String, Token)> {
+        let token = try!(self.next());
+        match token.kind {
+            Kind::Ident(ref ident) => return Ok((ident.clone(), token.clone())),
+            _ => (),
+        }
+        Err(token.error(&format!("expected {}", what)))
+    }
+
+    /// Parses the name of a new definition.
+    fn definition_name(&mut self) -> ParseResult<String> {
+        let (name, token) = try!(self.ident("a type name"));
+        if is_keyword(&name) {
+            return Err(token.error("expected a type name"));
+        }
+        if self.definitions.iter().any(|definition| definition.0 == name) {
+            return Err(ParseError { line: token.line, column: token.column,
+                                    message: format!("duplicate definition of `{}`", name) });
+        }
+        Ok(name)
+    }
+
+    fn parse_struct(&mut self) -> ParseResult<()> {
+        let name = try!(self.definition_name());
+        let schema = if try!(self.eat('{')) {
+            Schema::Struct(name.clone(), try!(self.parse_fields()))
+        } else if try!(self.eat('(')) {
+            let schema = Schema::Tuple(try!(self.parse_types()));
+            try!(self.eat(';'));
+            schema
+        } else {
+            try!(self.expect(';'));
+            Schema::Tuple(Vec::new())
+        };
+        self.definitions.push((name, schema));
+        Ok(())
+    }
+
+    fn parse_enum(&mut self) -> ParseResult<()> {
+        let name = try!(self.definition_name());
+        try!(self.expect('{'));
+        let mut variants: Vec<(String, u64, Vec<Schema>)> = Vec::new();
+        while !try!(self.eat('}')) {
+            let (variant, token) = try!(self.ident("a variant name"));
+            if variants.iter().any(|v| v.0 == variant) {
+                return Err(ParseError { line: token.line, column: token.column,
+                                        message: format!("duplicate variant `{}`", variant) });
+            }
+            let fields = if try!(self.eat('(')) {
+                try!(self.parse_types())
+            } else if try!(self.eat('{')) {
+                vec!(Schema::Struct(variant.clone(), try!(self.parse_fields())))
+            } else {
+                Vec::new()
+            };
+            let tag = if try!(self.eat('=')) {
+                let token = try!(self.next());
+                match token.kind {
+                    Kind::Int(tag) => tag,
+                    _ => return Err(token.error("expected an integer tag")),
+                }
+            } else {
+                variants.len() as u64
+            };
+            if variants.iter().any(|v| v.1 == tag) {
+                return Err(ParseError { line: token.line, column: token.column,
+                                        message: format!("duplicate variant tag {}", tag) });
+            }
+            variants.push((variant, tag, fields));
+            if !try!(self.eat(',')) {
+                try!(self.expect('}'));
+                break;
+            }
+        }
+        self.definitions.push((name.clone(), Schema::Enum(name, variants)));
+        Ok(())
+    }
+
+    /// Parses named fields up to and including the closing brace.
+    fn parse_fields(&mut self) -> ParseResult<Vec<(String, Schema)>> {
+        let mut fields: Vec<(String, Schema)> = Vec::new();
+        while !try!(self.eat('}')) {
+            let (field, token) = try!(self.ident("a field name"));
+            if fields.iter().any(|f| f.0 == field) {
+                return Err(ParseError { line: token.line, column: token.column,
+                                        message: format!("duplicate field `{}`", field) });
+            }
+            try!(self.expect(':'));
+            fields.push((field, try!(self.parse_type())));
+            if !try!(self.eat(',')) {
+                try!(self.expect('}'));
+                break;
+            }
+        }
+        Ok(fields)
+    }
+
+    /// Parses comma separated types up to and including the closing parenthesis.
+    fn parse_types(&mut self) -> ParseResult<Vec<Schema>> {
+        let mut types = Vec::new();
+        while !try!(self.eat(')')) {
+            types.push(try!(self.parse_type()));
+            if !try!(self.eat(',')) {
+                try!(self.expect(')'));
+                break;
+            }
+        }
+        Ok(types)
+    }
+
+    fn parse_type(&mut self) -> ParseResult<Schema> {
+        let token = try!(self.next());
+        let schema = match token.kind {
+            Kind::Punct('(') => Schema::Tuple(try!(self.parse_types())),
+            Kind::Ident(ref ident) => match primitive(ident) {
+                Some(ty) => Schema::Scalar(ty),
+                None if ident == "option" => Schema::Option(Box::new(try!(self.parse_param()))),
+                None if ident == "option_last" => {
+                    Schema::OptionLast(Box::new(try!(self.parse_param())))
+                },
+                None if ident == "seq" => Schema::Seq(Box::new(try!(self.parse_param()))),
+                None => match self.definitions.iter().find(|definition| &definition.0 == ident) {
+                    Some(definition) => definition.1.clone(),
+                    None => return Err(ParseError { line: token.line, column: token.column,
+                                                    message: format!("unknown type `{}`", ident) }),
+                },
+            },
+            _ => return Err(token.error("expected a type")),
+        };
+        if try!(self.peek()).kind == Kind::Ident("desc".to_string()) {
+            try!(self.next());
+            Ok(Schema::Desc(Box::new(schema)))
+        } else {
+            Ok(schema)
+        }
+    }
+
+    /// Parses the type parameter of `option`, `option_last`, or `seq`.
+    fn parse_param(&mut self) -> ParseResult<Schema> {
+        try!(self.expect('<'));
+        let schema = try!(self.parse_type());
+        try!(self.expect('>'));
+        Ok(schema)
+    }
+}
+
+fn primitive(ident: &str) -> Option<Type> {
+    Some(match ident {
+        "bool" => Type::Bool,
+        "u8" => Type::U8,
+        "u16" => Type::U16,
+        "u32" => Type::U32,
+        "u64" => Type::U64,
+        "usize" => Type::Usize,
+        "i8" => Type::I8,
+        "i16" => Type::I16,
+        "i32" => Type::I32,
+        "i64" => Type::I64,
+        "isize" => Type::Isize,
+        "f32" => Type::F32,
+        "f64" => Type::F64,
+        "char" => Type::Char,
+        "str" => Type::Str,
+        "bytes" => Type::Bytes,
+        _ => return None,
+    })
+}
+
+fn is_keyword(ident: &str) -> bool {
+    primitive(ident).is_some()
+        || ["struct", "enum", "option", "option_last", "seq", "desc"].contains(&ident)
+}
+
+#[cfg(test)]
+mod test {
+
+    use {Schema, Type};
+    use super::{parse_definitions, ParseError};
+
+    fn scalar(ty: Type) -> Schema {
+        Schema::Scalar(ty)
+    }
+
+    #[test]
+    fn test_parse() {
+        let src = "
+            // A user.
+            struct UserKey { tenant: u32, name: str desc, created: i64 }
+            enum Key {
+                User(UserKey),
+                Group(u64) = 5,
+                Pair((u8, bytes), seq<option_last<char>>),
+                Deleted { at: option<i64> desc, },
+            }
+        ";
+        let user = Schema::Struct("UserKey".to_string(), vec!(
+            ("tenant".to_string(), scalar(Type::U32)),
+            ("name".to_string(), Schema::Desc(Box::new(scalar(Type::Str)))),
+            ("created".to_string(), scalar(Type::I64))));
+        let key = Schema::Enum("Key".to_string(), vec!(
+            ("User".to_string(), 0, vec!(user.clone())),
+            ("Group".to_string(), 5, vec!(scalar(Type::U64))),
+            ("Pair".to_string(), 2, vec!(
+                Schema::Tuple(vec!(scalar(Type::U8), scalar(Type::Bytes))),
+                Schema::Seq(Box::new(Schema::OptionLast(Box::new(scalar(Type::Char))))))),
+            ("Deleted".to_string(), 3, vec!(Schema::Struct("Deleted".to_string(), vec!(
+                ("at".to_string(),
+                 Schema::Desc(Box::new(Schema::Option(Box::new(scalar(Type::I64))))))))))));
+        assert_eq!(vec!(("UserKey".to_string(), user), ("Key".to_string(), key.clone())),
+                   parse_definitions(src).unwrap());
+        assert_eq!(key, Schema::parse(src).unwrap());
+    }
+
+    #[test]
+    fn test_tuple_and_unit_structs() {
+        assert_eq!(Schema::Tuple(vec!(scalar(Type::U32), scalar(Type::Str))),
+                   Schema::parse("struct Pair(u32, str);").unwrap());
+        assert_eq!(Schema::Tuple(vec!()), Schema::parse("struct Marker;").unwrap());
+    }
+
+    fn error(src: &str) -> (usize, usize, String) {
+        let ParseError { line, column, message } = Schema::parse(src).unwrap_err();
+        (line, column, message)
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!((1, 1, "empty schema".to_string()), error(" // nothing"));
+        assert_eq!((1, 20, "unknown type `string`".to_string()), error("struct Key { name: string }"));
+        assert_eq!((2, 6, "expected `:`, found `,`".to_string()), error("struct Key {\n    a, u32 }"));
+        assert_eq!((1, 21, "expected `}`, found `b`".to_string()), error("struct Key { a: u32 b: u8"));
+        assert_eq!((1, 15, "duplicate variant tag 0".to_string()), error("enum Key { A, B = 0 }"));
+        assert_eq!((1, 8, "expected a type name, found `u32`".to_string()), error("struct u32;"));
+        assert_eq!((1, 16, "unexpected character `-`".to_string()), error("enum Key { A = -1 }"));
+        assert_eq!((1, 32, "expected a type, found end of input".to_string()),
+                   error("struct Key { a: option<u32>, b:"));
+    }
+}
@@ -0,0 +1,205 @@
+//! Runtime sort specifications for multi-column keys.
+
+use std::io::Write;
+
+use {Encoder, Error, Result, Schema, Value};
+
+/// The type of a column.
+///
+/// Each type is encoded as the Rust type of the same name, except for `Bytes`, which is encoded as
+/// an `Escaped` byte string.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Type {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    Usize,
+    I8,
+    I16,
+    I32,
+    I64,
+    Isize,
+    F32,
+    F64,
+    Char,
+    Str,
+    Bytes,
+}
+
+/// The sort direction of a column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Asc,
+    Desc,
+}
+
+/// The position of null values in a nullable column, relative to its non-null values.
+///
+/// As in SQL, the position is independent of the sort direction of the column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Nulls {
+    First,
+    Last,
+}
+
+/// A column of a sort specification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Column {
+    pub ty: Type,
+    pub direction: Direction,
+    /// The position of null values, or `None` if the column is not nullable.
+    pub nulls: Option<Nulls>,
+}
+
+impl Column {
+
+    /// Creates a new column which is not nullable.
+    pub fn new(ty: Type, direction: Direction) -> Column {
+        Column { ty: ty, direction: direction, nulls: None }
+    }
+
+    /// Creates a new nullable column.
+    ///
+    /// Values of nullable columns are `Value::Option`s.
+    pub fn nullable(ty: Type, direction: Direction, nulls: Nulls) -> Column {
+        Column { ty: ty, direction: direction, nulls: Some(nulls) }
+    }
+}
+
+/// A sort specification for a multi-column key.
+///
+/// A sort specification describes, at runtime, the type and ordering of each column of a composite
+/// key, like the columns of an SQL `ORDER BY` clause.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SortSpec {
+    pub columns: Vec<Column>,
+}
+
+impl SortSpec {
+
+    /// Creates a new sort specification with the columns.
+    pub fn new(columns: Vec<Column>) -> SortSpec {
+        SortSpec { columns: columns }
+    }
+
+    /// Returns the schema of rows encoded with the sort specification.
+    ///
+    /// The schema is a tuple of the columns, and can be used to decode rows with
+    /// `decode_with_schema`.
+    pub fn schema(&self) -> Schema {
+        Schema::Tuple(self.columns.iter().map(|column| {
+            let desc = column.direction == Direction::Desc;
+            let schema = match column.nulls.map(|nulls| (nulls == Nulls::Last) != desc) {
+                None => Schema::Scalar(column.ty),
+                Some(false) => Schema::Option(Box::new(Schema::Scalar(column.ty))),
+                Some(true) => Schema::OptionLast(Box::new(Schema::Scalar(column.ty))),
+            };
+            if desc { Schema::Desc(Box::new(schema)) } else { schema }
+        }).collect())
+    }
+}
+
+/// An encoder for rows of dynamic values described by a `SortSpec`.
+///
+/// The encoded row is identical to the encoding of the equivalent typed tuple. An ascending column
+/// is encoded as the value, and a descending column as a `Desc` of the value. The value of a
+/// nullable column is an `Option` if nulls sort first and an `OptionLast` if they sort last; for
+/// descending columns this is reversed, so `DESC NULLS FIRST` is encoded as `Desc(OptionLast(..))`.
+///
+/// #### Usage
+///
+/// ```
+/// # use bytekey::{encode_key, Column, Desc, Direction, Nulls, RowEncoder, SortSpec, Type, Value};
+/// let spec = SortSpec::new(vec!(Column::new(Type::U32, Direction::Asc),
+///                               Column::nullable(Type::Str, Direction::Desc, Nulls::Last)));
+/// let encoder = RowEncoder::new(spec);
+///
+/// let row = vec!(Value::U32(7), Value::Option(Some(Box::new(Value::Str("foo".to_string())))));
+/// assert_eq!(encode_key(&(7u32, Desc(Some("foo")))).unwrap(),
+///            encoder.encode(&row).unwrap());
+/// ```
+#[derive(Clone, Debug)]
+pub struct RowEncoder {
+    spec: SortSpec,
+}
+
+impl RowEncoder {
+
+    /// Creates a new row encoder for the sort specification.
+    pub fn new(spec: SortSpec) -> RowEncoder {
+        RowEncoder { spec: spec }
+    }
+
+    /// Returns the sort specification.
+    pub fn spec(&self) -> &SortSpec {
+        &self.spec
+    }
+
+    /// Encodes a row into a byte vector.
+    ///
+    /// Returns `Error::Schema` if the row does not have a value of the correct type for every
+    /// column.
+    pub fn encode(&self, row: &[Value]) -> Result<Vec<u8>> {
+        let mut encoder = Encoder::new(Vec::new());
+        try!(self.encode_into(row, &mut encoder));
+        encoder.finish()
+    }
+
+    /// Encodes a row with the encoder.
+    pub fn encode_into<W>(&self, row: &[Value], encoder: &mut Encoder<W>) -> Result<()>
+    where W: Write {
+        if row.len() != self.spec.columns.len() {
+            return Err(Error::Schema(format!("expected a row of {} columns, found {}",
+                                             self.spec.columns.len(), row.len())));
+        }
+        self.spec.schema().encode_value(&Value::Tuple(row.to_vec()), encoder)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use {decode_with_schema, encode_key, Desc, Error, OptionLast, Value};
+    use super::{Column, Direction, Nulls, RowEncoder, SortSpec, Type};
+
+    fn option(value: Option<Value>) -> Value {
+        Value::Option(value.map(Box::new))
+    }
+
+    #[quickcheck]
+    fn check_row(a: u32, b: Option<String>, c: Option<i64>, d: isize) -> bool {
+        let spec = SortSpec::new(vec!(Column::new(Type::U32, Direction::Desc),
+                                      Column::nullable(Type::Str, Direction::Asc, Nulls::Last),
+                                      Column::nullable(Type::I64, Direction::Desc, Nulls::First),
+                                      Column::new(Type::Isize, Direction::Asc)));
+        let row = vec!(Value::U32(a),
+                       option(b.clone().map(Value::Str)),
+                       option(c.map(Value::I64)),
+                       Value::Isize(d));
+        let expected = encode_key(&(Desc(a), OptionLast(b), Desc(OptionLast(c)), d)).unwrap();
+        expected == RowEncoder::new(spec).encode(&row).unwrap()
+    }
+
+    #[test]
+    fn test_mismatch() {
+        let encoder = RowEncoder::new(SortSpec::new(vec!(Column::new(Type::U8, Direction::Asc))));
+        let rows = vec!(vec!(), vec!(Value::U16(1)), vec!(option(None)), vec!(Value::U8(1), Value::U8(2)));
+        for row in rows {
+            match encoder.encode(&row) {
+                Err(Error::Schema(_)) => (),
+                other => panic!("unexpected result for {:?}: {:?}", row, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_schema() {
+        let spec = SortSpec::new(vec!(Column::nullable(Type::Str, Direction::Desc, Nulls::First),
+                                      Column::new(Type::Bytes, Direction::Asc)));
+        let row = vec!(option(Some(Value::Str("foo".to_string()))), Value::Bytes(vec!(0, 1)));
+        let bytes = RowEncoder::new(spec.clone()).encode(&row).unwrap();
+        assert_eq!(Value::Tuple(row), decode_with_schema(&spec.schema(), &bytes).unwrap());
+    }
+}
@@ -0,0 +1,364 @@
+//! Schemas of dynamically typed keys.
+
+use std::io::{Read, Write};
+
+use rustc_serialize::Decoder as RustcDecoder;
+use rustc_serialize::Encoder as RustcEncoder;
+
+use {Decoder, Encoder, Error, Result, Type, Value};
+
+/// The layout of a key, for encoding and decoding `Value`s without compile-time types.
+///
+/// Each schema is encoded exactly as the Rust type it describes:
+///
+/// * `Scalar` is encoded as the Rust type of the same name.
+/// * `Option` is encoded as an `Option`, and `OptionLast` as an `OptionLast`.
+/// * `Desc` is encoded as a `Desc`.
+/// * `Tuple` is encoded as a tuple, and `Struct` as a struct with the named fields. Both decode to
+///   a `Value::Tuple`.
+/// * `Enum` is encoded as an enum, with each variant given by its name, tag, and fields. The tag is
+///   the declaration index of the variant, or the tag given by `#[bytekey(tag = N)]`. The fields
+///   of a struct-like variant are given as a single `Struct`.
+///
+/// Schemas can also be parsed from a textual description with `Schema::parse`.
+///
+/// `Seq` has no Rust type equivalent. Each element of a sequence is preceded by a `0x01` byte, and
+/// the sequence is terminated by a `0x00` byte, so sequences sort element by element with shorter
+/// sequences sorting before longer sequences with the same prefix.
+///
+/// #### Usage
+///
+/// ```
+/// # use bytekey::{encode, decode_with_schema, encode_value, Schema, Type, Value};
+/// let schema = Schema::Tuple(vec!(Schema::Scalar(Type::U32), Schema::Scalar(Type::Str)));
+/// let bytes = encode(&(42u32, "foo".to_string())).unwrap();
+///
+/// let value = decode_with_schema(&schema, &bytes).unwrap();
+/// assert_eq!(Value::Tuple(vec!(Value::U32(42), Value::Str("foo".to_string()))), value);
+/// assert_eq!(bytes, encode_value(&schema, &value).unwrap());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Schema {
+    Scalar(Type),
+    Option(Box<Schema>),
+    OptionLast(Box<Schema>),
+    Desc(Box<Schema>),
+    Tuple(Vec<Schema>),
+    Struct(String, Vec<(String, Schema)>),
+    Enum(String, Vec<(String, u64, Vec<Schema>)>),
+    Seq(Box<Schema>),
+}
+
+impl Schema {
+
+    /// Encodes the value with the encoder.
+    ///
+    /// Returns `Error::Schema` if the value does not match the schema.
+    pub fn encode_value<W>(&self, value: &Value, encoder: &mut Encoder<W>) -> Result<()>
+    where W: Write {
+        match (self, value) {
+            (&Schema::Scalar(ty), value) => encode_scalar(ty, value, encoder),
+            (&Schema::Option(_), &Value::Option(None)) => encoder.emit_option_none(),
+            (&Schema::Option(ref schema), &Value::Option(Some(ref value))) => {
+                encoder.emit_option_some(|encoder| schema.encode_value(value, encoder))
+            },
+            (&Schema::OptionLast(_), &Value::Option(None)) => encoder.emit_bool(true),
+            (&Schema::OptionLast(ref schema), &Value::Option(Some(ref value))) => {
+                try!(encoder.emit_bool(false));
+                schema.encode_value(value, encoder)
+            },
+            (&Schema::Desc(ref schema), value) => {
+                encoder.emit_desc(|encoder| schema.encode_value(value, encoder))
+            },
+            (&Schema::Tuple(ref schemas), &Value::Tuple(ref values)) => {
+                if schemas.len() != values.len() {
+                    return Err(Error::Schema(format!("expected a tuple of {} values, found {}",
+                                                     schemas.len(), values.len())));
+                }
+                for (idx, (schema, value)) in schemas.iter().zip(values.iter()).enumerate() {
+                    try!(encoder.emit_tuple_arg(idx, |encoder| schema.encode_value(value, encoder)));
+                }
+                Ok(())
+            },
+            (&Schema::Struct(ref name, ref fields), &Value::Tuple(ref values)) => {
+                if fields.len() != values.len() {
+                    return Err(Error::Schema(format!("expected {} values for struct {}, found {}",
+                                                     fields.len(), name, values.len())));
+                }
+                for (idx, (field, value)) in fields.iter().zip(values.iter()).enumerate() {
+                    try!(encoder.emit_struct_field(&field.0, idx, |encoder| {
+                        field.1.encode_value(value, encoder)
+                    }));
+                }
+                Ok(())
+            },
+            (&Schema::Enum(_, ref variants), &Value::Variant(tag, ref values)) => {
+                let variant = match variants.iter().find(|variant| variant.1 == tag) {
+                    Some(variant) => variant,
+                    None => return Err(Error::UnknownTag { tag: tag }),
+                };
+                if variant.2.len() != values.len() {
+                    return Err(Error::Schema(format!("expected a variant of {} values, found {}",
+                                                     variant.2.len(), values.len())));
+                }
+                encoder.emit_enum_variant(&variant.0, tag as usize, values.len(), |encoder| {
+                    for (idx, (schema, value)) in variant.2.iter().zip(values.iter()).enumerate() {
+                        try!(encoder.emit_enum_variant_arg(idx, |encoder| {
+                            schema.encode_value(value, encoder)
+                        }));
+                    }
+                    Ok(())
+                })
+            },
+            (&Schema::Seq(ref schema), &Value::Seq(ref values)) => {
+                for (idx, value) in values.iter().enumerate() {
+                    try!(encoder.emit_u8(1));
+                    try!(encoder.emit_tuple_arg(idx, |encoder| schema.encode_value(value, encoder)));
+                }
+                encoder.emit_u8(0)
+            },
+            (schema, value) => {
+                Err(Error::Schema(format!("expected a value of schema {:?}, found {:?}",
+                                          schema, value)))
+            },
+        }
+    }
+
+    /// Decodes a value from the decoder.
+    pub fn decode_value<R>(&self, decoder: &mut Decoder<R>) -> Result<Value> where R: Read {
+        match *self {
+            Schema::Scalar(ty) => decode_scalar(ty, decoder),
+            Schema::Option(ref schema) => {
+                decoder.read_option(|decoder, is_some| {
+                    if is_some {
+                        schema.decode_value(decoder).map(|value| Value::Option(Some(Box::new(value))))
+                    } else {
+                        Ok(Value::Option(None))
+                    }
+                })
+            },
+            Schema::OptionLast(ref schema) => {
+                if try!(decoder.read_bool()) {
+                    Ok(Value::Option(None))
+                } else {
+                    schema.decode_value(decoder).map(|value| Value::Option(Some(Box::new(value))))
+                }
+            },
+            Schema::Desc(ref schema) => {
+                decoder.read_desc(|decoder| decoder.by_ref(|decoder| schema.decode_value(decoder)))
+            },
+            Schema::Tuple(ref schemas) => {
+                let mut values = Vec::with_capacity(schemas.len());
+                for (idx, schema) in schemas.iter().enumerate() {
+                    values.push(try!(decoder.read_tuple_arg(idx, |decoder| schema.decode_value(decoder))));
+                }
+                Ok(Value::Tuple(values))
+            },
+            Schema::Struct(_, ref fields) => {
+                let mut values = Vec::with_capacity(fields.len());
+                for (idx, field) in fields.iter().enumerate() {
+                    values.push(try!(decoder.read_struct_field(&field.0, idx, |decoder| {
+                        field.1.decode_value(decoder)
+                    })));
+                }
+                Ok(Value::Tuple(values))
+            },
+            Schema::Enum(_, ref variants) => {
+                // The variant names indexed by tag, for tracing. Variants with a tag beyond the
+                // number of variants are traced by their tag.
+                let names: Vec<&str> = (0..variants.len() as u64).map(|tag| {
+                    variants.iter().find(|variant| variant.1 == tag).map_or("", |variant| &variant.0)
+                }).collect();
+                decoder.read_enum_variant(&names, |decoder, tag| {
+                    let tag = tag as u64;
+                    let variant = match variants.iter().find(|variant| variant.1 == tag) {
+                        Some(variant) => variant,
+                        None => return Err(Error::UnknownTag { tag: tag }),
+                    };
+                    let mut values = Vec::with_capacity(variant.2.len());
+                    for (idx, schema) in variant.2.iter().enumerate() {
+                        values.push(try!(decoder.read_enum_variant_arg(idx, |decoder| {
+                            schema.decode_value(decoder)
+                        })));
+                    }
+                    Ok(Value::Variant(tag, values))
+                })
+            },
+            Schema::Seq(ref schema) => {
+                let mut values = Vec::new();
+                loop {
+                    match try!(decoder.read_u8()) {
+                        0 => return Ok(Value::Seq(values)),
+                        1 => {
+                            let idx = values.len();
+                            values.push(try!(decoder.read_tuple_arg(idx, |decoder| {
+                                schema.decode_value(decoder)
+                            })));
+                        },
+                        _ => return Err(decoder.error("invalid sequence marker")),
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl From<Type> for Schema {
+    fn from(ty: Type) -> Schema { Schema::Scalar(ty) }
+}
+
+/// Encodes a scalar value of the type.
+pub fn encode_scalar<W>(ty: Type, value: &Value, encoder: &mut Encoder<W>) -> Result<()>
+where W: Write {
+    match (ty, value) {
+        (Type::Bool, &Value::Bool(v)) => encoder.emit_bool(v),
+        (Type::U8, &Value::U8(v)) => encoder.emit_u8(v),
+        (Type::U16, &Value::U16(v)) => encoder.emit_u16(v),
+        (Type::U32, &Value::U32(v)) => encoder.emit_u32(v),
+        (Type::U64, &Value::U64(v)) => encoder.emit_u64(v),
+        (Type::Usize, &Value::Usize(v)) => encoder.emit_usize(v),
+        (Type::I8, &Value::I8(v)) => encoder.emit_i8(v),
+        (Type::I16, &Value::I16(v)) => encoder.emit_i16(v),
+        (Type::I32, &Value::I32(v)) => encoder.emit_i32(v),
+        (Type::I64, &Value::I64(v)) => encoder.emit_i64(v),
+        (Type::Isize, &Value::Isize(v)) => encoder.emit_isize(v),
+        (Type::F32, &Value::F32(v)) => encoder.emit_f32(v),
+        (Type::F64, &Value::F64(v)) => encoder.emit_f64(v),
+        (Type::Char, &Value::Char(v)) => encoder.emit_char(v),
+        (Type::Str, &Value::Str(ref v)) => encoder.emit_str(v),
+        (Type::Bytes, &Value::Bytes(ref v)) => encoder.emit_escaped(v),
+        (ty, value) => Err(Error::Schema(format!("expected a value of type {:?}, found {:?}",
+                                                 ty, value))),
+    }
+}
+
+/// Decodes a scalar value of the type.
+pub fn decode_scalar<R>(ty: Type, decoder: &mut Decoder<R>) -> Result<Value> where R: Read {
+    Ok(match ty {
+        Type::Bool => Value::Bool(try!(decoder.read_bool())),
+        Type::U8 => Value::U8(try!(decoder.read_u8())),
+        Type::U16 => Value::U16(try!(decoder.read_u16())),
+        Type::U32 => Value::U32(try!(decoder.read_u32())),
+        Type::U64 => Value::U64(try!(decoder.read_u64())),
+        Type::Usize => Value::Usize(try!(decoder.read_usize())),
+        Type::I8 => Value::I8(try!(decoder.read_i8())),
+        Type::I16 => Value::I16(try!(decoder.read_i16())),
+        Type::I32 => Value::I32(try!(decoder.read_i32())),
+        Type::I64 => Value::I64(try!(decoder.read_i64())),
+        Type::Isize => Value::Isize(try!(decoder.read_isize())),
+        Type::F32 => Value::F32(try!(decoder.read_f32())),
+        Type::F64 => Value::F64(try!(decoder.read_f64())),
+        Type::Char => Value::Char(try!(decoder.read_char())),
+        Type::Str => Value::Str(try!(decoder.read_str())),
+        Type::Bytes => Value::Bytes(try!(decoder.read_escaped())),
+    })
+}
+
+#[cfg(test)]
+mod test {
+
+    use quickcheck::TestResult;
+
+    use {encode, encode_key, decode_with_schema, encode_value, Decoder, Desc, Encoder, Error, Escaped,
+         OptionLast, Type, Value};
+    use encoder::test::TestEnum;
+    use super::Schema;
+
+    fn scalar(ty: Type) -> Schema {
+        Schema::Scalar(ty)
+    }
+
+    fn some(value: Value) -> Value {
+        Value::Option(Some(Box::new(value)))
+    }
+
+    #[quickcheck]
+    fn check_tuple(a: u8, b: Option<i32>, c: String, d: Option<isize>, e: Vec<u8>) -> TestResult {
+        if c.contains('\0') { return TestResult::discard() }
+        let schema = Schema::Tuple(vec!(scalar(Type::U8),
+                                        Schema::Option(Box::new(scalar(Type::I32))),
+                                        Schema::Desc(Box::new(scalar(Type::Str))),
+                                        Schema::Desc(Box::new(
+                                            Schema::OptionLast(Box::new(scalar(Type::Isize))))),
+                                        scalar(Type::Bytes)));
+        let bytes = encode_key(&(a, b, Desc(&c), Desc(OptionLast(d)), Escaped(&e))).unwrap();
+        let value = Value::Tuple(vec!(Value::U8(a),
+                                      Value::Option(b.map(|b| Box::new(Value::I32(b)))),
+                                      Value::Str(c),
+                                      Value::Option(d.map(|d| Box::new(Value::Isize(d)))),
+                                      Value::Bytes(e)));
+        TestResult::from_bool(value == decode_with_schema(&schema, &bytes).unwrap()
+            && bytes == encode_value(&schema, &value).unwrap())
+    }
+
+    #[test]
+    fn test_enum() {
+        let schema = Schema::Enum("TestEnum".to_string(),
+                                  vec!(("A".to_string(), 0, vec!(scalar(Type::U32), scalar(Type::Str))),
+                                       ("B".to_string(), 1, vec!()),
+                                       ("C".to_string(), 2, vec!(scalar(Type::Isize)))));
+        let bytes = encode(&TestEnum::A(7, "foo".to_string())).unwrap();
+        let value = Value::Variant(0, vec!(Value::U32(7), Value::Str("foo".to_string())));
+        assert_eq!(value, decode_with_schema(&schema, &bytes).unwrap());
+        assert_eq!(bytes, encode_value(&schema, &value).unwrap());
+
+        let schema = Schema::Enum("TestEnum".to_string(), vec!(("B".to_string(), 1, vec!())));
+        match decode_with_schema(&schema, &bytes) {
+            Err(Error::UnknownTag { tag: 0 }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_seq() {
+        let schema = Schema::Seq(Box::new(scalar(Type::Str)));
+        let seqs = vec!(vec!(), vec!("a"), vec!("a", ""), vec!("a", "b"), vec!("b"));
+        let encoded = seqs.iter().map(|seq| {
+            let value = Value::Seq(seq.iter().map(|s| Value::Str(s.to_string())).collect());
+            let bytes = encode_value(&schema, &value).unwrap();
+            assert_eq!(value, decode_with_schema(&schema, &bytes).unwrap());
+            bytes
+        }).collect::<Vec<_>>();
+        for pair in encoded.windows(2) {
+            assert!(pair[0] < pair[1]);
+        }
+    }
+
+    #[test]
+    fn test_nested_desc() {
+        let schema = Schema::Desc(Box::new(Schema::Tuple(vec!(
+            scalar(Type::U8), Schema::Desc(Box::new(Schema::Option(Box::new(scalar(Type::Str)))))))));
+        let value = Value::Tuple(vec!(Value::U8(1), some(Value::Str("foo".to_string()))));
+        let bytes = encode_value(&schema, &value).unwrap();
+        assert_eq!(encode_key(&Desc((1u8, Desc(Some("foo"))))).unwrap(), bytes);
+        assert_eq!(value, decode_with_schema(&schema, &bytes).unwrap());
+    }
+
+    #[test]
+    fn test_nulls_last() {
+        let schema = Schema::Option(Box::new(scalar(Type::U8)));
+        for &value in [None, Some(7u8)].iter() {
+            let mut encoder = Encoder::new(Vec::new());
+            encoder.set_nulls_last(true);
+            let option = value.map_or(Value::Option(None), |v| some(Value::U8(v)));
+            schema.encode_value(&option, &mut encoder).unwrap();
+            let bytes = encoder.finish().unwrap();
+            assert_eq!(encode_key(&OptionLast(value)).unwrap(), bytes);
+
+            let mut decoder = Decoder::new(&bytes[..]);
+            decoder.set_nulls_last(true);
+            assert_eq!(option, schema.decode_value(&mut decoder).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_mismatch() {
+        let schema = Schema::Tuple(vec!(scalar(Type::U8)));
+        for value in vec!(Value::U8(1), Value::Tuple(vec!()), Value::Tuple(vec!(Value::U16(1)))) {
+            match encode_value(&schema, &value) {
+                Err(Error::Schema(_)) => (),
+                other => panic!("unexpected result for {:?}: {:?}", value, other),
+            }
+        }
+    }
+}
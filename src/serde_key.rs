@@ -0,0 +1,518 @@
+//! `serde` support.
+//!
+//! `Encoder` implements `serde::Serializer` and `Decoder` implements `serde::Deserializer`, with
+//! the same format as the `rustc-serialize` implementations, with one exception: the `serde` data
+//! model has no `usize` or `isize`, so they are serialized as `u64` and `i64`, and are encoded with
+//! a fixed width rather than the variable-length encoding of `emit_usize` and `emit_isize`. The
+//! `Serde` adapter uses them to implement `KeyEncode` and `KeyDecode` for any `serde` type.
+
+use std::fmt::Display;
+use std::io::{self, Read, Write};
+
+use rustc_serialize::Decoder as RustcDecoder;
+use rustc_serialize::Encoder as RustcEncoder;
+use serde::{de, ser, Serialize};
+use serde::de::{DeserializeOwned, IntoDeserializer};
+
+use {Decoder, Encoder, Error, KeyDecode, KeyEncode, Result};
+
+/// An adapter which encodes and decodes a `serde` type as a key.
+///
+/// Sequences and maps are not supported, as with `rustc-serialize`. Byte strings serialized with
+/// `serialize_bytes` are encoded like `Escaped` byte strings, and `usize` and `isize` values are
+/// encoded like `u64` and `i64` values.
+///
+/// #### Usage
+///
+/// ```
+/// # extern crate bytekey;
+/// # #[macro_use] extern crate serde_derive;
+/// # use bytekey::{decode_key, encode_key, Serde};
+/// #[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// struct MyKey { a: u32, b: String }
+///
+/// # fn main() {
+/// let key = Serde(MyKey { a: 1, b: "foo".to_string() });
+/// assert_eq!(encode_key(&(1u32, "foo")).unwrap(), encode_key(&key).unwrap());
+/// assert_eq!(key, decode_key(&encode_key(&key).unwrap()).unwrap());
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Serde<T>(pub T);
+
+impl<T> KeyEncode for Serde<T> where T: Serialize {
+    fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+        self.0.serialize(encoder)
+    }
+}
+
+impl<T> KeyDecode for Serde<T> where T: DeserializeOwned {
+    fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<Serde<T>> where R: Read {
+        T::deserialize(decoder).map(Serde)
+    }
+}
+
+impl ser::Error for Error {
+    fn custom<T>(msg: T) -> Error where T: Display {
+        Error::Io(io::Error::new(io::ErrorKind::Other, msg.to_string()))
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T>(msg: T) -> Error where T: Display {
+        Error::Io(io::Error::new(io::ErrorKind::Other, msg.to_string()))
+    }
+}
+
+fn unsupported(what: &str) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::Other, format!("{} are not supported", what)))
+}
+
+impl<'a, W> ser::Serializer for &'a mut Encoder<W> where W: Write {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = ser::Impossible<(), Error>;
+    type SerializeTuple = Compound<'a, W>;
+    type SerializeTupleStruct = Compound<'a, W>;
+    type SerializeTupleVariant = Compound<'a, W>;
+    type SerializeMap = ser::Impossible<(), Error>;
+    type SerializeStruct = Compound<'a, W>;
+    type SerializeStructVariant = Compound<'a, W>;
+
+    fn serialize_bool(self, v: bool) -> Result<()> { self.emit_bool(v) }
+    fn serialize_i8(self, v: i8) -> Result<()> { self.emit_i8(v) }
+    fn serialize_i16(self, v: i16) -> Result<()> { self.emit_i16(v) }
+    fn serialize_i32(self, v: i32) -> Result<()> { self.emit_i32(v) }
+    fn serialize_i64(self, v: i64) -> Result<()> { self.emit_i64(v) }
+    fn serialize_u8(self, v: u8) -> Result<()> { self.emit_u8(v) }
+    fn serialize_u16(self, v: u16) -> Result<()> { self.emit_u16(v) }
+    fn serialize_u32(self, v: u32) -> Result<()> { self.emit_u32(v) }
+    fn serialize_u64(self, v: u64) -> Result<()> { self.emit_u64(v) }
+    fn serialize_f32(self, v: f32) -> Result<()> { self.emit_f32(v) }
+    fn serialize_f64(self, v: f64) -> Result<()> { self.emit_f64(v) }
+    fn serialize_char(self, v: char) -> Result<()> { self.emit_char(v) }
+    fn serialize_str(self, v: &str) -> Result<()> { self.emit_str(v) }
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> { self.emit_escaped(v) }
+
+    fn serialize_none(self) -> Result<()> {
+        self.emit_option_none()
+    }
+    fn serialize_some<T>(self, value: &T) -> Result<()> where T: ?Sized + Serialize {
+        self.emit_option_some(|encoder| value.serialize(encoder))
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        self.emit_nil()
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        self.emit_nil()
+    }
+    fn serialize_unit_variant(self,
+                              _name: &'static str,
+                              variant_index: u32,
+                              variant: &'static str)
+                              -> Result<()> {
+        self.emit_enum_variant(variant, variant_index as usize, 0, |_| Ok(()))
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<()>
+    where T: ?Sized + Serialize {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T>(self,
+                                    _name: &'static str,
+                                    variant_index: u32,
+                                    variant: &'static str,
+                                    value: &T)
+                                    -> Result<()>
+    where T: ?Sized + Serialize {
+        self.emit_enum_variant(variant, variant_index as usize, 1, |encoder| {
+            encoder.emit_enum_variant_arg(0, |encoder| value.serialize(encoder))
+        })
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(unsupported("sequences"))
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(unsupported("maps"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Compound<'a, W>> {
+        Ok(Compound { encoder: self, idx: 0 })
+    }
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Compound<'a, W>> {
+        Ok(Compound { encoder: self, idx: 0 })
+    }
+    fn serialize_tuple_variant(self,
+                               _name: &'static str,
+                               variant_index: u32,
+                               variant: &'static str,
+                               len: usize)
+                               -> Result<Compound<'a, W>> {
+        try!(self.emit_enum_variant(variant, variant_index as usize, len, |_| Ok(())));
+        Ok(Compound { encoder: self, idx: 0 })
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Compound<'a, W>> {
+        Ok(Compound { encoder: self, idx: 0 })
+    }
+    fn serialize_struct_variant(self,
+                                _name: &'static str,
+                                variant_index: u32,
+                                variant: &'static str,
+                                len: usize)
+                                -> Result<Compound<'a, W>> {
+        try!(self.emit_enum_variant(variant, variant_index as usize, len, |_| Ok(())));
+        Ok(Compound { encoder: self, idx: 0 })
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Serializes the fields of a tuple, struct, or enum variant.
+pub struct Compound<'a, W: 'a + Write> {
+    encoder: &'a mut Encoder<W>,
+    idx: usize,
+}
+
+impl<'a, W> Compound<'a, W> where W: Write {
+    fn next_idx(&mut self) -> usize {
+        self.idx += 1;
+        self.idx - 1
+    }
+}
+
+impl<'a, W> ser::SerializeTuple for Compound<'a, W> where W: Write {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()> where T: ?Sized + Serialize {
+        let idx = self.next_idx();
+        self.encoder.emit_tuple_arg(idx, |encoder| value.serialize(encoder))
+    }
+
+    fn end(self) -> Result<()> { Ok(()) }
+}
+
+impl<'a, W> ser::SerializeTupleStruct for Compound<'a, W> where W: Write {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()> where T: ?Sized + Serialize {
+        let idx = self.next_idx();
+        self.encoder.emit_tuple_struct_arg(idx, |encoder| value.serialize(encoder))
+    }
+
+    fn end(self) -> Result<()> { Ok(()) }
+}
+
+impl<'a, W> ser::SerializeTupleVariant for Compound<'a, W> where W: Write {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()> where T: ?Sized + Serialize {
+        let idx = self.next_idx();
+        self.encoder.emit_enum_variant_arg(idx, |encoder| value.serialize(encoder))
+    }
+
+    fn end(self) -> Result<()> { Ok(()) }
+}
+
+impl<'a, W> ser::SerializeStruct for Compound<'a, W> where W: Write {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where T: ?Sized + Serialize {
+        let idx = self.next_idx();
+        self.encoder.emit_struct_field(key, idx, |encoder| value.serialize(encoder))
+    }
+
+    fn end(self) -> Result<()> { Ok(()) }
+}
+
+impl<'a, W> ser::SerializeStructVariant for Compound<'a, W> where W: Write {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where T: ?Sized + Serialize {
+        let idx = self.next_idx();
+        self.encoder.emit_enum_struct_variant_field(key, idx, |encoder| value.serialize(encoder))
+    }
+
+    fn end(self) -> Result<()> { Ok(()) }
+}
+
+impl<'de, 'a, R> de::Deserializer<'de> for &'a mut Decoder<R> where R: Read {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        Err(unsupported("self-describing values"))
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        visitor.visit_bool(try!(self.read_bool()))
+    }
+    fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        visitor.visit_i8(try!(self.read_i8()))
+    }
+    fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        visitor.visit_i16(try!(self.read_i16()))
+    }
+    fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        visitor.visit_i32(try!(self.read_i32()))
+    }
+    fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        visitor.visit_i64(try!(self.read_i64()))
+    }
+    fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        visitor.visit_u8(try!(self.read_u8()))
+    }
+    fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        visitor.visit_u16(try!(self.read_u16()))
+    }
+    fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        visitor.visit_u32(try!(self.read_u32()))
+    }
+    fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        visitor.visit_u64(try!(self.read_u64()))
+    }
+    fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        visitor.visit_f32(try!(self.read_f32()))
+    }
+    fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        visitor.visit_f64(try!(self.read_f64()))
+    }
+    fn deserialize_char<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        visitor.visit_char(try!(self.read_char()))
+    }
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        visitor.visit_string(try!(self.read_str()))
+    }
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        visitor.visit_string(try!(self.read_str()))
+    }
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        visitor.visit_byte_buf(try!(self.read_escaped()))
+    }
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        visitor.visit_byte_buf(try!(self.read_escaped()))
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        let mut visitor = Some(visitor);
+        self.read_option(|decoder, is_some| {
+            let visitor = visitor.take().unwrap();
+            if is_some { visitor.visit_some(decoder) } else { visitor.visit_none() }
+        })
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        visitor.visit_unit()
+    }
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where V: de::Visitor<'de> {
+        visitor.visit_unit()
+    }
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where V: de::Visitor<'de> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V>(self, _visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        Err(unsupported("sequences"))
+    }
+    fn deserialize_map<V>(self, _visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        Err(unsupported("maps"))
+    }
+
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value>
+    where V: de::Visitor<'de> {
+        visitor.visit_seq(Elements { decoder: self, fields: None, idx: 0, len: len })
+    }
+    fn deserialize_tuple_struct<V>(self,
+                                   _name: &'static str,
+                                   len: usize,
+                                   visitor: V)
+                                   -> Result<V::Value>
+    where V: de::Visitor<'de> {
+        visitor.visit_seq(Elements { decoder: self, fields: None, idx: 0, len: len })
+    }
+    fn deserialize_struct<V>(self,
+                             _name: &'static str,
+                             fields: &'static [&'static str],
+                             visitor: V)
+                             -> Result<V::Value>
+    where V: de::Visitor<'de> {
+        let len = fields.len();
+        visitor.visit_seq(Elements { decoder: self, fields: Some(fields), idx: 0, len: len })
+    }
+
+    fn deserialize_enum<V>(self,
+                           _name: &'static str,
+                           variants: &'static [&'static str],
+                           visitor: V)
+                           -> Result<V::Value>
+    where V: de::Visitor<'de> {
+        visitor.visit_enum(Variant { decoder: self, variants: variants })
+    }
+
+    fn deserialize_identifier<V>(self, _visitor: V) -> Result<V::Value>
+    where V: de::Visitor<'de> {
+        Err(unsupported("identifiers"))
+    }
+    fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value>
+    where V: de::Visitor<'de> {
+        Err(unsupported("ignored values"))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Deserializes the fields of a tuple, struct, or enum variant.
+struct Elements<'a, R: 'a> {
+    decoder: &'a mut Decoder<R>,
+    fields: Option<&'static [&'static str]>,
+    idx: usize,
+    len: usize,
+}
+
+impl<'de, 'a, R> de::SeqAccess<'de> for Elements<'a, R> where R: Read {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where T: de::DeserializeSeed<'de> {
+        if self.idx == self.len { return Ok(None); }
+        let idx = self.idx;
+        self.idx += 1;
+        let value = match self.fields {
+            Some(fields) => {
+                try!(self.decoder.read_struct_field(fields[idx], idx, |decoder| {
+                    seed.deserialize(decoder)
+                }))
+            },
+            None => try!(self.decoder.read_tuple_arg(idx, |decoder| seed.deserialize(decoder))),
+        };
+        Ok(Some(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len - self.idx)
+    }
+}
+
+/// Deserializes an enum variant.
+struct Variant<'a, R: 'a> {
+    decoder: &'a mut Decoder<R>,
+    variants: &'static [&'static str],
+}
+
+impl<'de, 'a, R> de::EnumAccess<'de> for Variant<'a, R> where R: Read {
+    type Error = Error;
+    type Variant = Variant<'a, R>;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Variant<'a, R>)>
+    where V: de::DeserializeSeed<'de> {
+        let tag = try!(self.decoder.read_var_u64());
+        if tag >= self.variants.len() as u64 {
+            return Err(Error::UnknownTag { tag: tag });
+        }
+        let tag: de::value::U32Deserializer<Error> = (tag as u32).into_deserializer();
+        let value = try!(seed.deserialize(tag));
+        Ok((value, self))
+    }
+}
+
+impl<'de, 'a, R> de::VariantAccess<'de> for Variant<'a, R> where R: Read {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where T: de::DeserializeSeed<'de> {
+        self.decoder.read_enum_variant_arg(0, |decoder| seed.deserialize(decoder))
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value>
+    where V: de::Visitor<'de> {
+        visitor.visit_seq(Elements { decoder: self.decoder, fields: None, idx: 0, len: len })
+    }
+
+    fn struct_variant<V>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    where V: de::Visitor<'de> {
+        visitor.visit_seq(Elements {
+            decoder: self.decoder,
+            fields: Some(fields),
+            idx: 0,
+            len: fields.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::result;
+
+    use quickcheck::TestResult;
+
+    use {decode_key, encode_key, Error, Rustc};
+    use super::Serde;
+
+    #[derive(Serialize)]
+    struct Counts {
+        len: usize,
+        delta: isize,
+    }
+
+    #[quickcheck]
+    fn check_tuple(val: (u8, i16, u32, i64, bool, char, String, Option<String>)) -> TestResult {
+        if val.6.contains('\0') || val.7.as_ref().map_or(false, |s| s.contains('\0')) {
+            return TestResult::discard()
+        }
+        let serde = encode_key(&Serde(&val)).unwrap();
+        TestResult::from_bool(serde == encode_key(&Rustc(&val)).unwrap()
+            && Serde(val) == decode_key(&serde).unwrap())
+    }
+
+    #[quickcheck]
+    fn check_floats(val: (f32, f64)) -> bool {
+        encode_key(&Serde(&val)).unwrap() == encode_key(&Rustc(&val)).unwrap()
+    }
+
+    #[test]
+    fn test_enum() {
+        let ok: result::Result<u8, String> = Ok(7);
+        let err: result::Result<u8, String> = Err("a".to_string());
+        assert_eq!(vec!(0x00, 0x07), encode_key(&Serde(&ok)).unwrap());
+        assert_eq!(vec!(0x01, 0x61, 0x00), encode_key(&Serde(&err)).unwrap());
+        assert_eq!(Serde(ok), decode_key(&[0x00, 0x07]).unwrap());
+        assert_eq!(Serde(err), decode_key(&[0x01, 0x61, 0x00]).unwrap());
+        match decode_key::<Serde<result::Result<u8, String>>>(&[0x02, 0x07]) {
+            Err(Error::UnknownTag { tag: 2 }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_usize() {
+        // A struct is encoded as its fields, so `Rustc` encodes `Counts` like the tuple.
+        let serde = encode_key(&Serde(Counts { len: 1, delta: -1 })).unwrap();
+        assert_eq!(encode_key(&(1u64, -1i64)).unwrap(), serde);
+        assert_eq!(vec!(0x01, 0x7F), encode_key(&Rustc((1usize, -1isize))).unwrap());
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert!(encode_key(&Serde(vec!(1u8, 2))).is_err());
+        assert!(decode_key::<Serde<Vec<u8>>>(&[0x00]).is_err());
+    }
+}
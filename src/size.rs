@@ -0,0 +1,268 @@
+//! Encoded size computation.
+//!
+//! `SizeEncoder` runs the encoding of a value without producing any output, which allows the exact
+//! size of a key to be known before a buffer is allocated for it (see `encoded_len`).
+
+use rustc_serialize;
+
+use Error;
+use Result;
+use util::error;
+
+/// An encoder which computes the encoded size of a value without writing it.
+///
+/// `SizeEncoder` mirrors every `emit_*` method of `Encoder`, but only counts the number of bytes
+/// which would have been written. It never allocates.
+///
+/// Sequences and maps have no encoding, so counting one returns an error rather than panicking.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SizeEncoder {
+    len: usize,
+}
+
+impl SizeEncoder {
+
+    /// Creates a new size encoder with a length of 0.
+    pub fn new() -> SizeEncoder {
+        SizeEncoder { len: 0 }
+    }
+
+    /// Returns the number of bytes counted so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no bytes have been counted.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Count the encoded size of a `u64` encoded with `Encoder::emit_var_u64`.
+    pub fn emit_var_u64(&mut self, val: u64) -> Result<()> {
+        self.len += var_u64_len(val);
+        Ok(())
+    }
+
+    /// Count the encoded size of an `i64` encoded with `Encoder::emit_var_i64`.
+    pub fn emit_var_i64(&mut self, val: i64) -> Result<()> {
+        self.len += var_i64_len(val);
+        Ok(())
+    }
+}
+
+/// Returns the number of bytes `Encoder::emit_var_u64` writes for the value.
+pub fn var_u64_len(val: u64) -> usize {
+    if val < 1 << 4 { 1 }
+    else if val < 1 << 12 { 2 }
+    else if val < 1 << 20 { 3 }
+    else if val < 1 << 28 { 4 }
+    else if val < 1 << 36 { 5 }
+    else if val < 1 << 44 { 6 }
+    else if val < 1 << 52 { 7 }
+    else if val < 1 << 60 { 8 }
+    else { 9 }
+}
+
+/// Returns the number of bytes `Encoder::emit_var_i64` writes for the value.
+pub fn var_i64_len(v: i64) -> usize {
+    let mask = (v >> 63) as u64;
+    let val = v.wrapping_abs() as u64 - (1 & mask);
+    if val < 1 << 3 { 1 }
+    else if val < 1 << 11 { 2 }
+    else if val < 1 << 19 { 3 }
+    else if val < 1 << 27 { 4 }
+    else if val < 1 << 35 { 5 }
+    else if val < 1 << 43 { 6 }
+    else if val < 1 << 51 { 7 }
+    else if val < 1 << 59 { 8 }
+    else { 9 }
+}
+
+impl rustc_serialize::Encoder for SizeEncoder {
+
+    type Error = Error;
+
+    fn emit_nil(&mut self) -> Result<()> { Ok(()) }
+
+    fn emit_u8(&mut self, _v: u8) -> Result<()> { self.len += 1; Ok(()) }
+    fn emit_u16(&mut self, _v: u16) -> Result<()> { self.len += 2; Ok(()) }
+    fn emit_u32(&mut self, _v: u32) -> Result<()> { self.len += 4; Ok(()) }
+    fn emit_u64(&mut self, _v: u64) -> Result<()> { self.len += 8; Ok(()) }
+    fn emit_usize(&mut self, v: usize) -> Result<()> { self.emit_var_u64(v as u64) }
+
+    fn emit_i8(&mut self, _v: i8) -> Result<()> { self.len += 1; Ok(()) }
+    fn emit_i16(&mut self, _v: i16) -> Result<()> { self.len += 2; Ok(()) }
+    fn emit_i32(&mut self, _v: i32) -> Result<()> { self.len += 4; Ok(()) }
+    fn emit_i64(&mut self, _v: i64) -> Result<()> { self.len += 8; Ok(()) }
+    fn emit_isize(&mut self, v: isize) -> Result<()> { self.emit_var_i64(v as i64) }
+
+    fn emit_bool(&mut self, _v: bool) -> Result<()> { self.len += 1; Ok(()) }
+
+    fn emit_f32(&mut self, _v: f32) -> Result<()> { self.len += 4; Ok(()) }
+    fn emit_f64(&mut self, _v: f64) -> Result<()> { self.len += 8; Ok(()) }
+
+    fn emit_char(&mut self, v: char) -> Result<()> { self.len += v.len_utf8(); Ok(()) }
+
+    fn emit_str(&mut self, v: &str) -> Result<()> { self.len += v.len() + 1; Ok(()) }
+
+    fn emit_enum<F>(&mut self, _name: &str, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+    fn emit_enum_variant<F>(&mut self,
+                            _name: &str,
+                            id: usize,
+                            _len: usize,
+                            f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        try!(self.emit_usize(id));
+        f(self)
+    }
+    fn emit_enum_variant_arg<F>(&mut self,
+                                _idx: usize,
+                                f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+    fn emit_enum_struct_variant<F>(&mut self,
+                                   _name: &str,
+                                   id: usize,
+                                   _len: usize,
+                                   f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        try!(self.emit_usize(id));
+        f(self)
+    }
+    fn emit_enum_struct_variant_field<F>(&mut self,
+                                         _name: &str,
+                                         _idx: usize,
+                                         f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+
+    fn emit_struct<F>(&mut self, _name: &str, _len: usize, f: F)
+                      -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+    fn emit_struct_field<F>(&mut self, _name: &str, _idx: usize, f: F)
+                            -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+
+    fn emit_tuple<F>(&mut self, _len: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+    fn emit_tuple_arg<F>(&mut self, _idx: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+    fn emit_tuple_struct<F>(&mut self,
+                            name: &str,
+                            len: usize,
+                            f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        self.emit_struct(name, len, f)
+    }
+    fn emit_tuple_struct_arg<F>(&mut self,
+                                idx: usize,
+                                f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        self.emit_struct_field("", idx, f)
+    }
+
+    fn emit_option<F>(&mut self, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+    fn emit_option_none(&mut self) -> Result<()> {
+        self.emit_bool(false)
+    }
+    fn emit_option_some<F>(&mut self, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        try!(self.emit_bool(true));
+        f(self)
+    }
+
+    fn emit_seq<F>(&mut self, _len: usize, _f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        Err(error("sequences are not supported"))
+    }
+    fn emit_seq_elt<F>(&mut self, _idx: usize, _f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        Err(error("sequences are not supported"))
+    }
+
+    fn emit_map<F>(&mut self, _len: usize, _f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        Err(error("maps are not supported"))
+    }
+    fn emit_map_elt_key<F>(&mut self, _idx: usize, _f: F) -> Result<()> {
+        Err(error("maps are not supported"))
+    }
+    fn emit_map_elt_val<F>(&mut self, _idx: usize, _f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        Err(error("maps are not supported"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::collections::BTreeMap;
+
+    use {encode, encoded_len};
+    use encoder::test::{TestStruct, TestEnum};
+    use {Array, FixedStr, Natural};
+
+    #[quickcheck]
+    fn check_usize(val: usize) -> bool {
+        encode(&val).unwrap().len() == encoded_len(&val).unwrap()
+    }
+
+    #[quickcheck]
+    fn check_isize(val: isize) -> bool {
+        encode(&val).unwrap().len() == encoded_len(&val).unwrap()
+    }
+
+    #[quickcheck]
+    fn check_char(val: char) -> bool {
+        encode(&val).unwrap().len() == encoded_len(&val).unwrap()
+    }
+
+    #[quickcheck]
+    fn check_option(val: Option<String>) -> bool {
+        encode(&val).unwrap().len() == encoded_len(&val).unwrap()
+    }
+
+    #[quickcheck]
+    fn check_struct(val: TestStruct) -> bool {
+        encode(&val).unwrap().len() == encoded_len(&val).unwrap()
+    }
+
+    #[quickcheck]
+    fn check_enum(val: TestEnum) -> bool {
+        encode(&val).unwrap().len() == encoded_len(&val).unwrap()
+    }
+
+    #[quickcheck]
+    fn check_natural(val: String) -> bool {
+        let val = Natural(val);
+        encode(&val).unwrap().len() == encoded_len(&val).unwrap()
+    }
+
+    #[test]
+    fn test_wrappers() {
+        let values = (Array([1u32, 2, 3]), FixedStr::<[u8; 8]>::new("foo").unwrap());
+        assert_eq!(encode(&values).unwrap().len(), encoded_len(&values).unwrap());
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert!(encoded_len(&vec!(1u8, 2)).is_err());
+        assert!(encoded_len(&BTreeMap::<u8, u8>::new()).is_err());
+    }
+}
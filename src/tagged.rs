@@ -0,0 +1,771 @@
+//! A self-describing encoding, in which every value is prefixed by a type code.
+//!
+//! The default encoding does not serialize type information, so keys can only be decoded with
+//! the exact type they were encoded with, and adding or removing a struct field changes the
+//! meaning of every key. The tagged encoding prefixes each value with a type code, so keys can be
+//! decoded to a `Value` without knowing their type, and keys of different types can share a
+//! keyspace. The type codes give a well-defined order between types:
+//!
+//! <table>
+//!     <tr><th>code</th><th>type</th><th>Rust types</th><th>decoded <code>Value</code></th></tr>
+//!     <tr><td>0x01</td><td>nil</td><td><code>None</code>, <code>()</code></td><td><code>Option(None)</code></td></tr>
+//!     <tr><td>0x02</td><td>bytes</td><td></td><td><code>Bytes</code></td></tr>
+//!     <tr><td>0x03</td><td>string</td><td><code>String</code>, <code>char</code></td><td><code>Str</code></td></tr>
+//!     <tr><td>0x04, 0x05</td><td>int</td><td>integers</td><td><code>I64</code>, <code>U64</code></td></tr>
+//!     <tr><td>0x06</td><td>float</td><td><code>f32</code>, <code>f64</code></td><td><code>F64</code></td></tr>
+//!     <tr><td>0x07, 0x08</td><td>bool</td><td><code>bool</code></td><td><code>Bool</code></td></tr>
+//!     <tr><td>0x09</td><td>tuple</td><td>tuples, structs, enums, <code>Vec</code></td><td><code>Tuple</code></td></tr>
+//! </table>
+//!
+//! Negative integers are tagged `0x04` and encoded with `Encoder::emit_var_i64`, and non-negative
+//! integers are tagged `0x05` and encoded with `Encoder::emit_var_u64`, so integers of every width
+//! share one encoding and compare by value. Floats are widened to `f64`. Bytes and strings are
+//! escaped as with `Encoder::emit_escaped`, so they may contain null bytes. Tuples are terminated
+//! by a `0x00` byte, which sorts before every type code, so a tuple sorts before any tuple it is a
+//! prefix of.
+//!
+//! `Some` values are encoded as the wrapped value, and so a `Some` value which wraps nil, such as
+//! `Some(None)` or `Some(())`, can not be distinguished from `None` and is rejected. Enum variants
+//! are encoded as a tuple of the variant index and the variant's fields. Maps are not supported.
+//!
+//! #### Usage
+//!
+//! ```
+//! # use bytekey::Value;
+//! # use bytekey::tagged::{decode, decode_value, encode};
+//! let key = encode(&("users", 42u32)).unwrap();
+//! assert_eq!(vec!(0x09, 0x03, b'u', b's', b'e', b'r', b's', 0x00, 0x01, 0x05, 0x10, 0x2A, 0x00), key);
+//! assert_eq!(("users".to_string(), 42u64), decode(&key).unwrap());
+//! assert_eq!(Value::Tuple(vec!(Value::Str("users".to_string()), Value::U64(42))),
+//!            decode_value(&key).unwrap());
+//! ```
+
+use std::{i8, i16, i32, i64, u8, u16, u32};
+
+use rustc_serialize::{self, Decodable, Encodable};
+
+use {Error, Result, Value};
+use util::{error, single_char, Int};
+
+const END: u8 = 0x00;
+const NIL: u8 = 0x01;
+const BYTES: u8 = 0x02;
+const STRING: u8 = 0x03;
+const NEGATIVE: u8 = 0x04;
+const POSITIVE: u8 = 0x05;
+const FLOAT: u8 = 0x06;
+const FALSE: u8 = 0x07;
+const TRUE: u8 = 0x08;
+const TUPLE: u8 = 0x09;
+
+/// Encodes a value with type codes.
+pub fn encode<T>(value: &T) -> Result<Vec<u8>> where T: Encodable {
+    let mut encoder = Encoder { buf: Vec::new() };
+    try!(value.encode(&mut encoder));
+    Ok(encoder.buf)
+}
+
+/// Decodes a value encoded with type codes.
+///
+/// The value may be decoded as any type with the same shape as the encoded type. Integers and
+/// floats decode as any width which can hold the encoded value.
+pub fn decode<T>(bytes: &[u8]) -> Result<T> where T: Decodable {
+    let mut decoder = Decoder { bytes: bytes, pos: 0 };
+    let value = try!(Decodable::decode(&mut decoder));
+    try!(decoder.finish());
+    Ok(value)
+}
+
+/// Encodes a dynamically typed value with type codes.
+///
+/// `Option(None)` encodes as nil, `Seq` and `Variant` values as tuples, and `Char` as a string.
+/// `Option(Some)` encodes as the wrapped value, which must not be nil.
+pub fn encode_value(value: &Value) -> Result<Vec<u8>> {
+    let mut encoder = Encoder { buf: Vec::new() };
+    try!(encoder.write_value(value));
+    Ok(encoder.buf)
+}
+
+/// Decodes a value encoded with type codes, without knowing its type.
+pub fn decode_value(bytes: &[u8]) -> Result<Value> {
+    let mut decoder = Decoder { bytes: bytes, pos: 0 };
+    let value = try!(decoder.read_value());
+    try!(decoder.finish());
+    Ok(value)
+}
+
+/// An encoder which prefixes every value with its type code.
+struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+
+    /// Writes primitive values with the untagged encoder.
+    fn untagged<F>(&mut self, tag: u8, f: F) -> Result<()>
+    where F: FnOnce(&mut ::Encoder<&mut Vec<u8>>) -> Result<()> {
+        self.buf.push(tag);
+        f(&mut ::Encoder::new(&mut self.buf))
+    }
+
+    /// Writes the value wrapped by a `Some`, which is rejected if it would decode as `None`.
+    fn write_some<F>(&mut self, f: F) -> Result<()> where F: FnOnce(&mut Encoder) -> Result<()> {
+        let start = self.buf.len();
+        try!(f(self));
+        if self.buf.get(start) == Some(&NIL) {
+            return Err(error("Some wrapping nil can not be distinguished from None"));
+        }
+        Ok(())
+    }
+
+    fn write_unsigned(&mut self, v: u64) -> Result<()> {
+        self.untagged(POSITIVE, |encoder| encoder.emit_var_u64(v))
+    }
+
+    fn write_signed(&mut self, v: i64) -> Result<()> {
+        if v < 0 {
+            self.untagged(NEGATIVE, |encoder| encoder.emit_var_i64(v))
+        } else {
+            self.write_unsigned(v as u64)
+        }
+    }
+
+    fn write_tuple<F>(&mut self, f: F) -> Result<()> where F: FnOnce(&mut Encoder) -> Result<()> {
+        self.buf.push(TUPLE);
+        try!(f(self));
+        self.buf.push(END);
+        Ok(())
+    }
+
+    fn write_value(&mut self, value: &Value) -> Result<()> {
+        use rustc_serialize::Encoder;
+        match *value {
+            Value::Bool(v) => self.emit_bool(v),
+            Value::U8(v) => self.write_unsigned(v as u64),
+            Value::U16(v) => self.write_unsigned(v as u64),
+            Value::U32(v) => self.write_unsigned(v as u64),
+            Value::U64(v) => self.write_unsigned(v),
+            Value::Usize(v) => self.write_unsigned(v as u64),
+            Value::I8(v) => self.write_signed(v as i64),
+            Value::I16(v) => self.write_signed(v as i64),
+            Value::I32(v) => self.write_signed(v as i64),
+            Value::I64(v) => self.write_signed(v),
+            Value::Isize(v) => self.write_signed(v as i64),
+            Value::F32(v) => self.emit_f64(v as f64),
+            Value::F64(v) => self.emit_f64(v),
+            Value::Char(v) => self.emit_char(v),
+            Value::Str(ref v) => self.emit_str(v),
+            Value::Bytes(ref v) => self.untagged(BYTES, |encoder| encoder.emit_escaped(v)),
+            Value::Option(None) => self.emit_option_none(),
+            Value::Option(Some(ref v)) => self.write_some(|encoder| encoder.write_value(v)),
+            Value::Tuple(ref values) | Value::Seq(ref values) => self.write_tuple(|encoder| {
+                for value in values.iter() {
+                    try!(encoder.write_value(value));
+                }
+                Ok(())
+            }),
+            Value::Variant(id, ref values) => self.write_tuple(|encoder| {
+                try!(encoder.write_unsigned(id));
+                for value in values.iter() {
+                    try!(encoder.write_value(value));
+                }
+                Ok(())
+            }),
+        }
+    }
+}
+
+impl rustc_serialize::Encoder for Encoder {
+
+    type Error = Error;
+
+    fn emit_nil(&mut self) -> Result<()> {
+        self.buf.push(NIL);
+        Ok(())
+    }
+
+    fn emit_u8(&mut self, v: u8) -> Result<()> { self.write_unsigned(v as u64) }
+    fn emit_u16(&mut self, v: u16) -> Result<()> { self.write_unsigned(v as u64) }
+    fn emit_u32(&mut self, v: u32) -> Result<()> { self.write_unsigned(v as u64) }
+    fn emit_u64(&mut self, v: u64) -> Result<()> { self.write_unsigned(v) }
+    fn emit_usize(&mut self, v: usize) -> Result<()> { self.write_unsigned(v as u64) }
+
+    fn emit_i8(&mut self, v: i8) -> Result<()> { self.write_signed(v as i64) }
+    fn emit_i16(&mut self, v: i16) -> Result<()> { self.write_signed(v as i64) }
+    fn emit_i32(&mut self, v: i32) -> Result<()> { self.write_signed(v as i64) }
+    fn emit_i64(&mut self, v: i64) -> Result<()> { self.write_signed(v) }
+    fn emit_isize(&mut self, v: isize) -> Result<()> { self.write_signed(v as i64) }
+
+    fn emit_bool(&mut self, v: bool) -> Result<()> {
+        self.buf.push(if v { TRUE } else { FALSE });
+        Ok(())
+    }
+
+    fn emit_f32(&mut self, v: f32) -> Result<()> { self.emit_f64(v as f64) }
+    fn emit_f64(&mut self, v: f64) -> Result<()> {
+        self.untagged(FLOAT, |encoder| rustc_serialize::Encoder::emit_f64(encoder, v))
+    }
+
+    fn emit_char(&mut self, v: char) -> Result<()> { self.emit_str(&v.to_string()) }
+    fn emit_str(&mut self, v: &str) -> Result<()> {
+        self.untagged(STRING, |encoder| encoder.emit_escaped(v.as_bytes()))
+    }
+
+    fn emit_enum<F>(&mut self, _name: &str, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+    fn emit_enum_variant<F>(&mut self, _name: &str, id: usize, _len: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        self.write_tuple(|encoder| {
+            try!(encoder.write_unsigned(id as u64));
+            f(encoder)
+        })
+    }
+    fn emit_enum_variant_arg<F>(&mut self, _idx: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+    fn emit_enum_struct_variant<F>(&mut self,
+                                   name: &str,
+                                   id: usize,
+                                   len: usize,
+                                   f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        self.emit_enum_variant(name, id, len, f)
+    }
+    fn emit_enum_struct_variant_field<F>(&mut self, _name: &str, _idx: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+
+    fn emit_struct<F>(&mut self, _name: &str, _len: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        self.write_tuple(f)
+    }
+    fn emit_struct_field<F>(&mut self, _name: &str, _idx: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+
+    fn emit_tuple<F>(&mut self, _len: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        self.write_tuple(f)
+    }
+    fn emit_tuple_arg<F>(&mut self, _idx: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+    fn emit_tuple_struct<F>(&mut self, _name: &str, _len: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        self.write_tuple(f)
+    }
+    fn emit_tuple_struct_arg<F>(&mut self, _idx: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+
+    fn emit_option<F>(&mut self, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+    fn emit_option_none(&mut self) -> Result<()> {
+        self.buf.push(NIL);
+        Ok(())
+    }
+    fn emit_option_some<F>(&mut self, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        self.write_some(f)
+    }
+
+    fn emit_seq<F>(&mut self, _len: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        self.write_tuple(f)
+    }
+    fn emit_seq_elt<F>(&mut self, _idx: usize, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        f(self)
+    }
+
+    fn emit_map<F>(&mut self, _len: usize, _f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        Err(error("maps are not supported by the tagged encoding"))
+    }
+    fn emit_map_elt_key<F>(&mut self, _idx: usize, _f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        Err(error("maps are not supported by the tagged encoding"))
+    }
+    fn emit_map_elt_val<F>(&mut self, _idx: usize, _f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        Err(error("maps are not supported by the tagged encoding"))
+    }
+}
+
+/// A decoder for values prefixed by their type code.
+#[derive(Clone, Copy)]
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+
+    fn peek(&self) -> Result<u8> {
+        self.bytes.get(self.pos).cloned().ok_or(Error::UnexpectedEof)
+    }
+
+    fn expect(&mut self, tag: u8, ty: &str) -> Result<()> {
+        if try!(self.peek()) == tag {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(error(&format!("expected {} at byte {}", ty, self.pos)))
+        }
+    }
+
+    fn finish(&self) -> Result<()> {
+        if self.pos == self.bytes.len() {
+            Ok(())
+        } else {
+            Err(error(&format!("trailing bytes after the value at byte {}", self.pos)))
+        }
+    }
+
+    /// Reads a primitive value following its tag with the untagged decoder.
+    fn untagged<T, F>(&mut self, f: F) -> Result<T>
+    where F: FnOnce(&mut ::Decoder<&mut &'a [u8]>) -> Result<T> {
+        let mut rest = &self.bytes[self.pos + 1..];
+        let value = try!(f(&mut ::Decoder::buffered(&mut rest)));
+        self.pos = self.bytes.len() - rest.len();
+        Ok(value)
+    }
+
+    /// Reads an integer as its sign and magnitude.
+    fn read_int(&mut self) -> Result<Int> {
+        let pos = self.pos;
+        match try!(self.peek()) {
+            NEGATIVE => match try!(self.untagged(|decoder| decoder.read_var_i64())) {
+                v if v < 0 => Ok(Int::Negative(!(v as u64) + 1)),
+                _ => Err(error(&format!("expected a negative integer at byte {}", pos))),
+            },
+            POSITIVE => self.untagged(|decoder| decoder.read_var_u64()).map(Int::Positive),
+            _ => Err(error(&format!("expected an integer at byte {}", pos))),
+        }
+    }
+
+    fn read_unsigned(&mut self, max: u64) -> Result<u64> {
+        self.read_int().and_then(|int| int.unsigned(max))
+    }
+
+    fn read_signed(&mut self, min: i64, max: i64) -> Result<i64> {
+        self.read_int().and_then(|int| int.signed(min, max))
+    }
+
+    fn read_escaped(&mut self, tag: u8, ty: &str) -> Result<Vec<u8>> {
+        if try!(self.peek()) != tag {
+            return Err(error(&format!("expected {} at byte {}", ty, self.pos)));
+        }
+        self.untagged(|decoder| decoder.read_escaped())
+    }
+
+    fn read_elements<T, F>(&mut self, f: F) -> Result<T> where F: FnOnce(&mut Decoder<'a>) -> Result<T> {
+        try!(self.expect(TUPLE, "a tuple"));
+        let value = try!(f(self));
+        try!(self.expect(END, "the end of a tuple"));
+        Ok(value)
+    }
+
+    /// Skips over a value without decoding it.
+    fn skip_value(&mut self) -> Result<()> {
+        match try!(self.peek()) {
+            NIL | FALSE | TRUE => self.pos += 1,
+            BYTES | STRING => {
+                self.pos += 1;
+                loop {
+                    let nul = match self.bytes[self.pos..].iter().position(|&b| b == 0) {
+                        Some(nul) => nul,
+                        None => return Err(Error::UnexpectedEof),
+                    };
+                    self.pos += nul + 1;
+                    match try!(self.peek()) {
+                        0xFF => self.pos += 1,
+                        0x01 => break,
+                        _ => return Err(error(&format!("invalid escape sequence at byte {}",
+                                                       self.pos))),
+                    }
+                }
+                self.pos += 1;
+            },
+            NEGATIVE | POSITIVE => { try!(self.read_int()); },
+            FLOAT if self.pos + 9 > self.bytes.len() => return Err(Error::UnexpectedEof),
+            FLOAT => self.pos += 9,
+            TUPLE => {
+                self.pos += 1;
+                while try!(self.peek()) != END {
+                    try!(self.skip_value());
+                }
+                self.pos += 1;
+            },
+            tag => {
+                return Err(error(&format!("unknown type code 0x{:02x} at byte {}", tag, self.pos)))
+            },
+        }
+        Ok(())
+    }
+
+    fn read_value(&mut self) -> Result<Value> {
+        use rustc_serialize::Decoder;
+        match try!(self.peek()) {
+            NIL => {
+                self.pos += 1;
+                Ok(Value::Option(None))
+            },
+            BYTES => self.read_escaped(BYTES, "a byte string").map(Value::Bytes),
+            STRING => self.read_str().map(Value::Str),
+            NEGATIVE => self.read_i64().map(Value::I64),
+            POSITIVE => self.read_u64().map(Value::U64),
+            FLOAT => self.read_f64().map(Value::F64),
+            FALSE | TRUE => self.read_bool().map(Value::Bool),
+            TUPLE => self.read_elements(|decoder| {
+                let mut values = Vec::new();
+                while try!(decoder.peek()) != END {
+                    values.push(try!(decoder.read_value()));
+                }
+                Ok(values)
+            }).map(Value::Tuple),
+            tag => Err(error(&format!("unknown type code 0x{:02x} at byte {}", tag, self.pos))),
+        }
+    }
+}
+
+impl<'a> rustc_serialize::Decoder for Decoder<'a> {
+
+    type Error = Error;
+
+    fn read_nil(&mut self) -> Result<()> { self.expect(NIL, "nil") }
+
+    fn read_u8(&mut self) -> Result<u8> { self.read_unsigned(u8::MAX as u64).map(|v| v as u8) }
+    fn read_u16(&mut self) -> Result<u16> { self.read_unsigned(u16::MAX as u64).map(|v| v as u16) }
+    fn read_u32(&mut self) -> Result<u32> { self.read_unsigned(u32::MAX as u64).map(|v| v as u32) }
+    fn read_u64(&mut self) -> Result<u64> { self.read_unsigned(!0) }
+    fn read_usize(&mut self) -> Result<usize> {
+        self.read_unsigned(!0usize as u64).map(|v| v as usize)
+    }
+
+    fn read_i8(&mut self) -> Result<i8> {
+        self.read_signed(i8::MIN as i64, i8::MAX as i64).map(|v| v as i8)
+    }
+    fn read_i16(&mut self) -> Result<i16> {
+        self.read_signed(i16::MIN as i64, i16::MAX as i64).map(|v| v as i16)
+    }
+    fn read_i32(&mut self) -> Result<i32> {
+        self.read_signed(i32::MIN as i64, i32::MAX as i64).map(|v| v as i32)
+    }
+    fn read_i64(&mut self) -> Result<i64> { self.read_signed(i64::MIN, i64::MAX) }
+    fn read_isize(&mut self) -> Result<isize> {
+        self.read_signed(::std::isize::MIN as i64, ::std::isize::MAX as i64).map(|v| v as isize)
+    }
+
+    fn read_bool(&mut self) -> Result<bool> {
+        match try!(self.peek()) {
+            FALSE => { self.pos += 1; Ok(false) },
+            TRUE => { self.pos += 1; Ok(true) },
+            _ => Err(error(&format!("expected a boolean at byte {}", self.pos))),
+        }
+    }
+
+    fn read_f32(&mut self) -> Result<f32> {
+        let v = try!(self.read_f64());
+        if v.is_finite() && v as f32 as f64 != v {
+            return Err(error("float does not fit in 32 bits"));
+        }
+        Ok(v as f32)
+    }
+    fn read_f64(&mut self) -> Result<f64> {
+        if try!(self.peek()) != FLOAT {
+            return Err(error(&format!("expected a float at byte {}", self.pos)));
+        }
+        self.untagged(|decoder| rustc_serialize::Decoder::read_f64(decoder))
+    }
+
+    fn read_char(&mut self) -> Result<char> {
+        single_char(&try!(self.read_str()))
+    }
+    fn read_str(&mut self) -> Result<String> {
+        let bytes = try!(self.read_escaped(STRING, "a string"));
+        String::from_utf8(bytes).map_err(|_| Error::NotUtf8)
+    }
+
+    fn read_enum<T, F>(&mut self, _name: &str, f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        f(self)
+    }
+    fn read_enum_variant<T, F>(&mut self, _names: &[&str], mut f: F) -> Result<T>
+            where F: FnMut(&mut Self, usize) -> Result<T> {
+        self.read_elements(|decoder| {
+            let id = try!(decoder.read_usize());
+            f(decoder, id)
+        })
+    }
+    fn read_enum_variant_arg<T, F>(&mut self, _idx: usize, f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        f(self)
+    }
+    fn read_enum_struct_variant<T, F>(&mut self, names: &[&str], f: F) -> Result<T>
+            where F: FnMut(&mut Self, usize) -> Result<T> {
+        self.read_enum_variant(names, f)
+    }
+    fn read_enum_struct_variant_field<T, F>(&mut self, _name: &str, _idx: usize, f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        f(self)
+    }
+
+    fn read_struct<T, F>(&mut self, _name: &str, _len: usize, f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        self.read_elements(f)
+    }
+    fn read_struct_field<T, F>(&mut self, _name: &str, _idx: usize, f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        f(self)
+    }
+
+    fn read_tuple<T, F>(&mut self, _len: usize, f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        self.read_elements(f)
+    }
+    fn read_tuple_arg<T, F>(&mut self, _idx: usize, f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        f(self)
+    }
+    fn read_tuple_struct<T, F>(&mut self, _name: &str, _len: usize, f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        self.read_elements(f)
+    }
+    fn read_tuple_struct_arg<T, F>(&mut self, _idx: usize, f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        f(self)
+    }
+
+    fn read_option<T, F>(&mut self, mut f: F) -> Result<T>
+            where F: FnMut(&mut Self, bool) -> Result<T> {
+        if try!(self.peek()) == NIL {
+            self.pos += 1;
+            f(self, false)
+        } else {
+            f(self, true)
+        }
+    }
+
+    fn read_seq<T, F>(&mut self, f: F) -> Result<T>
+            where F: FnOnce(&mut Self, usize) -> Result<T> {
+        // Count the elements by skipping over them with a copy of the decoder.
+        let mut len = 0;
+        let mut scan = *self;
+        try!(scan.expect(TUPLE, "a tuple"));
+        while try!(scan.peek()) != END {
+            try!(scan.skip_value());
+            len += 1;
+        }
+        self.read_elements(|decoder| f(decoder, len))
+    }
+    fn read_seq_elt<T, F>(&mut self, _idx: usize, f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        f(self)
+    }
+
+    fn read_map<T, F>(&mut self, _f: F) -> Result<T>
+            where F: FnOnce(&mut Self, usize) -> Result<T> {
+        Err(error("maps are not supported by the tagged encoding"))
+    }
+    fn read_map_elt_key<T, F>(&mut self, _idx: usize, _f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        Err(error("maps are not supported by the tagged encoding"))
+    }
+    fn read_map_elt_val<T, F>(&mut self, _idx: usize, _f: F) -> Result<T>
+            where F: FnOnce(&mut Self) -> Result<T> {
+        Err(error("maps are not supported by the tagged encoding"))
+    }
+
+    fn error(&mut self, err: &str) -> Error {
+        error(err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::{f64, i64, u64};
+
+    use rustc_serialize::{self, Decodable, Encodable};
+
+    use Value;
+    use super::{decode, decode_value, encode, encode_value};
+
+    #[derive(Debug, PartialEq)]
+    struct Key {
+        id: u32,
+        name: String,
+        parent: Option<i16>,
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Created(Key),
+        Deleted { at: i64 },
+    }
+
+    impl Encodable for Key {
+        fn encode<S>(&self, s: &mut S) -> Result<(), S::Error> where S: rustc_serialize::Encoder {
+            s.emit_struct("Key", 3, |s| {
+                try!(s.emit_struct_field("id", 0, |s| self.id.encode(s)));
+                try!(s.emit_struct_field("name", 1, |s| self.name.encode(s)));
+                s.emit_struct_field("parent", 2, |s| self.parent.encode(s))
+            })
+        }
+    }
+
+    impl Decodable for Key {
+        fn decode<D>(d: &mut D) -> Result<Key, D::Error> where D: rustc_serialize::Decoder {
+            d.read_struct("Key", 3, |d| {
+                Ok(Key {
+                    id: try!(d.read_struct_field("id", 0, Decodable::decode)),
+                    name: try!(d.read_struct_field("name", 1, Decodable::decode)),
+                    parent: try!(d.read_struct_field("parent", 2, Decodable::decode)),
+                })
+            })
+        }
+    }
+
+    impl Encodable for Event {
+        fn encode<S>(&self, s: &mut S) -> Result<(), S::Error> where S: rustc_serialize::Encoder {
+            s.emit_enum("Event", |s| match *self {
+                Event::Created(ref key) => s.emit_enum_variant("Created", 0, 1, |s| {
+                    s.emit_enum_variant_arg(0, |s| key.encode(s))
+                }),
+                Event::Deleted { at } => s.emit_enum_struct_variant("Deleted", 1, 1, |s| {
+                    s.emit_enum_struct_variant_field("at", 0, |s| at.encode(s))
+                }),
+            })
+        }
+    }
+
+    impl Decodable for Event {
+        fn decode<D>(d: &mut D) -> Result<Event, D::Error> where D: rustc_serialize::Decoder {
+            d.read_enum("Event", |d| d.read_enum_struct_variant(&["Created", "Deleted"], |d, idx| match idx {
+                0 => Ok(Event::Created(try!(d.read_enum_variant_arg(0, Decodable::decode)))),
+                1 => Ok(Event::Deleted { at: try!(d.read_enum_struct_variant_field("at", 0, Decodable::decode)) }),
+                _ => Err(d.error("unknown Event variant")),
+            }))
+        }
+    }
+
+    #[test]
+    fn test_vectors() {
+        assert_eq!(vec!(0x01), encode(&None::<u8>).unwrap());
+        assert_eq!(vec!(0x01), encode(&()).unwrap());
+        assert_eq!(vec!(0x03, 0x61, 0x00, 0xFF, 0x62, 0x00, 0x01), encode(&"a\0b").unwrap());
+        assert_eq!(vec!(0x05, 0x00), encode(&0u8).unwrap());
+        assert_eq!(vec!(0x05, 0x00), encode(&0i64).unwrap());
+        assert_eq!(vec!(0x05, 0x10, 0x10), encode(&16u16).unwrap());
+        assert_eq!(vec!(0x04, 0x7F), encode(&-1i8).unwrap());
+        assert_eq!(vec!(0x06, 0xBF, 0xF0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00), encode(&1.0f32).unwrap());
+        assert_eq!(vec!(0x07), encode(&false).unwrap());
+        assert_eq!(vec!(0x08), encode(&true).unwrap());
+        assert_eq!(vec!(0x09, 0x05, 0x01, 0x03, 0x61, 0x00, 0x01, 0x01, 0x00),
+                   encode(&Key { id: 1, name: "a".to_string(), parent: None }).unwrap());
+        assert_eq!(vec!(0x09, 0x05, 0x01, 0x05, 0x00, 0x00), encode(&Event::Deleted { at: 0 }).unwrap());
+        assert_eq!(vec!(0x09, 0x00), encode(&Vec::<u8>::new()).unwrap());
+        assert_eq!(vec!(0x02, 0x00, 0xFF, 0x00, 0x01), encode_value(&Value::Bytes(vec!(0))).unwrap());
+    }
+
+    #[test]
+    fn test_type_order() {
+        let values = vec!(Value::Option(None),
+                          Value::Bytes(vec!(0xFF)),
+                          Value::Str("".to_string()),
+                          Value::I64(i64::MIN),
+                          Value::I8(-1),
+                          Value::U8(0),
+                          Value::U64(u64::MAX),
+                          Value::F64(f64::NEG_INFINITY),
+                          Value::F32(1.5),
+                          Value::Bool(false),
+                          Value::Bool(true),
+                          Value::Tuple(vec!()),
+                          Value::Tuple(vec!(Value::Option(None))),
+                          Value::Tuple(vec!(Value::U8(1), Value::U8(2))),
+                          Value::Tuple(vec!(Value::U8(2))));
+        let keys: Vec<_> = values.iter().map(|value| encode_value(value).unwrap()).collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+    }
+
+    #[quickcheck]
+    fn check_int_order(a: i64, b: u32) -> bool {
+        a.cmp(&(b as i64)) == encode(&a).unwrap().cmp(&encode(&b).unwrap())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let key = Key { id: 7, name: "foo\0bar".to_string(), parent: Some(-3) };
+        assert_eq!(key, decode(&encode(&key).unwrap()).unwrap());
+        let event = Event::Created(key);
+        assert_eq!(event, decode(&encode(&event).unwrap()).unwrap());
+        let event = Event::Deleted { at: i64::MIN };
+        assert_eq!(event, decode(&encode(&event).unwrap()).unwrap());
+        let values = vec!(vec!(1.5f64, -0.0), vec!(), vec!(f64::INFINITY));
+        assert_eq!(values, decode::<Vec<Vec<f64>>>(&encode(&values).unwrap()).unwrap());
+        assert_eq!(('x', u64::MAX), decode(&encode(&('x', u64::MAX)).unwrap()).unwrap());
+
+        // Integers and floats decode as any type which holds the value.
+        assert_eq!(300i16, decode(&encode(&300u64).unwrap()).unwrap());
+        assert_eq!(0.5f32, decode(&encode(&0.5f64).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_decode_value() {
+        let key = Key { id: 7, name: "foo".to_string(), parent: Some(-3) };
+        assert_eq!(Value::Tuple(vec!(Value::U64(7), Value::Str("foo".to_string()), Value::I64(-3))),
+                   decode_value(&encode(&key).unwrap()).unwrap());
+        assert_eq!(Value::Tuple(vec!(Value::U64(1), Value::I64(-5))),
+                   decode_value(&encode(&Event::Deleted { at: -5 }).unwrap()).unwrap());
+        assert_eq!(Value::Option(None), decode_value(&encode(&None::<String>).unwrap()).unwrap());
+
+        let value = Value::Tuple(vec!(Value::Bytes(vec!(0, 1)), Value::F64(2.5), Value::Bool(true)));
+        assert_eq!(value, decode_value(&encode_value(&value).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(decode::<u8>(&encode(&256u16).unwrap()).is_err());
+        assert!(decode::<u32>(&encode(&-1i32).unwrap()).is_err());
+        assert!(decode::<i64>(&encode(&u64::MAX).unwrap()).is_err());
+        assert!(decode::<f32>(&encode(&0.1f64).unwrap()).is_err());
+        assert!(decode::<String>(&encode(&1u8).unwrap()).is_err());
+        assert!(decode::<(u8, u8)>(&encode(&(1u8,)).unwrap()).is_err());
+        assert!(decode::<(u8,)>(&encode(&(1u8, 2u8)).unwrap()).is_err());
+        assert!(decode::<u8>(&[0x05, 0x01, 0x05]).is_err());
+        assert!(decode_value(&[0x0A]).is_err());
+        assert!(decode_value(&[0x09, 0x05, 0x01]).is_err());
+        assert!(decode_value(&[0x04, 0x81]).is_err());
+        assert!(decode::<i64>(&[0x04, 0x81]).is_err());
+        assert!(decode::<Vec<String>>(&[0x09, 0x03, 0x61, 0x00, 0x02, 0x00]).is_err());
+    }
+
+    #[test]
+    fn test_some_nil() {
+        assert!(encode(&Some(None::<u8>)).is_err());
+        assert!(encode(&(1u8, Some(()))).is_err());
+        assert!(encode_value(&Value::Option(Some(Box::new(Value::Option(None))))).is_err());
+        assert_eq!(Some(Some(1u8)), decode(&encode(&Some(Some(1u8))).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_seq() {
+        let values = vec!((Some("a\0b".to_string()), vec!(-1i64, 1 << 40), true, 0.5f64),
+                          (None, vec!(), false, -0.5));
+        assert_eq!(values, decode::<Vec<(Option<String>, Vec<i64>, bool, f64)>>(
+                               &encode(&values).unwrap()).unwrap());
+    }
+}
@@ -0,0 +1,318 @@
+//! Tracing of the byte spans of encoded and decoded values.
+
+use std::{cmp, fmt, mem};
+use std::io::{self, BufRead, Read, Write};
+
+/// A span of the bytes of an encoded value.
+///
+/// Spans form a tree. Struct fields, enum variants, and tuple elements are labelled with their
+/// name or index, and contain the spans of their constituent values. Primitive values, and option
+/// markers, are leaves with an empty label and the `Debug` representation of the value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub label: String,
+    pub start: usize,
+    pub end: usize,
+    pub value: Option<String>,
+    pub children: Vec<Span>,
+}
+
+/// The spans recorded by a tracing `Encoder` or `Decoder`.
+///
+/// See `Encoder::set_tracing` and `Decoder::set_tracing`.
+///
+/// #### Usage
+///
+/// ```
+/// # extern crate rustc_serialize;
+/// # extern crate bytekey;
+/// # use bytekey::Encoder;
+/// # use rustc_serialize::Encodable;
+/// # fn main() {
+/// let mut encoder = Encoder::new(Vec::new());
+/// encoder.set_tracing(true);
+/// (42u8, "foo").encode(&mut encoder).unwrap();
+/// let trace = encoder.take_trace();
+/// print!("{}", trace.hexdump(&encoder.finish().unwrap()));
+/// // 0000  2a                        0 = 42
+/// // 0001  66 6f 6f 00               1 = "foo"
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Trace {
+    pub spans: Vec<Span>,
+}
+
+impl Trace {
+
+    /// Renders an annotated hexdump of the encoded bytes, with one line per primitive value.
+    ///
+    /// Each line gives the offset and bytes of the value, the path of labels leading to it, and
+    /// its value. Values within descending spans are shown as they were before inversion.
+    pub fn hexdump(&self, bytes: &[u8]) -> String {
+        let mut out = String::new();
+        for (path, span) in self.leaves() {
+            let end = cmp::min(span.end, bytes.len());
+            let start = cmp::min(span.start, end);
+            let hex = bytes[start..end].iter()
+                                       .map(|b| format!("{:02x}", b))
+                                       .collect::<Vec<_>>()
+                                       .join(" ");
+            let path = if path.is_empty() { "value".to_string() } else { path };
+            let value = span.value.as_ref().unwrap();
+            out.push_str(&format!("{:04x}  {:<24}  {} = {}\n", span.start, hex, path, value));
+        }
+        out
+    }
+
+    /// Returns the spans of the primitive values in order, with the dotted path of labels leading
+    /// to each.
+    pub fn leaves(&self) -> Vec<(String, &Span)> {
+        let mut leaves = Vec::new();
+        for span in self.spans.iter() {
+            push_leaves(span, "", &mut leaves);
+        }
+        leaves
+    }
+}
+
+fn push_leaves<'a>(span: &'a Span, path: &str, leaves: &mut Vec<(String, &'a Span)>) {
+    let path = match (path.is_empty(), span.label.is_empty()) {
+        (_, true) => path.to_string(),
+        (true, false) => span.label.clone(),
+        (false, false) => format!("{}.{}", path, span.label),
+    };
+    for child in span.children.iter() {
+        push_leaves(child, &path, leaves);
+    }
+    if span.value.is_some() {
+        leaves.push((path, span));
+    }
+}
+
+/// Records the span tree of an encoder or decoder.
+///
+/// Positions passed to the tracer are relative to the start of the encoder's output or the
+/// decoder's input, and `offset` converts them to absolute positions in the key.
+#[derive(Debug, Default)]
+pub struct Tracer {
+    pub offset: usize,
+    stack: Vec<Span>,
+    spans: Vec<Span>,
+}
+
+impl Tracer {
+
+    pub fn new() -> Tracer {
+        Tracer::default()
+    }
+
+    /// Opens a span starting at the position.
+    pub fn open(&mut self, label: String, pos: usize) {
+        let start = self.offset + pos;
+        self.stack.push(Span { label: label, start: start, end: start, value: None, children: Vec::new() });
+    }
+
+    /// Closes the innermost open span at the position.
+    pub fn close(&mut self, pos: usize) {
+        if let Some(mut span) = self.stack.pop() {
+            span.end = self.offset + pos;
+            self.push(span);
+        }
+    }
+
+    /// Records a primitive value between the positions.
+    pub fn leaf(&mut self, start: usize, end: usize, value: String) {
+        let span = Span {
+            label: String::new(),
+            start: self.offset + start,
+            end: self.offset + end,
+            value: Some(value),
+            children: Vec::new(),
+        };
+        self.push(span);
+    }
+
+    fn push(&mut self, span: Span) {
+        match self.stack.last_mut() {
+            Some(parent) => parent.children.push(span),
+            None => self.spans.push(span),
+        }
+    }
+
+    /// Takes the recorded spans, closing any spans left open by an error.
+    pub fn take(&mut self) -> Trace {
+        while let Some(mut span) = self.stack.pop() {
+            span.end = span.children.last().map_or(span.start, |child| child.end);
+            self.push(span);
+        }
+        Trace { spans: mem::replace(&mut self.spans, Vec::new()) }
+    }
+}
+
+/// A label which is formatted as a trace value without quotes.
+pub struct Label(pub &'static str);
+
+impl fmt::Debug for Label {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+/// A reader or writer which counts the bytes read or written.
+pub struct Counter<T> {
+    pub inner: T,
+    pub pos: usize,
+}
+
+impl<T> Counter<T> {
+    pub fn new(inner: T) -> Counter<T> {
+        Counter { inner: inner, pos: 0 }
+    }
+}
+
+impl<W> Write for Counter<W> where W: Write {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = try!(self.inner.write(buf));
+        self.pos += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R> Read for Counter<R> where R: Read {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.inner.read(buf));
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl<R> BufRead for Counter<R> where R: BufRead {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.pos += amt;
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use rustc_serialize::{self, Decodable, Encodable};
+
+    use {Decoder, Encoder};
+
+    #[derive(Debug, PartialEq)]
+    struct Key {
+        id: u32,
+        name: String,
+        kind: Option<(u8, i8)>,
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Created(Key),
+        Deleted { at: i64 },
+    }
+
+    impl Encodable for Key {
+        fn encode<S>(&self, s: &mut S) -> Result<(), S::Error> where S: rustc_serialize::Encoder {
+            s.emit_struct("Key", 3, |s| {
+                try!(s.emit_struct_field("id", 0, |s| self.id.encode(s)));
+                try!(s.emit_struct_field("name", 1, |s| self.name.encode(s)));
+                s.emit_struct_field("kind", 2, |s| self.kind.encode(s))
+            })
+        }
+    }
+
+    impl Decodable for Key {
+        fn decode<D>(d: &mut D) -> Result<Key, D::Error> where D: rustc_serialize::Decoder {
+            d.read_struct("Key", 3, |d| {
+                Ok(Key {
+                    id: try!(d.read_struct_field("id", 0, Decodable::decode)),
+                    name: try!(d.read_struct_field("name", 1, Decodable::decode)),
+                    kind: try!(d.read_struct_field("kind", 2, Decodable::decode)),
+                })
+            })
+        }
+    }
+
+    impl Encodable for Event {
+        fn encode<S>(&self, s: &mut S) -> Result<(), S::Error> where S: rustc_serialize::Encoder {
+            s.emit_enum("Event", |s| match *self {
+                Event::Created(ref key) => s.emit_enum_variant("Created", 0, 1, |s| {
+                    s.emit_enum_variant_arg(0, |s| key.encode(s))
+                }),
+                Event::Deleted { at } => s.emit_enum_struct_variant("Deleted", 1, 1, |s| {
+                    s.emit_enum_struct_variant_field("at", 0, |s| at.encode(s))
+                }),
+            })
+        }
+    }
+
+    impl Decodable for Event {
+        fn decode<D>(d: &mut D) -> Result<Event, D::Error> where D: rustc_serialize::Decoder {
+            d.read_enum("Event", |d| d.read_enum_struct_variant(&["Created", "Deleted"], |d, idx| match idx {
+                0 => Ok(Event::Created(try!(d.read_enum_variant_arg(0, Decodable::decode)))),
+                1 => Ok(Event::Deleted { at: try!(d.read_enum_struct_variant_field("at", 0, Decodable::decode)) }),
+                _ => Err(d.error("unknown Event variant")),
+            }))
+        }
+    }
+
+    fn line(start: usize, hex: &str, path: &str, value: &str) -> String {
+        format!("{:04x}  {:<24}  {} = {}\n", start, hex, path, value)
+    }
+
+    fn check_trace<T>(value: &T, expected: &str) where T: Encodable + Decodable {
+        let mut encoder = Encoder::new(Vec::new());
+        encoder.set_tracing(true);
+        value.encode(&mut encoder).unwrap();
+        let encoded = encoder.take_trace();
+        let bytes = encoder.finish().unwrap();
+        assert_eq!(expected, encoded.hexdump(&bytes));
+
+        let mut decoder = Decoder::buffered(&bytes[..]);
+        decoder.set_tracing(true);
+        let _: T = Decodable::decode(&mut decoder).unwrap();
+        assert_eq!(encoded, decoder.take_trace());
+    }
+
+    #[test]
+    fn test_struct() {
+        let key = Key { id: 1, name: "ab".to_string(), kind: Some((2, -1)) };
+        let expected = [line(0, "00 00 00 01", "id", "1"),
+                        line(4, "61 62 00", "name", "\"ab\""),
+                        line(7, "01", "kind", "Some"),
+                        line(8, "02", "kind.0", "2"),
+                        line(9, "7f", "kind.1", "-1")].concat();
+        check_trace(&key, &expected);
+    }
+
+    #[test]
+    fn test_enum() {
+        let key = Key { id: 1, name: "".to_string(), kind: None };
+        let expected = [line(0, "00", "Created", "0"),
+                        line(1, "00 00 00 01", "Created.0.id", "1"),
+                        line(5, "00", "Created.0.name", "\"\""),
+                        line(6, "00", "Created.0.kind", "None")].concat();
+        check_trace(&Event::Created(key), &expected);
+        check_trace(&Event::Deleted { at: 0 },
+                    &[line(0, "01", "Deleted", "1"),
+                      line(1, "80 00 00 00 00 00 00 00", "Deleted.at", "0")].concat());
+    }
+
+    #[test]
+    fn test_not_tracing() {
+        let mut encoder = Encoder::new(Vec::new());
+        42u8.encode(&mut encoder).unwrap();
+        assert!(encoder.take_trace().spans.is_empty());
+    }
+}
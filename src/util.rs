@@ -0,0 +1,47 @@
+//! Helpers shared by the `fdb`, `tagged`, and `memcmp` encodings.
+
+use std::{i64, io};
+
+use {Error, Result};
+
+/// Returns a generic error with the message.
+pub fn error(msg: &str) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::Other, msg))
+}
+
+/// A decoded integer, as its sign and magnitude.
+pub enum Int {
+    Positive(u64),
+    Negative(u64),
+}
+
+impl Int {
+
+    /// Returns the integer if it is between zero and `max`.
+    pub fn unsigned(self, max: u64) -> Result<u64> {
+        match self {
+            Int::Positive(v) if v <= max => Ok(v),
+            _ => Err(error("integer out of range")),
+        }
+    }
+
+    /// Returns the integer if it is between `min` and `max`.
+    pub fn signed(self, min: i64, max: i64) -> Result<i64> {
+        let v = match self {
+            Int::Positive(v) if v <= i64::MAX as u64 => v as i64,
+            Int::Negative(v) if v == 1 << 63 => i64::MIN,
+            Int::Negative(v) if v < 1 << 63 => -(v as i64),
+            _ => return Err(error("integer out of range")),
+        };
+        if v < min || v > max { Err(error("integer out of range")) } else { Ok(v) }
+    }
+}
+
+/// Returns the only character of a string.
+pub fn single_char(s: &str) -> Result<char> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(error("expected a single character string")),
+    }
+}
@@ -0,0 +1,34 @@
+//! Dynamically typed values.
+
+/// A dynamically typed value.
+///
+/// `Value` holds a value whose type is only known at runtime, for instance a column of a row
+/// encoded with a `RowEncoder`, or a key decoded with `decode_with_schema`. Each variant is encoded
+/// as described by its `Schema`, and scalar variants are encoded exactly as the corresponding Rust
+/// type would be encoded by `Encoder`.
+///
+/// `Bytes` is encoded as an `Escaped` byte string, and `Variant` holds the tag and fields of an
+/// enum variant.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    Usize(usize),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    Isize(isize),
+    F32(f32),
+    F64(f64),
+    Char(char),
+    Str(String),
+    Bytes(Vec<u8>),
+    Option(Option<Box<Value>>),
+    Tuple(Vec<Value>),
+    Variant(u64, Vec<Value>),
+    Seq(Vec<Value>),
+}
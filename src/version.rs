@@ -0,0 +1,270 @@
+//! Semantic version keys.
+
+use std::fmt;
+use std::io::{Read, Write};
+
+use rustc_serialize::Decoder as RustcDecoder;
+use rustc_serialize::Encoder as RustcEncoder;
+
+use {Decoder, Encoder, KeyDecode, KeyEncode, Result};
+#[cfg(feature = "semver")] use semver;
+
+/// A semantic version which is encoded in [SemVer](http://semver.org/) precedence order.
+///
+/// Versions sort by major, minor, and patch number, then by pre-release identifiers. A version
+/// with pre-release identifiers sorts before the same version without them, so `1.0.0-alpha`
+/// sorts before `1.0.0`. Build metadata does not take part in precedence, so it is not encoded:
+/// versions which differ only in build metadata have the same key, and decoded versions have no
+/// build metadata.
+///
+/// With the `semver` feature enabled, `SemVer` converts to and from `semver::Version`. Converting
+/// to `semver::Version` panics if an identifier is not a valid SemVer identifier.
+///
+/// ##### Encoding
+///
+/// The major, minor, and patch numbers are variable-length encoded (see
+/// `Encoder::emit_var_u64`). They are followed by a release marker byte: `0x01` for a release, or
+/// `0x00` followed by the pre-release identifiers for a pre-release. Each identifier is encoded as a
+/// tag byte (`0x01` for numeric and `0x02` for alphanumeric identifiers) followed by the
+/// variable-length encoded number or the null-terminated string, and the list of identifiers is
+/// terminated by a null byte.
+///
+/// Numeric components are encoded in full on every platform, including 32-bit platforms.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SemVer {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub pre: Vec<Identifier>,
+    pub build: Vec<Identifier>,
+}
+
+/// A pre-release or build metadata identifier.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Identifier {
+    Numeric(u64),
+    AlphaNumeric(String),
+}
+
+impl SemVer {
+
+    /// Creates a new release version with no pre-release identifiers or build metadata.
+    pub fn new(major: u64, minor: u64, patch: u64) -> SemVer {
+        SemVer { major: major, minor: minor, patch: patch, pre: Vec::new(), build: Vec::new() }
+    }
+}
+
+impl KeyEncode for SemVer {
+    fn encode_key<W>(&self, encoder: &mut Encoder<W>) -> Result<()> where W: Write {
+        try!(encoder.emit_var_u64(self.major));
+        try!(encoder.emit_var_u64(self.minor));
+        try!(encoder.emit_var_u64(self.patch));
+        if self.pre.is_empty() {
+            encoder.emit_u8(1)
+        } else {
+            try!(encoder.emit_u8(0));
+            encode_identifiers(&self.pre, encoder)
+        }
+    }
+}
+
+impl KeyDecode for SemVer {
+    fn decode_key<R>(decoder: &mut Decoder<R>) -> Result<SemVer> where R: Read {
+        let major = try!(decoder.read_var_u64());
+        let minor = try!(decoder.read_var_u64());
+        let patch = try!(decoder.read_var_u64());
+        let pre = match try!(decoder.read_u8()) {
+            0 => try!(decode_identifiers(decoder)),
+            1 => Vec::new(),
+            _ => return Err(decoder.error("invalid semantic version release marker")),
+        };
+        Ok(SemVer { major: major, minor: minor, patch: patch, pre: pre, build: Vec::new() })
+    }
+}
+
+fn encode_identifiers<W>(identifiers: &[Identifier], encoder: &mut Encoder<W>) -> Result<()>
+where W: Write {
+    for identifier in identifiers.iter() {
+        match *identifier {
+            Identifier::Numeric(n) => {
+                try!(encoder.emit_u8(1));
+                try!(encoder.emit_var_u64(n));
+            },
+            Identifier::AlphaNumeric(ref string) => {
+                try!(encoder.emit_u8(2));
+                try!(encoder.emit_str(string));
+            },
+        }
+    }
+    encoder.emit_u8(0)
+}
+
+fn decode_identifiers<R>(decoder: &mut Decoder<R>) -> Result<Vec<Identifier>> where R: Read {
+    let mut identifiers = Vec::new();
+    loop {
+        match try!(decoder.read_u8()) {
+            0 => return Ok(identifiers),
+            1 => identifiers.push(Identifier::Numeric(try!(decoder.read_var_u64()))),
+            2 => identifiers.push(Identifier::AlphaNumeric(try!(decoder.read_str()))),
+            _ => return Err(decoder.error("invalid semantic version identifier tag")),
+        }
+    }
+}
+
+impl fmt::Display for SemVer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}.{}.{}", self.major, self.minor, self.patch));
+        for (i, identifier) in self.pre.iter().enumerate() {
+            try!(write!(f, "{}{}", if i == 0 { "-" } else { "." }, identifier));
+        }
+        for (i, identifier) in self.build.iter().enumerate() {
+            try!(write!(f, "{}{}", if i == 0 { "+" } else { "." }, identifier));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Identifier::Numeric(n) => write!(f, "{}", n),
+            Identifier::AlphaNumeric(ref string) => write!(f, "{}", string),
+        }
+    }
+}
+
+#[cfg(feature = "semver")]
+impl From<semver::Version> for SemVer {
+    fn from(version: semver::Version) -> SemVer {
+        SemVer {
+            major: version.major,
+            minor: version.minor,
+            patch: version.patch,
+            pre: parse_identifiers(version.pre.as_str()),
+            build: parse_identifiers(version.build.as_str()),
+        }
+    }
+}
+
+#[cfg(feature = "semver")]
+impl From<SemVer> for semver::Version {
+    fn from(version: SemVer) -> semver::Version {
+        semver::Version {
+            major: version.major,
+            minor: version.minor,
+            patch: version.patch,
+            pre: semver::Prerelease::new(&join_identifiers(&version.pre))
+                .expect("invalid pre-release identifier"),
+            build: semver::BuildMetadata::new(&join_identifiers(&version.build))
+                .expect("invalid build metadata identifier"),
+        }
+    }
+}
+
+/// Splits dot-separated identifiers. Identifiers are numeric if they are digits without leading
+/// zeros which fit in a `u64`.
+#[cfg(feature = "semver")]
+fn parse_identifiers(s: &str) -> Vec<Identifier> {
+    if s.is_empty() { return Vec::new(); }
+    s.split('.').map(|identifier| match identifier.parse::<u64>() {
+        Ok(n) if n.to_string() == identifier => Identifier::Numeric(n),
+        _ => Identifier::AlphaNumeric(identifier.to_string()),
+    }).collect()
+}
+
+#[cfg(feature = "semver")]
+fn join_identifiers(identifiers: &[Identifier]) -> String {
+    identifiers.iter().map(ToString::to_string).collect::<Vec<_>>().join(".")
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::u64;
+
+    use {decode_key, encode_key, VarInt};
+    use super::{Identifier, SemVer};
+
+    /// Parses a well-formed version string.
+    fn version(s: &str) -> SemVer {
+        fn identifiers(s: &str) -> Vec<Identifier> {
+            s.split('.').map(|identifier| match identifier.parse() {
+                Ok(n) => Identifier::Numeric(n),
+                Err(_) => Identifier::AlphaNumeric(identifier.to_string()),
+            }).collect()
+        }
+
+        let (s, build) = match s.find('+') {
+            Some(idx) => (&s[..idx], identifiers(&s[idx + 1..])),
+            None => (s, Vec::new()),
+        };
+        let (s, pre) = match s.find('-') {
+            Some(idx) => (&s[..idx], identifiers(&s[idx + 1..])),
+            None => (s, Vec::new()),
+        };
+        let numbers: Vec<u64> = s.split('.').map(|n| n.parse().unwrap()).collect();
+        SemVer { major: numbers[0], minor: numbers[1], patch: numbers[2], pre: pre, build: build }
+    }
+
+    #[test]
+    fn test_order() {
+        let versions = vec![
+            "0.0.0", "0.0.1", "0.1.0", "0.9.0", "0.10.0", "1.0.0-0", "1.0.0-2", "1.0.0-10",
+            "1.0.0-alpha", "1.0.0-alpha.1", "1.0.0-alpha.beta", "1.0.0-beta", "1.0.0-beta.2",
+            "1.0.0-beta.11", "1.0.0-rc.1", "1.0.0", "1.2.9", "1.2.10", "1.10.0", "2.0.0",
+            "100000.0.0",
+        ];
+        for pair in versions.windows(2) {
+            let (a, b) = (version(pair[0]), version(pair[1]));
+            assert!(encode_key(&a).unwrap() < encode_key(&b).unwrap(), "{} should sort before {}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_build_metadata() {
+        let key = encode_key(&version("1.0.0-rc.1")).unwrap();
+        assert_eq!(key, encode_key(&version("1.0.0-rc.1+build.1")).unwrap());
+        assert_eq!(key, encode_key(&version("1.0.0-rc.1+build.2")).unwrap());
+        assert!(encode_key(&version("1.0.0+build.1")).unwrap() > key);
+    }
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!(vec!(0x01, 0x02, 0x03, 0x01), encode_key(&SemVer::new(1, 2, 3)).unwrap());
+        assert_eq!(vec!(0x01, 0x00, 0x00, 0x00, 0x02, 0x72, 0x63, 0x00, 0x01, 0x01, 0x00),
+                   encode_key(&version("1.0.0-rc.1")).unwrap());
+    }
+
+    #[test]
+    fn test_large_numbers() {
+        for &n in [15, 16, 1 << 32, (1 << 60) - 1, 1 << 60, u64::MAX].iter() {
+            let v = SemVer { pre: vec!(Identifier::Numeric(n)), ..SemVer::new(n, n, n) };
+            let expected = (VarInt(n), VarInt(n), VarInt(n), 0u8, 1u8, VarInt(n), 0u8);
+            assert_eq!(encode_key(&expected).unwrap(), encode_key(&v).unwrap());
+            assert_eq!(v, decode_key(&encode_key(&v).unwrap()).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for s in vec!["0.0.0", "1.2.3", "1.0.0-alpha.1", "1.0.0-x.7.z.92+exp.sha.5114f85"] {
+            let mut v = version(s);
+            assert_eq!(s, v.to_string());
+            v.build.clear();
+            assert_eq!(v, decode_key(&encode_key(&v).unwrap()).unwrap());
+        }
+    }
+    #[cfg(feature = "semver")]
+    #[test]
+    fn test_semver() {
+        use semver::Version;
+
+        for s in vec!["1.2.3", "1.0.0-alpha.1", "1.0.0-x.7.z.92+exp.sha.5114f85", "1.0.0+build.007"] {
+            let v = version(s);
+            let converted = SemVer::from(Version::parse(s).unwrap());
+            assert_eq!(v.pre, converted.pre);
+            assert_eq!(s, converted.to_string());
+            assert_eq!(Version::parse(s).unwrap(), Version::from(converted));
+        }
+    }
+}